@@ -0,0 +1,92 @@
+//! Compares acknowledging a lot of confirmed messages with a confirmation for every message, in order and out of order, and with a single cumulative confirmation.
+//!
+//! Run with `cargo bench --bench acknowledge`.
+
+use serde_derive::Serialize;
+use std::net::SocketAddr;
+use std::num::NonZeroU64;
+use std::time::{Duration, Instant};
+use udp_connector::{Connector, ConnectorParam, Socket};
+
+const MESSAGES: u64 = 50_000;
+const RUNS: u32 = 10;
+
+struct Param;
+impl ConnectorParam for Param {
+    type TSend = u32;
+    type TReceive = u32;
+}
+
+/// The packets a sender receives, with the same layout as the packets of the connector
+#[derive(Serialize)]
+#[allow(dead_code, clippy::enum_variant_names)]
+enum Packet {
+    Ping,
+    Pong,
+    PacketNotFound,
+    RequestPacket,
+    ConfirmPacket { id: NonZeroU64 },
+    Data,
+    Disconnect,
+    Batch,
+    ConfirmUpTo { id: NonZeroU64 },
+}
+
+/// A socket that discards everything that is send
+struct NullSocket;
+impl Socket for NullSocket {
+    fn recv_from(&mut self, _: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        Err(std::io::ErrorKind::WouldBlock.into())
+    }
+    fn local_addr(&self) -> SocketAddr {
+        "127.0.0.1:1".parse().unwrap()
+    }
+    fn send_to(&mut self, _: &[u8], _: SocketAddr) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Send `MESSAGES` confirmed messages, and time how long it takes to handle the given confirmations
+fn run(confirmations: &[Vec<u8>]) -> Duration {
+    let mut total = Duration::default();
+    for _ in 0..RUNS {
+        let mut connector = Connector::<Param>::bound_to("127.0.0.1:2".parse().unwrap());
+        for i in 0..MESSAGES as u32 {
+            connector.send_confirmed(&mut NullSocket, i).unwrap();
+        }
+        let start = Instant::now();
+        for confirmation in confirmations {
+            connector
+                .handle_incoming_data(&mut NullSocket, confirmation)
+                .unwrap();
+        }
+        total += start.elapsed();
+        assert_eq!(0, connector.unconfirmed().count());
+    }
+    total / RUNS
+}
+
+fn main() {
+    let individual = (1..=MESSAGES)
+        .map(|id| {
+            let id = NonZeroU64::new(id).unwrap();
+            bincode::serialize(&Packet::ConfirmPacket { id }).unwrap()
+        })
+        .collect::<Vec<_>>();
+    // Every other message first, so every confirmation removes a message from the middle of the unconfirmed messages
+    let out_of_order = individual
+        .iter()
+        .step_by(2)
+        .chain(individual.iter().skip(1).step_by(2))
+        .cloned()
+        .collect::<Vec<_>>();
+    let cumulative = vec![bincode::serialize(&Packet::ConfirmUpTo {
+        id: NonZeroU64::new(MESSAGES).unwrap(),
+    })
+    .unwrap()];
+
+    println!("{} confirmed messages, average of {} runs", MESSAGES, RUNS);
+    println!("individual: {:?}", run(&individual));
+    println!("individual, out of order: {:?}", run(&out_of_order));
+    println!("cumulative: {:?}", run(&cumulative));
+}
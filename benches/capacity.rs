@@ -0,0 +1,52 @@
+//! Compares sending a lot of confirmed messages with and without pre-sizing the unconfirmed message cache.
+//!
+//! Run with `cargo bench --bench capacity`.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use udp_connector::{Connector, ConnectorParam, Socket};
+
+const MESSAGES: usize = 100_000;
+const RUNS: u32 = 10;
+
+struct Param;
+impl ConnectorParam for Param {
+    type TSend = u32;
+    type TReceive = u32;
+}
+
+/// A socket that discards everything that is send
+struct NullSocket;
+impl Socket for NullSocket {
+    fn recv_from(&mut self, _: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        Err(std::io::ErrorKind::WouldBlock.into())
+    }
+    fn local_addr(&self) -> SocketAddr {
+        "127.0.0.1:1".parse().unwrap()
+    }
+    fn send_to(&mut self, _: &[u8], _: SocketAddr) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn run(create: impl Fn() -> Connector<Param>) -> Duration {
+    let mut total = Duration::default();
+    for _ in 0..RUNS {
+        let mut connector = create();
+        let start = Instant::now();
+        for i in 0..MESSAGES as u32 {
+            connector.send_confirmed(&mut NullSocket, i).unwrap();
+        }
+        total += start.elapsed();
+    }
+    total / RUNS
+}
+
+fn main() {
+    let peer_addr = "127.0.0.1:2".parse().unwrap();
+    let default = run(|| Connector::bound_to(peer_addr));
+    let presized = run(|| Connector::with_capacity(peer_addr, MESSAGES));
+    println!("{} confirmed messages, average of {} runs", MESSAGES, RUNS);
+    println!("bound_to:      {:?}", default);
+    println!("with_capacity: {:?}", presized);
+}
@@ -0,0 +1,35 @@
+//! Feeds arbitrary datagrams into `Connector::handle_incoming_data`, followed by an update. Neither may panic, no matter what the peer sends.
+//!
+//! Run with `cargo fuzz run handle_incoming_data` from the root of the repository.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::net::SocketAddr;
+use udp_connector::{Connector, ConnectorParam, Socket};
+
+struct Param;
+impl ConnectorParam for Param {
+    type TSend = String;
+    type TReceive = String;
+}
+
+/// A socket that discards everything that is send
+struct NullSocket;
+impl Socket for NullSocket {
+    fn recv_from(&mut self, _: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        Err(std::io::ErrorKind::WouldBlock.into())
+    }
+    fn local_addr(&self) -> SocketAddr {
+        "127.0.0.1:1".parse().unwrap()
+    }
+    fn send_to(&mut self, _: &[u8], _: SocketAddr) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut connector = Connector::<Param>::bound_to("127.0.0.1:2".parse().unwrap());
+    // Errors are fine, the datagram is invalid most of the time
+    let _ = connector.handle_incoming_data(&mut NullSocket, data);
+    let _ = connector.update(&mut NullSocket);
+});
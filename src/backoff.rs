@@ -0,0 +1,76 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// How long `Connector::reconnect_loop` waits for the peer to answer, and when it gives up.
+///
+/// Every attempt waits longer than the one before, starting at `initial_delay` and multiplying by `multiplier` up to `max_delay`. The loop gives up after `max_attempts` attempts or after `max_total` time, whichever comes first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackoffPolicy {
+    /// How long the first attempt waits for the peer to answer
+    pub initial_delay: Duration,
+
+    /// The longest an attempt waits, the delay does not grow any further
+    pub max_delay: Duration,
+
+    /// The delay of every attempt is the delay of the previous attempt times this. Values below 1 are treated as 1.
+    pub multiplier: f64,
+
+    /// The fraction of the delay that is random, between 0 and 1. With 0.2, a delay of 1 second becomes a random delay between 0.8 and 1.2 seconds. This prevents many clients that lost their connection at the same moment from reconnecting in lockstep.
+    pub jitter: f64,
+
+    /// The amount of attempts after which the loop gives up, or `None` to keep trying
+    pub max_attempts: Option<u32>,
+
+    /// The total time after which the loop gives up, or `None` to keep trying
+    pub max_total: Option<Duration>,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> BackoffPolicy {
+        BackoffPolicy {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.,
+            jitter: 0.2,
+            max_attempts: Some(10),
+            max_total: None,
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// The delay of the given attempt, counting from 0, without jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.)
+            .powi(attempt.min(i32::MAX as u32) as i32);
+        // `f64::min` ignores the NaN of 0 times an infinite factor
+        let delay = (self.initial_delay.as_secs_f64() * factor).min(self.max_delay.as_secs_f64());
+        // A delay close to `Duration::MAX` may round up past it as a float
+        Duration::try_from_secs_f64(delay).unwrap_or(self.max_delay)
+    }
+
+    /// The delay of the given attempt, with `jitter` applied
+    pub(crate) fn jittered_delay(&self, attempt: u32) -> Duration {
+        // NaN stays NaN when clamped
+        let jitter = match self.jitter {
+            jitter if jitter.is_finite() => jitter.clamp(0., 1.),
+            _ => 0.,
+        };
+        let delay = self.delay(attempt);
+        if jitter == 0. {
+            return delay;
+        }
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        let jittered = delay.as_secs_f64() * (1. + jitter * (2. * random - 1.));
+        Duration::try_from_secs_f64(jittered).unwrap_or(delay)
+    }
+
+    /// Returns `true` if no attempt should follow after the given amount of attempts, that took the given time
+    pub(crate) fn gives_up(&self, attempts: u32, elapsed: Duration) -> bool {
+        self.max_attempts.is_some_and(|max| attempts >= max)
+            || self.max_total.is_some_and(|max| elapsed >= max)
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+/// The source of time of a connector
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Clock {
+    /// Use the time of the system
+    System,
+
+    /// Time only moves when `Clock::advance` is called. This makes tests that depend on timing exact, and avoids having to sleep in them. This is also used to replay a `Transcript`.
+    Manual(Instant),
+}
+
+impl Clock {
+    /// Create a manual clock, starting at the current time
+    #[cfg(test)]
+    pub fn manual() -> Clock {
+        Clock::Manual(Instant::now())
+    }
+
+    /// Get the current time
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+            Clock::Manual(now) => *now,
+        }
+    }
+
+    /// Wait for the given duration. A manual clock is moved forward instead, so waiting takes no time.
+    pub fn sleep(&mut self, duration: std::time::Duration) {
+        match self {
+            Clock::System => std::thread::sleep(duration),
+            Clock::Manual(now) => *now += duration,
+        }
+    }
+
+    /// Move a manual clock forward. This does nothing for the system clock.
+    #[cfg(test)]
+    pub fn advance(&mut self, duration: std::time::Duration) {
+        if let Clock::Manual(now) = self {
+            *now += duration;
+        }
+    }
+}
+
+/// The time between `since` and `now` in seconds, or 0 if `since` is after `now`
+pub(crate) fn seconds_between(since: Instant, now: Instant) -> f64 {
+    now.saturating_duration_since(since).as_secs_f64()
+}
+
+/// A setting in seconds as a `Duration`. Settings are not checked, so like in the comparisons with `seconds_between` a negative value becomes 0, and NaN or a value that is too large, like infinity, becomes `Duration::MAX`.
+pub(crate) fn duration_from_secs(seconds: f64) -> Duration {
+    if seconds <= 0. {
+        return Duration::ZERO;
+    }
+    Duration::try_from_secs_f64(seconds).unwrap_or(Duration::MAX)
+}
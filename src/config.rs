@@ -0,0 +1,138 @@
+use crate::{
+    AddressFilter, ConnectorParam, DisconnectReason, MissingRequestOrder, Padding,
+    SendBeforeConnected, Timing,
+};
+
+/// The settings a connector runs with, as configured in its `ConnectorParam`. This is returned by `Connector::config_report`.
+///
+/// This can be serialized, e.g. to log it or to attach it to a support ticket. Every field has the name of the matching constant in `ConnectorParam`, see there for more info.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[allow(missing_docs)]
+pub struct ConfigReport {
+    /// The name of `ConnectorParam::TSend`
+    pub send_type: String,
+    /// The name of `ConnectorParam::TReceive`
+    pub receive_type: String,
+    pub ping_interval_s: f64,
+    pub request_missing_packet_interval_s: f64,
+    pub enable_missing_requests: bool,
+    pub ack_delay_s: f64,
+    pub compact_message_ids: bool,
+    pub cumulative_acks: bool,
+    pub negotiate_cumulative_acks: bool,
+    pub acks_in_pings: bool,
+    pub clock_sync: bool,
+    pub sequence_acks: bool,
+    pub max_sequence_acks: usize,
+    pub max_streams: usize,
+    pub wrap_message_ids: bool,
+    pub ignore_unknown_packets: bool,
+    pub strict: bool,
+    pub receives_messages: bool,
+    pub any_packet_is_ping: bool,
+    pub resend_latest_unconfirmed_on_reconnect: bool,
+    pub idle_after_s: Option<f64>,
+    pub idle_ping_interval_s: f64,
+    pub max_requests_per_update: usize,
+    pub max_amplification: Option<u64>,
+    pub max_id_gap: Option<u64>,
+    pub max_unconfirmed_bytes: Option<usize>,
+    pub missing_request_order: MissingRequestOrder,
+    pub emit_unconfirmed_packet_interval_s: f64,
+    pub connect_timeout_s: Option<f64>,
+    pub max_session_duration_s: Option<f64>,
+    pub session_expired_reason: Option<DisconnectReason>,
+    pub receive_ping_timeout_s: f64,
+    pub send_ping_timeout_s: f64,
+    pub channel_weights: Vec<u32>,
+    pub retransmit_budget_bytes: usize,
+    pub congestion_backoff_s: f64,
+    pub loss_window_s: f64,
+    /// The kind of `ConnectorParam::ADDRESS_FILTER`, a custom filter can not be serialized
+    pub address_filter: String,
+    pub max_deserialize_bytes: u64,
+    pub max_send_datagram_size: usize,
+    pub max_receive_datagram_size: usize,
+    pub buffer_sends: bool,
+    pub send_before_connected: SendBeforeConnected,
+    pub checksummed_batches: bool,
+    pub padding: Padding,
+    pub max_reorder_buffer: usize,
+    pub event_queue_capacity: usize,
+    pub queue_messages: bool,
+}
+
+impl ConfigReport {
+    /// Collect the settings of the given `ConnectorParam`
+    pub(crate) fn of<TParam: ConnectorParam>() -> ConfigReport {
+        ConfigReport {
+            send_type: std::any::type_name::<TParam::TSend>().to_owned(),
+            receive_type: std::any::type_name::<TParam::TReceive>().to_owned(),
+            ping_interval_s: TParam::PING_INTERVAL_S,
+            request_missing_packet_interval_s: TParam::REQUEST_MISSING_PACKET_INTERVAL_S,
+            enable_missing_requests: TParam::ENABLE_MISSING_REQUESTS,
+            ack_delay_s: TParam::ACK_DELAY_S,
+            compact_message_ids: TParam::COMPACT_MESSAGE_IDS,
+            cumulative_acks: TParam::CUMULATIVE_ACKS,
+            negotiate_cumulative_acks: TParam::NEGOTIATE_CUMULATIVE_ACKS,
+            acks_in_pings: TParam::ACKS_IN_PINGS,
+            clock_sync: TParam::CLOCK_SYNC,
+            sequence_acks: TParam::SEQUENCE_ACKS,
+            max_sequence_acks: TParam::MAX_SEQUENCE_ACKS,
+            max_streams: TParam::MAX_STREAMS,
+            wrap_message_ids: TParam::WRAP_MESSAGE_IDS,
+            ignore_unknown_packets: TParam::IGNORE_UNKNOWN_PACKETS,
+            strict: TParam::STRICT,
+            receives_messages: TParam::RECEIVES_MESSAGES,
+            any_packet_is_ping: TParam::ANY_PACKET_IS_PING,
+            resend_latest_unconfirmed_on_reconnect: TParam::RESEND_LATEST_UNCONFIRMED_ON_RECONNECT,
+            idle_after_s: TParam::IDLE_AFTER_S,
+            idle_ping_interval_s: TParam::IDLE_PING_INTERVAL_S,
+            max_requests_per_update: TParam::MAX_REQUESTS_PER_UPDATE,
+            max_amplification: TParam::MAX_AMPLIFICATION,
+            max_id_gap: TParam::MAX_ID_GAP,
+            max_unconfirmed_bytes: TParam::MAX_UNCONFIRMED_BYTES,
+            missing_request_order: TParam::MISSING_REQUEST_ORDER,
+            emit_unconfirmed_packet_interval_s: TParam::EMIT_UNCONFIRMED_PACKET_INTERVAL_S,
+            connect_timeout_s: TParam::CONNECT_TIMEOUT_S,
+            max_session_duration_s: TParam::MAX_SESSION_DURATION_S,
+            session_expired_reason: TParam::SESSION_EXPIRED_REASON,
+            receive_ping_timeout_s: TParam::RECEIVE_PING_TIMEOUT_S,
+            send_ping_timeout_s: TParam::SEND_PING_TIMEOUT_S,
+            channel_weights: TParam::CHANNEL_WEIGHTS.to_vec(),
+            retransmit_budget_bytes: TParam::RETRANSMIT_BUDGET_BYTES,
+            congestion_backoff_s: TParam::CONGESTION_BACKOFF_S,
+            loss_window_s: TParam::LOSS_WINDOW_S,
+            address_filter: match TParam::ADDRESS_FILTER {
+                AddressFilter::Exact => "Exact",
+                AddressFilter::IpOnly => "IpOnly",
+                AddressFilter::Custom(_) => "Custom",
+            }
+            .to_owned(),
+            max_deserialize_bytes: TParam::MAX_DESERIALIZE_BYTES,
+            max_send_datagram_size: TParam::MAX_SEND_DATAGRAM_SIZE,
+            max_receive_datagram_size: TParam::MAX_RECEIVE_DATAGRAM_SIZE,
+            buffer_sends: TParam::BUFFER_SENDS,
+            send_before_connected: TParam::SEND_BEFORE_CONNECTED,
+            checksummed_batches: TParam::CHECKSUMMED_BATCHES,
+            padding: TParam::PADDING,
+            max_reorder_buffer: TParam::MAX_REORDER_BUFFER,
+            event_queue_capacity: TParam::EVENT_QUEUE_CAPACITY,
+            queue_messages: TParam::QUEUE_MESSAGES,
+        }
+    }
+
+    /// Replace the intervals and timeouts with the ones a connector runs with, see `Connector::set_timing`
+    pub(crate) fn with_timing(self, timing: &Timing) -> ConfigReport {
+        ConfigReport {
+            ping_interval_s: timing.ping_interval_s,
+            request_missing_packet_interval_s: timing.request_missing_packet_interval_s,
+            emit_unconfirmed_packet_interval_s: timing.emit_unconfirmed_packet_interval_s,
+            receive_ping_timeout_s: timing.receive_ping_timeout_s,
+            send_ping_timeout_s: timing.send_ping_timeout_s,
+            connect_timeout_s: timing.connect_timeout_s,
+            ack_delay_s: timing.ack_delay_s,
+            ..self
+        }
+    }
+}
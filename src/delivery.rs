@@ -0,0 +1,84 @@
+use crate::{Error, Result};
+use std::future::Future;
+use std::num::NonZeroU64;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// A future that resolves once the peer confirmed a message, see `Connector::send_confirmed_await`.
+///
+/// The future does not borrow the connector. The connector still has to be updated and receive data as usual, e.g. in another task, for the confirmation to arrive.
+#[derive(Debug)]
+pub struct Delivery {
+    id: NonZeroU64,
+    state: Arc<Mutex<DeliveryState>>,
+}
+
+/// The part of a `Delivery` that is kept by the connector, to resolve the future
+#[derive(Debug)]
+pub(crate) struct DeliveryWaiter {
+    state: Arc<Mutex<DeliveryState>>,
+}
+
+#[derive(Debug, Default)]
+struct DeliveryState {
+    /// `Some(true)` if the message was confirmed, `Some(false)` if it will never be confirmed
+    delivered: Option<bool>,
+    waker: Option<Waker>,
+}
+
+/// Create a future for the message with the given id, and the waiter that resolves it
+pub(crate) fn delivery(id: NonZeroU64) -> (Delivery, DeliveryWaiter) {
+    let state = Arc::new(Mutex::new(DeliveryState::default()));
+    let waiter = DeliveryWaiter {
+        state: state.clone(),
+    };
+    (Delivery { id, state }, waiter)
+}
+
+impl Delivery {
+    /// The id of the message, as returned by `Connector::send_confirmed`
+    pub fn id(&self) -> NonZeroU64 {
+        self.id
+    }
+}
+
+impl Future for Delivery {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.delivered {
+            Some(true) => Poll::Ready(Ok(())),
+            Some(false) => Poll::Ready(Err(Error::NotDelivered { id: self.id }.into())),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl DeliveryWaiter {
+    /// Resolve the future with the given outcome, and wake the task that is waiting for it
+    pub(crate) fn resolve(self, delivered: bool) {
+        self.resolve_inner(delivered);
+    }
+
+    fn resolve_inner(&self, delivered: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.delivered.is_none() {
+            state.delivered = Some(delivered);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Drop for DeliveryWaiter {
+    /// A waiter that is dropped without resolving belongs to a message that the connector forgot, e.g. because it reconnected or was dropped itself
+    fn drop(&mut self) {
+        self.resolve_inner(false);
+    }
+}
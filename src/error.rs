@@ -0,0 +1,213 @@
+use crate::NetworkState;
+use failure::Fail;
+use std::fmt;
+use std::net::SocketAddr;
+use std::num::NonZeroU64;
+use std::time::Duration;
+
+/// Errors that are specific to this crate. These are returned wrapped in a `failure::Error`, and can be inspected with `failure::Error::downcast_ref::<Error>()`.
+#[derive(Debug)]
+pub enum Error {
+    /// The address family of the peer does not match the address family of the local socket, e.g. an IPv6 peer on an IPv4 socket.
+    AddressFamilyMismatch {
+        /// The local address of the socket
+        local: SocketAddr,
+        /// The address of the peer
+        peer: SocketAddr,
+    },
+
+    /// The address of the peer can not be send to, because it is the unspecified address like `0.0.0.0`, or because its port is 0.
+    InvalidPeerAddress {
+        /// The address of the peer
+        peer: SocketAddr,
+    },
+
+    /// A message was send on a channel that does not exist. See `ConnectorParam::CHANNEL_WEIGHTS` for more info.
+    UnknownChannel {
+        /// The channel the message was send on
+        channel: usize,
+    },
+
+    /// A message was received, but `ConnectorParam::RECEIVES_MESSAGES` is disabled, so this connector can not receive messages. This means the peer is sending messages to a connector that is meant to only send.
+    ReceiveNotSupported,
+
+    /// A message was send while the connector is disconnected, so it would never arrive. Call `Connector::connect` to connect again first.
+    NotConnected {
+        /// The state of the connector when the message was send
+        state: NetworkState,
+    },
+
+    /// An interval in `ConnectorParam` is shorter than the measured round trip time, so packets are resend before the answer of the peer could have arrived. See `Connector::validate` for more info.
+    IntervalShorterThanRtt {
+        /// The name of the setting in `ConnectorParam`
+        setting: &'static str,
+        /// The value of the setting
+        interval: Duration,
+        /// The round trip time that it was compared to
+        rtt: Duration,
+    },
+
+    /// More messages arrived out of order than `ConnectorParam::MAX_REORDER_BUFFER` allows, while waiting for an earlier message that did not arrive. The connection is closed, as the messages can not be delivered in order.
+    ReorderBufferFull {
+        /// The amount of messages that were waiting
+        len: usize,
+    },
+
+    /// A confirmed message was not send, because the confirmed messages that are waiting for a confirmation of the peer already take up `ConnectorParam::MAX_UNCONFIRMED_BYTES`.
+    CacheFull {
+        /// The size in bytes of the messages that are waiting
+        bytes: usize,
+    },
+
+    /// A confirmed message will never be confirmed by the peer, because it expired or the connector forgot it, e.g. by calling `Connector::connect` again. See `Connector::send_confirmed_await` for more info.
+    NotDelivered {
+        /// The id of the message
+        id: NonZeroU64,
+    },
+
+    /// A confirmed message was not send, because every message id was used. With `ConnectorParam::WRAP_MESSAGE_IDS` enabled, the ids start at 1 again once the peer confirmed all messages, so the message can be send later.
+    MessageIdsExhausted,
+
+    /// The peer did not answer any of the connection attempts of `Connector::reconnect_loop`, before its `BackoffPolicy` gave up.
+    ReconnectFailed {
+        /// The amount of connection attempts
+        attempts: u32,
+    },
+
+    /// The peer send a packet that does not make sense. This is only returned with `ConnectorParam::STRICT` enabled, otherwise it is counted in `Stats`.
+    Anomaly {
+        /// What was wrong with the packet
+        anomaly: Anomaly,
+    },
+
+    /// The peer send a compressed message with `Connector::send_confirmed_compressed`, but it was handled with `Connector::handle_incoming_data_borrowed`. The decompressed message only lives while it is handled, so it can not borrow from it. Use `Connector::handle_incoming_data` instead.
+    CompressedMessageBorrowed,
+}
+
+/// A packet of the peer that does not make sense, see `ConnectorParam::STRICT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anomaly {
+    /// The peer confirmed a message that was never send. This is counted in `Stats::invalid_confirmations`.
+    UnknownConfirmation {
+        /// The id of the confirmed message
+        id: NonZeroU64,
+    },
+
+    /// The peer reported that a message is not available, but it was not requested. This is counted in `Stats::unexpected_not_founds`.
+    UnexpectedPacketNotFound {
+        /// The id of the message
+        id: NonZeroU64,
+    },
+
+    /// A pong arrived that does not answer a recent ping. This is counted in `Stats::unmatched_pongs`.
+    UnmatchedPong {
+        /// The nonce of the pong
+        nonce: u32,
+    },
+
+    /// The peer reported a last send message id that is lower than the one it reported before. This is counted in `Stats::id_regressions`.
+    IdRegression {
+        /// The id the peer reported
+        id: NonZeroU64,
+        /// The id the peer reported before
+        previous: NonZeroU64,
+    },
+
+    /// The peer send a message, or reported a last send message id, that is more than `ConnectorParam::MAX_ID_GAP` ahead of the highest message that arrived. A message that would make us track more missing ids than `ConnectorParam::MAX_AMPLIFICATION` allows is reported the same way. This is counted in `Stats::ids_too_far_ahead`.
+    IdGapTooLarge {
+        /// The id of the message, or the id the peer reported
+        id: NonZeroU64,
+        /// The id of the highest message that arrived
+        last: Option<NonZeroU64>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::AddressFamilyMismatch { local, peer } => write!(
+                f,
+                "Can not connect from {} to {}: the address families do not match",
+                local, peer
+            ),
+            Error::InvalidPeerAddress { peer } => {
+                write!(f, "Can not connect to {}: this address can not be send to", peer)
+            }
+            Error::UnknownChannel { channel } => write!(f, "Unknown channel {}", channel),
+            Error::ReceiveNotSupported => write!(
+                f,
+                "Received a message, but this connector can not receive messages"
+            ),
+            Error::NotConnected { state } => {
+                write!(f, "Can not send a message while the state is {:?}", state)
+            }
+            Error::IntervalShorterThanRtt {
+                setting,
+                interval,
+                rtt,
+            } => write!(
+                f,
+                "{} is {:?}, which is shorter than the round trip time of {:?}, so packets are resend needlessly",
+                setting, interval, rtt
+            ),
+            Error::ReorderBufferFull { len } => write!(
+                f,
+                "{} messages arrived out of order while waiting for an earlier message",
+                len
+            ),
+            Error::CacheFull { bytes } => write!(
+                f,
+                "Can not send a confirmed message, {} bytes are already waiting for a confirmation",
+                bytes
+            ),
+            Error::NotDelivered { id } => {
+                write!(f, "Message {} was not confirmed by the peer", id)
+            }
+            Error::MessageIdsExhausted => write!(
+                f,
+                "Can not send a confirmed message, every message id is in use"
+            ),
+            Error::ReconnectFailed { attempts } => {
+                write!(f, "The peer did not answer after {} attempts", attempts)
+            }
+            Error::Anomaly { anomaly } => write!(f, "Protocol anomaly: {}", anomaly),
+            Error::CompressedMessageBorrowed => write!(
+                f,
+                "Received a compressed message, which can not be borrowed from the datagram"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Anomaly::UnknownConfirmation { id } => {
+                write!(f, "message {} was confirmed, but never send", id)
+            }
+            Anomaly::UnexpectedPacketNotFound { id } => {
+                write!(
+                    f,
+                    "message {} was reported as not found, but never requested",
+                    id
+                )
+            }
+            Anomaly::UnmatchedPong { nonce } => {
+                write!(f, "pong {} does not answer a recent ping", nonce)
+            }
+            Anomaly::IdRegression { id, previous } => write!(
+                f,
+                "the peer reported {} as its last send message id, after reporting {}",
+                id, previous
+            ),
+            Anomaly::IdGapTooLarge { id, last } => write!(
+                f,
+                "message id {} is too far ahead of the last received message id {}",
+                id,
+                last.map_or(0, NonZeroU64::get)
+            ),
+        }
+    }
+}
+
+impl Fail for Error {}
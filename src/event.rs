@@ -0,0 +1,79 @@
+use crate::{DisconnectReason, NetworkState};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::num::NonZeroU64;
+
+/// Something that happened on a `Connector`. Events are queued while the connector is handling incoming data and updating, and can be retrieved with `Connector::drain_events`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<TReceive> {
+    /// A message was received from the peer. Messages are only queued when they are received through `Connector::poll`, or when `ConnectorParam::QUEUE_MESSAGES` is enabled.
+    Message(TReceive),
+
+    /// The peer confirmed that it received the confirmed message with the given id. This id is returned from `Connector::send_confirmed`.
+    Acknowledged(NonZeroU64),
+
+    /// The application of the peer handled the confirmed message with the given id, see `Connector::acknowledge_processed`. This is reported after `Event::Acknowledged` for the same id. The peer sends this only once, so it is not reported if that packet is lost.
+    Processed(NonZeroU64),
+
+    /// The peer no longer has the confirmed message with the given id that we were missing, so it will never arrive.
+    MessageLost(NonZeroU64),
+
+    /// The confirmed message with the given id was not confirmed by the peer before its deadline, and will no longer be resend. See `Connector::send_confirmed_with_deadline`.
+    MessageExpired(NonZeroU64),
+
+    /// The peer closed the connection with `Connector::close`. The connector is now disconnected, until `Connector::connect` is called.
+    Disconnected {
+        /// The reason the peer gave for closing the connection
+        reason: Option<DisconnectReason>,
+    },
+
+    /// The state of the connector changed to the given value. See `Connector::state` for more info.
+    StateChanged(NetworkState),
+
+    /// A packet from the peer could not be read, or handling it failed, e.g. because of an anomaly with `ConnectorParam::STRICT`. The packet is dropped, this event contains its bytes so they can be inspected, e.g. with `hex_dump`.
+    ///
+    /// If this was the only or first packet in the datagram, the error is also returned from `Connector::handle_incoming_data`. A packet in a batch that can not be split is reported with the whole datagram.
+    MalformedPacket {
+        /// The address the packet came from. This is the address of the peer, unless `ConnectorParam::ADDRESS_FILTER` accepted a datagram from another address. `Connector::handle_incoming_data` is not told where the datagram came from, so it reports the address of the peer.
+        from: SocketAddr,
+        /// The bytes of the packet
+        data: Vec<u8>,
+        /// A description of what was wrong with the packet
+        error: String,
+    },
+
+    /// Both sides had no data to send for `ConnectorParam::IDLE_AFTER_S`, so pings are now send every `ConnectorParam::IDLE_PING_INTERVAL_S`.
+    IdleStarted,
+
+    /// Data was send or received after an `Event::IdleStarted`, so pings are send every `ConnectorParam::PING_INTERVAL_S` again.
+    IdleEnded,
+
+    /// A `ConnectorPool` created a connector for a new peer, because its handshake arrived, with the address of the peer. This is queued before the other events of that datagram, and its messages are returned by `ConnectorPool::handle_incoming_data` as usual.
+    NewPeer(SocketAddr),
+}
+
+/// Format bytes as a hex dump, with 16 bytes on every line. Every line starts with the offset of its first byte, and ends with the printable ASCII characters in it. This is useful to inspect an `Event::MalformedPacket`.
+///
+/// ```text
+/// 0000  05 00 00 00 01 00 68 69                          |......hi|
+/// ```
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (index, line) in data.chunks(16).enumerate() {
+        let _ = write!(dump, "{:04x} ", index * 16);
+        for byte in line {
+            let _ = write!(dump, " {:02x}", byte);
+        }
+        dump.push_str(&"   ".repeat(16 - line.len()));
+        dump.push_str("  |");
+        dump.extend(line.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        dump.push_str("|\n");
+    }
+    dump
+}
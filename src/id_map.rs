@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+use std::num::NonZeroU64;
+use std::ops::Index;
+
+/// A map from message ids to values, ordered by id like a `BTreeMap`. The entries are stored in a single `VecDeque`, so room for them can be reserved up front, see `Connector::with_capacity`.
+///
+/// Message ids are mostly added in increasing order and confirmed oldest first, so most changes only touch the ends of the deque. An entry that is removed from the middle is left behind as an empty slot, so it does not shift the entries after it. Empty slots are dropped once they reach an end of the deque, or once they make up more than half of it.
+#[derive(Debug)]
+pub(crate) struct IdMap<V> {
+    entries: VecDeque<(NonZeroU64, Option<V>)>,
+
+    /// The amount of slots in `entries` that hold a value
+    len: usize,
+}
+
+impl<V> Default for IdMap<V> {
+    fn default() -> Self {
+        IdMap {
+            entries: VecDeque::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<V> IdMap<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The amount of entries that fit without reallocating
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    /// Reserve room for at least `additional` more entries
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+
+    /// Remove all entries, but keep the allocated capacity
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.len = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The index of the slot with the given id, or where it would be inserted
+    fn position(&self, id: NonZeroU64) -> Result<usize, usize> {
+        self.entries.binary_search_by_key(&id, |(id, _)| *id)
+    }
+
+    /// Drop the empty slots at both ends, and all of them when they take up more than half of the deque
+    fn trim(&mut self) {
+        while matches!(self.entries.front(), Some((_, None))) {
+            self.entries.pop_front();
+        }
+        while matches!(self.entries.back(), Some((_, None))) {
+            self.entries.pop_back();
+        }
+        if self.len * 2 < self.entries.len() {
+            self.entries.retain(|(_, value)| value.is_some());
+        }
+    }
+
+    /// Insert a value, returning the value that had the same id before
+    pub fn insert(&mut self, id: NonZeroU64, value: V) -> Option<V> {
+        match self.position(id) {
+            Ok(index) => {
+                let old = self.entries[index].1.replace(value);
+                if old.is_none() {
+                    self.len += 1;
+                }
+                old
+            }
+            Err(index) => {
+                self.entries.insert(index, (id, Some(value)));
+                self.len += 1;
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: &NonZeroU64) -> Option<V> {
+        let index = self.position(*id).ok()?;
+        let value = self.entries[index].1.take()?;
+        self.len -= 1;
+        self.trim();
+        Some(value)
+    }
+
+    /// Remove the entries with an id up to and including `id`, ordered by id
+    pub fn remove_up_to(&mut self, id: NonZeroU64) -> Vec<(NonZeroU64, V)> {
+        let end = match self.position(id) {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        };
+        let removed = self
+            .entries
+            .drain(..end)
+            .filter_map(|(id, value)| Some((id, value?)))
+            .collect::<Vec<_>>();
+        self.len -= removed.len();
+        self.trim();
+        removed
+    }
+
+    pub fn get(&self, id: &NonZeroU64) -> Option<&V> {
+        let index = self.position(*id).ok()?;
+        self.entries[index].1.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: &NonZeroU64) -> Option<&mut V> {
+        let index = self.position(*id).ok()?;
+        self.entries[index].1.as_mut()
+    }
+
+    /// Iterate over the entries, ordered by id
+    pub fn iter(&self) -> impl Iterator<Item = (&NonZeroU64, &V)> {
+        self.entries
+            .iter()
+            .filter_map(|(id, value)| Some((id, value.as_ref()?)))
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.entries
+            .iter_mut()
+            .filter_map(|(_, value)| value.as_mut())
+    }
+}
+
+impl<V> Index<&NonZeroU64> for IdMap<V> {
+    type Output = V;
+
+    fn index(&self, id: &NonZeroU64) -> &V {
+        self.get(id).expect("No entry with this id")
+    }
+}
@@ -9,6 +9,7 @@
 //! * * Guaranteed to arrive ***at some point***
 //! * * Not guaranteed to arrive in the correct order
 //! * Unconfirmed: This is a message that is not guaranteed to arrive
+//! * Unconfirmed ordered: This is an unconfirmed message that is dropped when a newer message on the same stream arrived before it
 //!
 //! Use cases can be:
 //! * Sending player data does not always have to arrive, because the location is updated 10 times a second (unconfirmed)
@@ -17,44 +18,190 @@
 #[macro_use]
 extern crate serde_derive;
 
+mod backoff;
+mod clock;
+mod config;
+#[cfg(any(test, feature = "async"))]
+mod delivery;
+mod error;
+mod event;
+mod id_map;
 mod packet;
 mod param;
+mod pool;
+mod snapshot;
+mod stats;
+mod stream;
+mod timing;
+mod transcript;
 
 #[cfg(test)]
-pub mod test;
+mod test;
 
 /// The result that is used in this type. It is a simple wrapper around `Result<T, failure::Error>`
 pub type Result<T> = std::result::Result<T, failure::Error>;
 
-use self::packet::Packet;
-pub use self::param::ConnectorParam;
+pub use self::backoff::BackoffPolicy;
+use self::clock::{duration_from_secs, seconds_between, Clock};
+pub use self::config::ConfigReport;
+#[cfg(any(test, feature = "async"))]
+pub use self::delivery::Delivery;
+pub use self::error::{Anomaly, Error};
+pub use self::event::{hex_dump, Event};
+use self::id_map::IdMap;
+pub use self::packet::DisconnectReason;
+use self::packet::{Packet, CAPABILITY_CUMULATIVE_ACKS};
+pub use self::param::{
+    AddressFilter, ConnectorParam, MissingRequestOrder, Padding, SendBeforeConnected,
+};
+pub use self::pool::ConnectorPool;
+pub use self::stats::{PacketTypeCounts, Stats, StreamFeedback};
+pub use self::stream::{ReliableStream, StreamChunk};
+pub use self::timing::{DefaultParam, Timing};
+pub use self::transcript::{Direction, Transcript, TranscriptEntry};
 
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
 use std::io::ErrorKind;
 use std::net::{SocketAddr, UdpSocket};
-use std::{num::NonZeroU64, time::Instant};
+use std::num::NonZeroU64;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The amount of measurements that `Connector::estimated_clock_offset` picks the best one from
+const CLOCK_SAMPLES: usize = 16;
+
+/// The amount of confirmed ids that are remembered to answer requests that crossed the confirmation, see `Packet::AlreadyDelivered`
+const RECENTLY_CONFIRMED_WINDOW: usize = 64;
 
 /// Contains data about the sending half of this connector
 #[derive(Debug)]
-struct ConnectorSend<TParam: ConnectorParam> {
+struct ConnectorSend {
     /// Contains a list of messages that are send but are not confirmed yet.
-    unconfirmed_message_cache: HashMap<NonZeroU64, CachedPacket<TParam::TSend>>,
+    unconfirmed_message_cache: IdMap<CachedPacket>,
+
+    /// The summed size of the packets in `unconfirmed_message_cache`, see `ConnectorParam::MAX_UNCONFIRMED_BYTES`
+    unconfirmed_bytes: usize,
 
     /// Contains the last Id that was send to the peer connector.
     next_message_id: Option<NonZeroU64>,
 
+    /// Set when `u64::MAX` was used as message id, so there is no next id until the ids wrap, see `ConnectorParam::WRAP_MESSAGE_IDS`
+    ids_exhausted: bool,
+
+    /// The epoch of our message ids, which increases every time the ids wrap
+    epoch: u32,
+
+    /// Set while the peer did not acknowledge the current epoch yet
+    wrap_pending: bool,
+
+    /// A random number that identifies this session to the peer, see `Packet::Hello`. A new one is picked on every `connect`.
+    session: u32,
+
     /// Last time a ping was send
     last_ping: Instant,
+
+    /// The nonces of the pings that were send, and when they were send. A pong only counts if it answers one of these pings.
+    outstanding_pings: Vec<(u32, Instant)>,
+
+    /// The round trip time of the last ping that was answered
+    last_rtt: Option<Duration>,
+
+    /// The time of the peer that arrived right before its pong, with the nonce of that pong. See `ConnectorParam::CLOCK_SYNC`.
+    peer_time: Option<(u32, u64)>,
+
+    /// The round trip time and the measured clock offset in seconds of the last `CLOCK_SAMPLES` pongs that came with the time of the peer
+    clock_samples: VecDeque<(Duration, f64)>,
+
+    /// The ids of the last `RECENTLY_CONFIRMED_WINDOW` messages that the peer confirmed. A request for one of these crossed the confirmation, and is answered with `Packet::AlreadyDelivered`.
+    recently_confirmed: VecDeque<NonZeroU64>,
+
+    /// Serialized packets that are waiting for `flush`, see `ConnectorParam::BUFFER_SENDS`. Packets that were send with `Connector::send_unconfirmed_keyed` have their key stored alongside them.
+    outgoing_buffer: Vec<(Option<u32>, Vec<u8>)>,
+
+    /// The futures that are waiting for a confirmation of a message, see `Connector::send_confirmed_await`. Dropping a waiter resolves its future with an error, so resetting this fails the futures of forgotten messages.
+    #[cfg(any(test, feature = "async"))]
+    deliveries: BTreeMap<NonZeroU64, delivery::DeliveryWaiter>,
 }
 
-impl<TParam: ConnectorParam> Default for ConnectorSend<TParam> {
-    fn default() -> Self {
+impl ConnectorSend {
+    fn new(now: Instant) -> Self {
         ConnectorSend {
-            unconfirmed_message_cache: HashMap::new(),
+            unconfirmed_message_cache: IdMap::new(),
+            unconfirmed_bytes: 0,
             next_message_id: None,
-            last_ping: Instant::now(),
+            ids_exhausted: false,
+            epoch: 0,
+            wrap_pending: false,
+            session: RandomState::new().build_hasher().finish() as u32,
+            last_ping: now,
+            outstanding_pings: Vec::new(),
+            last_rtt: None,
+            peer_time: None,
+            clock_samples: VecDeque::new(),
+            recently_confirmed: VecDeque::new(),
+            outgoing_buffer: Vec::new(),
+            #[cfg(any(test, feature = "async"))]
+            deliveries: BTreeMap::new(),
+        }
+    }
+
+    /// Reset to the state of `new`, but keep the allocated capacity of the unconfirmed message cache
+    fn reset(&mut self, now: Instant) {
+        let mut unconfirmed_message_cache = std::mem::take(&mut self.unconfirmed_message_cache);
+        unconfirmed_message_cache.clear();
+        *self = ConnectorSend {
+            unconfirmed_message_cache,
+            ..ConnectorSend::new(now)
+        };
+    }
+
+    /// The id of the last confirmed message that was send in the current epoch
+    fn last_message_id(&self) -> Option<NonZeroU64> {
+        if self.ids_exhausted {
+            NonZeroU64::new(u64::MAX)
+        } else {
+            self.next_message_id
+                .and_then(|id| NonZeroU64::new(id.get() - 1))
+        }
+    }
+
+    /// Add a packet to the unconfirmed cache
+    fn cache(&mut self, id: NonZeroU64, packet: CachedPacket) {
+        self.unconfirmed_bytes += packet.bytes.len();
+        if let Some(old) = self.unconfirmed_message_cache.insert(id, packet) {
+            self.unconfirmed_bytes -= old.bytes.len();
         }
     }
+
+    /// Remove a packet from the unconfirmed cache, returning `true` if it was in the cache
+    fn uncache(&mut self, id: NonZeroU64) -> bool {
+        match self.unconfirmed_message_cache.remove(&id) {
+            Some(packet) => {
+                self.unconfirmed_bytes -= packet.bytes.len();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remember that the peer confirmed the message with the given id, see `recently_confirmed`
+    fn remember_confirmed(&mut self, id: NonZeroU64) {
+        if self.recently_confirmed.len() >= RECENTLY_CONFIRMED_WINDOW {
+            self.recently_confirmed.pop_front();
+        }
+        self.recently_confirmed.push_back(id);
+    }
+
+    /// Remove all packets up to and including `id` from the unconfirmed cache, returning their ids
+    fn uncache_up_to(&mut self, id: NonZeroU64) -> Vec<NonZeroU64> {
+        let removed = self.unconfirmed_message_cache.remove_up_to(id);
+        self.unconfirmed_bytes -= removed
+            .iter()
+            .map(|(_, packet)| packet.bytes.len())
+            .sum::<usize>();
+        removed.into_iter().map(|(id, _)| id).collect()
+    }
 }
 
 /// Contains data about the receiving half of this connector
@@ -63,39 +210,223 @@ struct ConnectorReceive {
     /// Contains the last ID we've received from the peer.
     last_message_id: Option<NonZeroU64>,
 
-    /// Contains the IDs that we are requesting from the peer.
+    /// The highest id of the confirmed messages that actually arrived. Unlike `last_message_id` this is not raised by the pings of the peer, see `ConnectorParam::MAX_ID_GAP` and `Connector::last_received_id`.
+    highest_received_id: Option<NonZeroU64>,
+
+    /// The id of the last confirmed message that the peer send, as told in its pings and pongs
+    peer_last_sent_id: Option<NonZeroU64>,
+
+    /// Contains the IDs that we are requesting from the peer, sorted by id. Ids are only added above `last_message_id`, so appending keeps the order.
     missing_message_id_list: Vec<MissingId>,
 
     /// Last time a ping was received
     last_ping: Instant,
+
+    /// The ids of received confirmed messages that are not confirmed to the peer yet, and when the first of them was received. See `ConnectorParam::ACK_DELAY_S` and `ConnectorParam::ACKS_IN_PINGS`.
+    pending_acks: Vec<NonZeroU64>,
+    pending_acks_since: Option<Instant>,
+
+    /// The sequence number of the newest message that was received on every stream, see `Connector::send_unconfirmed_ordered`
+    latest_sequences: BTreeMap<u32, u64>,
+
+    /// The amount of messages that were received on every stream, see `ConnectorParam::SEQUENCE_ACKS`
+    stream_received: BTreeMap<u32, u64>,
+
+    /// The stream that the next pong reports first, see `ConnectorParam::MAX_SEQUENCE_ACKS`
+    next_sequence_ack_stream: u32,
+
+    /// The epoch of the message ids of the peer, see `ConnectorParam::WRAP_MESSAGE_IDS`
+    epoch: u32,
+
+    /// The session of the peer, as told in its last `Packet::Hello`
+    peer_session: Option<u32>,
+
+    /// The capabilities that the peer announced, and whether the peer told us it received ours. See `ConnectorParam::NEGOTIATE_CUMULATIVE_ACKS`.
+    peer_capabilities: Option<u32>,
+    capabilities_acknowledged: bool,
 }
 
-impl Default for ConnectorReceive {
-    fn default() -> Self {
+impl ConnectorReceive {
+    fn new(now: Instant) -> Self {
         ConnectorReceive {
             last_message_id: None,
+            highest_received_id: None,
+            peer_last_sent_id: None,
             missing_message_id_list: Vec::new(),
-            last_ping: Instant::now(),
+            last_ping: now,
+            pending_acks: Vec::new(),
+            pending_acks_since: None,
+            latest_sequences: BTreeMap::new(),
+            stream_received: BTreeMap::new(),
+            next_sequence_ack_stream: 0,
+            epoch: 0,
+            peer_session: None,
+            peer_capabilities: None,
+            capabilities_acknowledged: false,
+        }
+    }
+
+    /// Reset to the state of `new`, but keep the allocated capacity of the missing id list
+    fn reset(&mut self, now: Instant) {
+        let mut missing_message_id_list = std::mem::take(&mut self.missing_message_id_list);
+        missing_message_id_list.clear();
+        *self = ConnectorReceive {
+            missing_message_id_list,
+            ..ConnectorReceive::new(now)
+        };
+    }
+
+    /// The position of the id in the missing id list, or where it would be inserted
+    fn missing_index(&self, id: NonZeroU64) -> std::result::Result<usize, usize> {
+        self.missing_message_id_list
+            .binary_search_by_key(&id, |missing| missing.id)
+    }
+
+    /// Stop requesting the message with the given id. Returns `false` if the id was not missing.
+    fn remove_missing(&mut self, id: NonZeroU64) -> bool {
+        match self.missing_index(id) {
+            Ok(index) => {
+                self.missing_message_id_list.remove(index);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Contains data about the idle mode of this connector, see `ConnectorParam::IDLE_AFTER_S`
+#[derive(Debug)]
+struct ConnectorIdle {
+    /// The last time a message was send or received
+    last_data: Instant,
+
+    /// Set when the peer told us it has no data to send, until it sends a message. The peer may send pings less often.
+    peer_quiet: bool,
+
+    /// Set when the peer told us it knows we have no data to send, so it expects our pings less often
+    peer_acknowledged: bool,
+
+    /// The `acknowledged` field of the last `Packet::Idle` that was send since we ran out of data
+    last_sent: Option<bool>,
+
+    /// Whether the connector is idle, as last reported through `Event::IdleStarted` and `Event::IdleEnded`
+    active: bool,
+}
+
+impl ConnectorIdle {
+    fn new(now: Instant) -> Self {
+        ConnectorIdle {
+            last_data: now,
+            peer_quiet: false,
+            peer_acknowledged: false,
+            last_sent: None,
+            active: false,
         }
     }
+
+    /// Reset to the state of `new`, but keep the reported state so leaving idle mode is still reported
+    fn reset(&mut self, now: Instant) {
+        *self = ConnectorIdle {
+            active: self.active,
+            ..ConnectorIdle::new(now)
+        };
+    }
 }
 
 /// The connector is used to handle handshakes and timeouts with a different, remote connector
 ///
 /// For client-side applications, we recommend calling `update_and_receive` at a frequent rate
 ///
-/// For server-side applications, we recommend dealing with your own UdpSocket receiving logic, looking up the connector based on a SocketAddr, and then calling `handle_incoming_data`.
+/// For server-side applications, we recommend dealing with your own UdpSocket receiving logic, looking up the connector based on a SocketAddr, and then calling `handle_incoming_data`. `ConnectorPool` does this lookup for you.
 ///
 /// The connector struct has a lot of config settings. All these settings can be found in `ConnectorParam`
+///
+/// A connector is `Send` and `Sync` when `ConnectorParam::TSend` and `ConnectorParam::TReceive` are, so it can be moved between threads or shared in a `Mutex`.
 pub struct Connector<TParam: ConnectorParam> {
     /// Contains data about the sending half of this connector
-    send: ConnectorSend<TParam>,
+    send: ConnectorSend,
 
     /// Contains data about the receiving half of this connector
     receive: ConnectorReceive,
 
+    /// Contains data about the idle mode of this connector
+    idle: ConnectorIdle,
+
     /// The address that this connector is associated with
     peer_addr: SocketAddr,
+
+    /// The local address of the socket, once it is known. Datagrams from this address are our own, and are ignored unless this connector is connected to itself.
+    local_addr: Option<SocketAddr>,
+
+    /// Received messages that were not returned yet, by `update_and_receive_only` because they have a different reliability, or because they were queued by `update_and_queue`
+    inbox: VecDeque<Received<TParam::TReceive>>,
+
+    /// Events that have not been drained yet. See `drain_events` for more info.
+    events: VecDeque<Event<TParam::TReceive>>,
+
+    /// The ids of the messages that were confirmed by the peer, and were not drained yet. See `drain_acknowledgements` for more info.
+    acknowledgements: VecDeque<NonZeroU64>,
+
+    /// The state that was last reported through `Event::StateChanged`
+    last_state: NetworkState,
+
+    /// The moment the current `NetworkState::Connected` session began
+    connected_since: Option<Instant>,
+
+    /// The amount of times `connect` was called
+    connect_count: u32,
+
+    /// The latest unconfirmed message that was send, per key. Only used when `ConnectorParam::RESEND_LATEST_UNCONFIRMED_ON_RECONNECT` is enabled.
+    latest_unconfirmed: BTreeMap<Option<u32>, Vec<u8>>,
+
+    /// The sequence number of the next message on every stream, see `send_unconfirmed_ordered`. This is kept when reconnecting.
+    next_sequences: BTreeMap<u32, u64>,
+
+    /// The moment the current session started, see `ConnectorParam::MAX_SESSION_DURATION_S`
+    session_started: Instant,
+
+    /// The moment `connect` was called, until the peer answers the first ping after that
+    handshake_started: Option<Instant>,
+
+    /// The nonce of the next ping. This is not reset by `connect`, so a pong for a ping from before the reconnect is not mistaken for an answer to a new ping.
+    next_ping_nonce: u32,
+
+    /// The source of time of this connector
+    clock: Clock,
+
+    /// See `stats` for more info
+    stats: Stats,
+
+    /// The last moment congestion was reported, see `report_congestion`
+    congested_at: Option<Instant>,
+
+    /// The moments confirmed messages were send in the last `ConnectorParam::LOSS_WINDOW_S` seconds, and whether it was a retransmission. See `estimated_loss_rate` for more info.
+    recent_sends: VecDeque<(Instant, bool)>,
+
+    /// The moment `pause` was called, if the connector is paused
+    paused_at: Option<Instant>,
+
+    /// Set when the connection was closed by either side. A closed connector is disconnected until it is connected again.
+    closed: bool,
+
+    /// The intervals and timeouts this connector runs with, see `set_timing`
+    timing: Timing,
+
+    /// Decides what happens with outgoing datagrams. See `set_send_interceptor` for more info.
+    #[cfg(any(test, feature = "chaos"))]
+    send_interceptor: Option<SendInterceptor>,
+
+    /// Called with the old and new state on every state change. See `on_state_change` for more info.
+    state_change_handler: Option<StateChangeHandler>,
+
+    /// Decides which missing messages are requested by `update`. See `set_request_selector` for more info.
+    request_selector: Option<RequestSelector>,
+
+    /// The moment the capture started and the datagrams that were captured so far, if a capture is running. See `start_capture` for more info.
+    capture: Option<(Instant, Transcript)>,
+
+    /// The buffer that `receive_from` and `receive_once` read every datagram into. It is allocated on the first receive, so the connectors of a `ConnectorPool` do not need one.
+    receive_buffer: Vec<u8>,
     // /// Additional data stored in this Connector
     // data: TParam::TData,
 }
@@ -107,13 +438,20 @@ struct MissingId {
 }
 
 #[derive(Debug)]
-struct CachedPacket<TSend> {
-    pub packet: Packet<TSend>,
+struct CachedPacket {
+    /// The encoded packet, as it is send to the peer, see `Connector::encode`
+    pub bytes: Vec<u8>,
     pub last_emit: Instant,
+    /// The channel this packet was send on, see `ConnectorParam::CHANNEL_WEIGHTS`
+    pub channel: usize,
+    /// The moment after which the packet is no longer resend, see `Connector::send_confirmed_with_deadline`
+    pub deadline: Option<Instant>,
+    /// Set while the packet is kept until the connection is established, see `ConnectorParam::SEND_BEFORE_CONNECTED`
+    pub held: bool,
 }
 
 /// The state of the connector. This is based on when the last ping was send or received. Changing your ConnectorParam will greatly affect the results of `Connector.state()`, returning this value.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
 pub enum NetworkState {
     /// We received a ping a reasonable amount of time ago, so we're connected. See `ConnectorParam::PING_INTERVAL_S` for more info.
     Connected,
@@ -123,17 +461,133 @@ pub enum NetworkState {
 
     /// We have not received a ping for a while but we did try to connect. See `ConnectorParam::SEND_PING_TIMEOUT_S` for more info.
     Connecting,
+
+    /// The peer did not answer within `ConnectorParam::CONNECT_TIMEOUT_S` after calling `Connector::connect`, so the handshake never completed. This is different from `Disconnected`, where the connection was dropped.
+    Failed,
+}
+
+impl std::fmt::Display for NetworkState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            NetworkState::Connected => "connected",
+            NetworkState::Disconnected => "disconnected",
+            NetworkState::Connecting => "connecting",
+            NetworkState::Failed => "failed",
+        })
+    }
 }
 
+/// How a message was send, see `Connector::update_and_receive_only`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Reliability {
+    /// The message was send with `Connector::send_confirmed` or a variant of it
+    Confirmed,
+
+    /// The message was send with `Connector::send_unconfirmed` or a variant of it
+    Unconfirmed,
+
+    /// The message was send with `Connector::send_unconfirmed_ordered`. Messages that arrive after a newer message on the same stream are dropped.
+    UnconfirmedOrdered,
+}
+
+/// A message that was received, with how it was send
+struct Received<T> {
+    reliability: Reliability,
+    /// The id of a confirmed message, see `Connector::acknowledge_processed`
+    id: Option<NonZeroU64>,
+    message: T,
+}
+
+/// What happens with an outgoing datagram. This is returned from the interceptor passed to `Connector::set_send_interceptor`.
+#[cfg(any(test, feature = "chaos"))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SendAction {
+    /// Send the datagram as normal
+    Send,
+
+    /// Do not send the datagram, as if it was lost on the way
+    Drop,
+
+    /// Send the datagram twice
+    Duplicate,
+}
+
+/// A function that decides what happens with an outgoing datagram. See `Connector::set_send_interceptor` for more info.
+///
+/// The function must be `Send + Sync`, so the connector can still be moved between threads.
+#[cfg(any(test, feature = "chaos"))]
+pub type SendInterceptor = Box<dyn FnMut(&[u8]) -> SendAction + Send + Sync>;
+
+/// A function that is called with the old and new state when the state of a connector changes. See `Connector::on_state_change` for more info.
+///
+/// The function must be `Send + Sync`, so the connector can still be moved between threads.
+pub type StateChangeHandler = Box<dyn FnMut(NetworkState, NetworkState) + Send + Sync>;
+
+/// A function that decides which missing messages are requested from the peer. It is called by `update` with the id of every missing message, and how long ago it was last requested, ordered by id. It returns the ids that should be requested now. See `Connector::set_request_selector` for more info.
+///
+/// The function must be `Send + Sync`, so the connector can still be moved between threads.
+pub type RequestSelector =
+    Box<dyn FnMut(&[(NonZeroU64, Duration)]) -> Vec<NonZeroU64> + Send + Sync>;
+
 impl MissingId {
-    pub fn new(id: NonZeroU64) -> MissingId {
+    pub fn new(id: NonZeroU64, now: Instant) -> MissingId {
         MissingId {
             id,
-            last_request: Instant::now(),
+            last_request: now,
         }
     }
 }
 
+/// A packet that is send by `Connector::update` because its timer ran out
+#[derive(Debug, Clone, Copy)]
+enum Due {
+    Ping,
+    Request(NonZeroU64),
+    Retransmit(NonZeroU64),
+}
+
+/// The time of the system since the unix epoch, see `ConnectorParam::CLOCK_SYNC`
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Returns `Error::InvalidPeerAddress` if the address can not be send to: the unspecified address, or port 0
+fn validate_peer_addr(peer: SocketAddr) -> Result<()> {
+    if peer.ip().is_unspecified() || peer.port() == 0 {
+        return Err(Error::InvalidPeerAddress { peer }.into());
+    }
+    Ok(())
+}
+
+/// Returns `true` if the error means the send buffer of the socket is full. This is a temporary condition, so the send can be retried later.
+fn is_send_buffer_full(error: &failure::Error) -> bool {
+    #[cfg(windows)]
+    const ENOBUFS: Option<i32> = Some(10055);
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    const ENOBUFS: Option<i32> = Some(55);
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const ENOBUFS: Option<i32> = Some(105);
+    // The code differs between the other systems, so only `WouldBlock` is recognized there
+    #[cfg(not(any(
+        windows,
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "android"
+    )))]
+    const ENOBUFS: Option<i32> = None;
+
+    match error.downcast_ref::<std::io::Error>() {
+        Some(e) => {
+            e.kind() == ErrorKind::WouldBlock || (ENOBUFS.is_some() && e.raw_os_error() == ENOBUFS)
+        }
+        None => false,
+    }
+}
+
 /// A generic trait over a socket. This is automatically implemented for `UdpSocket` but can be implemented for your own connector as well.
 pub trait Socket {
     /// Receive data from any remote, returning the amount of bytes read, and the SocketAddr that the data was received from
@@ -143,7 +597,30 @@ pub trait Socket {
     fn local_addr(&self) -> SocketAddr;
 
     /// Send data to the given SocketAddr
-    fn send_to(&mut self, buffer: &[u8], target: SocketAddr) -> Result<()>;
+    fn send_to(&mut self, buffer: &[u8], target: SocketAddr) -> std::io::Result<()>;
+
+    /// The ECN marking of the datagram that was last returned by `recv_from`. Sockets that can read the ECN bits of received datagrams can implement this to let the connector back off when the network is congested, see `Connector::report_congestion`.
+    ///
+    /// The default implementation never reports congestion.
+    fn received_ecn(&self) -> Ecn {
+        Ecn::NotEct
+    }
+}
+
+/// The Explicit Congestion Notification marking of a received datagram, see `Socket::received_ecn`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Ecn {
+    /// The datagram was not send with ECN, or the socket does not support reading the marking
+    NotEct,
+
+    /// ECN capable transport, codepoint 0
+    Ect0,
+
+    /// ECN capable transport, codepoint 1
+    Ect1,
+
+    /// Congestion experienced: a router on the way marked the datagram instead of dropping it
+    Ce,
 }
 
 impl Socket for UdpSocket {
@@ -153,20 +630,109 @@ impl Socket for UdpSocket {
     fn local_addr(&self) -> SocketAddr {
         UdpSocket::local_addr(self).unwrap()
     }
-    fn send_to(&mut self, buffer: &[u8], target: SocketAddr) -> Result<()> {
-        UdpSocket::send_to(self, buffer, target)?;
-        Ok(())
+    fn send_to(&mut self, buffer: &[u8], target: SocketAddr) -> std::io::Result<()> {
+        UdpSocket::send_to(self, buffer, target).map(|_| ())
     }
 }
 
 impl<TParam: ConnectorParam> Connector<TParam> {
     /// Create a Connector that is bound to the given remote SocketAddr
+    ///
+    /// The address is not validated until `connect` is called, which returns `Error::InvalidPeerAddress` for an address that can not be send to, like `0.0.0.0:0`.
     pub fn bound_to(peer_addr: SocketAddr) -> Self {
-        Connector {
-            send: Default::default(),
-            receive: Default::default(),
+        let clock = Clock::System;
+        let now = clock.now();
+        let mut connector = Connector {
+            send: ConnectorSend::new(now),
+            receive: ConnectorReceive::new(now),
+            idle: ConnectorIdle::new(now),
             peer_addr,
+            local_addr: None,
+            inbox: VecDeque::new(),
+            events: VecDeque::new(),
+            acknowledgements: VecDeque::new(),
+            last_state: NetworkState::Disconnected,
+            connected_since: None,
+            connect_count: 0,
+            latest_unconfirmed: BTreeMap::new(),
+            next_sequences: BTreeMap::new(),
+            session_started: now,
+            handshake_started: None,
+            next_ping_nonce: 0,
+            clock,
+            stats: Stats::default(),
+            congested_at: None,
+            recent_sends: VecDeque::new(),
+            paused_at: None,
+            closed: false,
+            timing: Timing::of::<TParam>(),
+            #[cfg(any(test, feature = "chaos"))]
+            send_interceptor: None,
+            state_change_handler: None,
+            request_selector: None,
+            capture: None,
+            receive_buffer: Vec::new(),
+        };
+        connector.last_state = connector.state();
+        if connector.last_state == NetworkState::Connected {
+            connector.connected_since = Some(now);
+        }
+        connector
+    }
+
+    /// Create a Connector that is bound to the given remote SocketAddr, like `bound_to`. The cache of unconfirmed messages and the list of missing messages are allocated with room for `capacity` messages.
+    ///
+    /// This avoids reallocating the cache while it grows, for a sender that expects to have a lot of confirmed messages in flight, and the same for the list of a receiver that expects a lot of them to go missing at once. The capacity is kept when the connector reconnects.
+    pub fn with_capacity(peer_addr: SocketAddr, capacity: usize) -> Self {
+        let mut connector = Self::bound_to(peer_addr);
+        connector.send.unconfirmed_message_cache.reserve(capacity);
+        connector.receive.missing_message_id_list.reserve(capacity);
+        connector
+    }
+
+    /// Create a Connector that is bound to the given remote SocketAddr, and immediately start the connection handshake.
+    ///
+    /// Unlike `bound_to`, this validates that the peer address can be reached from the given socket, and returns an error if it can not.
+    pub fn connect_to(peer_addr: SocketAddr, socket: &mut dyn Socket) -> Result<Self> {
+        let local = socket.local_addr();
+        if local != peer_addr {
+            validate_peer_addr(peer_addr)?;
+        }
+        if local.is_ipv4() != peer_addr.is_ipv4() {
+            return Err(Error::AddressFamilyMismatch {
+                local,
+                peer: peer_addr,
+            }
+            .into());
+        }
+        let mut connector = Self::bound_to(peer_addr);
+        connector.local_addr = Some(local);
+        connector.connect(socket)?;
+        Ok(connector)
+    }
+
+    /// Create a Connector that is connected to the socket itself, and immediately start the connection handshake. Everything that is send is received by the same connector, including its own pings. This is useful for tests and tools.
+    ///
+    /// `ConnectorParam::TSend` and `ConnectorParam::TReceive` should be the same type, as the connector receives its own messages.
+    pub fn loopback(socket: &mut dyn Socket) -> Result<Self> {
+        Self::connect_to(socket.local_addr(), socket)
+    }
+
+    /// Returns `true` if this connector is connected to its own socket, see `loopback`
+    pub fn is_loopback(&self) -> bool {
+        self.local_addr == Some(self.peer_addr)
+    }
+
+    /// Tell the connector that the local address of its socket changed, e.g. because the socket was bound again after a network change.
+    ///
+    /// The local address is cached the first time the connector receives from a socket, to ignore datagrams that the connector send to itself. After a rebind, datagrams from the old address are no longer ignored, and datagrams from the new address are. A `loopback` connector keeps sending to itself, so its peer address changes to the new address as well.
+    ///
+    /// The connection itself is kept. The peer sees datagrams from a new address, so it has to accept them, e.g. with `ConnectorParam::ADDRESS_FILTER`, or the connection times out.
+    pub fn update_local_addr(&mut self, local_addr: SocketAddr) {
+        if self.is_loopback() {
+            self.peer_addr = local_addr;
         }
+        self.local_addr = Some(local_addr);
     }
 
     /// Get the socket address that this connector is paired with
@@ -175,19 +741,100 @@ impl<TParam: ConnectorParam> Connector<TParam> {
     }
 
     /// Connect to the `bound_addr`. This will reset the internal state of the connector, and start up the connection handshake
+    ///
+    /// The handshake starts a new session, see `Packet::Hello`. The peer then forgets which of our messages it received before, as the ids of our confirmed messages start at 1 again.
     pub fn connect(&mut self, socket: &mut dyn Socket) -> Result<()> {
-        self.send = Default::default();
-        self.receive = Default::default();
-        self.send_ping(socket)
+        // A loopback connector sends to its own socket, which may be bound to the unspecified address
+        if !self.is_loopback() {
+            validate_peer_addr(self.peer_addr)?;
+        }
+        let now = self.now();
+        self.send.reset(now);
+        self.receive.reset(now);
+        self.idle.reset(now);
+        self.closed = false;
+        self.session_started = now;
+        self.handshake_started = Some(now);
+        self.connected_since = Some(now);
+        self.connect_count = self.connect_count.saturating_add(1);
+        self.send_ping(socket)?;
+        self.refresh_state();
+        Ok(())
+    }
+
+    /// Connect to the `bound_addr` like `connect`, and block until the peer answers. If it does not answer in time, `connect` is called again, waiting longer every attempt, as decided by the `policy`. Returns `Error::ReconnectFailed` when the policy gives up.
+    ///
+    /// The socket should be non-blocking, as it is polled every few milliseconds while waiting. Messages that arrive in the meantime are queued, see `pop_message`.
+    pub fn reconnect_loop(&mut self, socket: &mut dyn Socket, policy: BackoffPolicy) -> Result<()> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(10);
+        let mut elapsed = Duration::from_secs(0);
+        let mut attempts = 0;
+        loop {
+            self.connect(socket)?;
+            let delay = policy.jittered_delay(attempts);
+            attempts += 1;
+            let mut waited = Duration::from_secs(0);
+            loop {
+                self.update_and_queue(socket)?;
+                if !self.is_before_connected() {
+                    return Ok(());
+                }
+                if waited >= delay || self.state() == NetworkState::Failed {
+                    break;
+                }
+                let step = POLL_INTERVAL.min(delay - waited);
+                self.clock.sleep(step);
+                waited += step;
+            }
+            elapsed += waited;
+            if policy.gives_up(attempts, elapsed) {
+                return Err(Error::ReconnectFailed { attempts }.into());
+            }
+        }
+    }
+
+    /// The moment the current connected session began, or `None` if the connector is not connected. A session begins when the state changes to `NetworkState::Connected`, or when `connect` is called.
+    pub fn connected_since(&self) -> Option<Instant> {
+        if self.state() == NetworkState::Connected {
+            self.connected_since
+        } else {
+            None
+        }
+    }
+
+    /// How long the current connected session has lasted, or `None` if the connector is not connected. See `connected_since` for more info.
+    pub fn session_uptime(&self) -> Option<Duration> {
+        self.connected_since()
+            .map(|since| self.now().saturating_duration_since(since))
+    }
+
+    /// The amount of times `connect` was called to reconnect, not counting the first time. A high number can indicate a flapping connection.
+    pub fn reconnect_count(&self) -> u32 {
+        self.connect_count.saturating_sub(1)
     }
 
     /// Get the current state of this connector. This is dependent on a couple of settings in ConnectorParam:
     /// * If we have received a ping since `ConnectorParam::RECEIVE_PING_TIMEOUT_S` ago, we're connected
     /// * If we have send a ping since `ConnectorParam::SEND_PING_TIMEOUT_S` ago, we're connecting
     /// * Else we're disconnected
+    ///
+    /// If the peer did not answer a ping within `ConnectorParam::CONNECT_TIMEOUT_S` after calling `connect`, the connector has failed until `connect` is called again.
+    ///
+    /// A connector that is closed by either side with `close`, or that exceeded `ConnectorParam::MAX_SESSION_DURATION_S`, is always disconnected. While the connector is paused, the state is frozen at the moment `pause` was called.
     pub fn state(&self) -> NetworkState {
-        if self.receive.last_ping.elapsed().as_secs_f64() > TParam::RECEIVE_PING_TIMEOUT_S {
-            if self.send.last_ping.elapsed().as_secs_f64() > TParam::SEND_PING_TIMEOUT_S {
+        if self.closed || self.is_session_expired() {
+            return NetworkState::Disconnected;
+        }
+        let now = self.now();
+        if let (Some(started), Some(timeout)) =
+            (self.handshake_started, self.timing.connect_timeout_s)
+        {
+            if seconds_between(started, now) > timeout {
+                return NetworkState::Failed;
+            }
+        }
+        if seconds_between(self.receive.last_ping, now) > self.receive_timeout_s() {
+            if seconds_between(self.send.last_ping, now) > self.timing.send_ping_timeout_s {
                 NetworkState::Connecting
             } else {
                 NetworkState::Disconnected
@@ -197,182 +844,1745 @@ impl<TParam: ConnectorParam> Connector<TParam> {
         }
     }
 
-    /// Receive data from the other connector. This will call `handle_incoming_data` internally.
+    /// The time until this connector considers the connection lost, because no ping was received from the peer for `ConnectorParam::RECEIVE_PING_TIMEOUT_S`. This is reset every time a ping arrives, and can be used to show a countdown instead of only the `state`.
     ///
-    /// Ideally you would never need this function. Use `update_and_receive` on clients, and `handle_incoming_data` on servers.
-    pub fn receive_from(&mut self, socket: &mut dyn Socket) -> Result<Vec<TParam::TReceive>> {
-        let mut buffer = [0u8; 1024];
-        let mut result = Vec::new();
-        let mut had_message = false;
-        loop {
-            let receive_result = socket.recv_from(&mut buffer);
-            let count = match receive_result {
-                Ok((_, addr)) if addr != self.peer_addr => continue, // ignored
-                Ok((count, _)) if count == 0 => {
-                    if !had_message {
-                        return Err(std::io::Error::from(ErrorKind::BrokenPipe).into());
-                    } else {
-                        return Ok(result);
-                    }
-                }
-                Ok((count, _)) => count,
-                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(result),
-                Err(e) => return Err(e.into()),
-            };
-            had_message = true;
-            if let Some(msg) = self.handle_incoming_data(socket, &buffer[..count])? {
-                result.push(msg);
-            }
+    /// Returns `None` if the timeout already passed, if the connector is closed, or if it failed to connect, see `NetworkState::Failed`.
+    pub fn time_until_disconnect(&self) -> Option<Duration> {
+        if let NetworkState::Disconnected | NetworkState::Failed = self.state() {
+            return None;
         }
+        let elapsed = self.now().saturating_duration_since(self.receive.last_ping);
+        duration_from_secs(self.receive_timeout_s()).checked_sub(elapsed)
     }
 
-    /// Update this connector and receive data from the remote connector.
-    pub fn update_and_receive(&mut self, socket: &mut dyn Socket) -> Result<Vec<TParam::TReceive>> {
-        self.update(socket)?;
-        self.receive_from(socket)
+    /// Returns `true` if both sides had no data to send for `ConnectorParam::IDLE_AFTER_S`, so pings are send every `ConnectorParam::IDLE_PING_INTERVAL_S`. Changes are reported as `Event::IdleStarted` and `Event::IdleEnded`.
+    pub fn is_idle(&self) -> bool {
+        self.is_quiet() && self.idle.peer_acknowledged
     }
 
-    /// Update this connector. This will make sure the connection is still intact and requests any potentially missing packets.
-    pub fn update(&mut self, socket: &mut dyn Socket) -> Result<()> {
-        if NetworkState::Disconnected == self.state() {
-            return Ok(());
+    /// Returns `true` if we had no data to send for `ConnectorParam::IDLE_AFTER_S`, and are not waiting for the peer to confirm or resend a message
+    fn is_quiet(&self) -> bool {
+        TParam::IDLE_AFTER_S
+            .is_some_and(|after| seconds_between(self.idle.last_data, self.now()) > after)
+            && self.send.unconfirmed_message_cache.is_empty()
+            && self.receive.missing_message_id_list.is_empty()
+    }
+
+    /// The interval at which pings are send, see `ConnectorParam::IDLE_AFTER_S`
+    fn ping_interval_s(&self) -> f64 {
+        if self.is_idle() {
+            TParam::IDLE_PING_INTERVAL_S
+        } else {
+            self.timing.ping_interval_s
+        }
+    }
+
+    /// The time without a ping after which the peer is considered gone. This is longer when the peer is idle, see `ConnectorParam::IDLE_AFTER_S`.
+    fn receive_timeout_s(&self) -> f64 {
+        if self.idle.peer_quiet {
+            self.timing.receive_ping_timeout_s + TParam::IDLE_PING_INTERVAL_S
+        } else {
+            self.timing.receive_ping_timeout_s
         }
-        if self.send.last_ping.elapsed().as_secs_f64() > TParam::PING_INTERVAL_S {
-            self.send_ping(socket)?;
+    }
+
+    /// Leave idle mode because a message was send, or received when `received` is set
+    fn wake(&mut self, received: bool) {
+        let now = self.now();
+        if received && self.idle.peer_quiet {
+            // The peer sends pings at the normal interval again, but the first one may still be on its way
+            self.receive.last_ping = now;
         }
-        for missing_packet in &mut self.receive.missing_message_id_list {
-            if missing_packet.last_request.elapsed().as_secs_f64()
-                > TParam::REQUEST_MISSING_PACKET_INTERVAL_S
-            {
-                send_packet_to::<TParam::TSend>(
-                    self.peer_addr,
-                    socket,
-                    &Packet::RequestPacket {
-                        id: missing_packet.id,
-                    },
-                )?;
-                missing_packet.last_request = Instant::now();
-            }
-        }
-        for unconfirmed_packet in self.send.unconfirmed_message_cache.values_mut() {
-            if unconfirmed_packet.last_emit.elapsed().as_secs_f64()
-                > TParam::EMIT_UNCONFIRMED_PACKET_INTERVAL_S
-            {
-                unconfirmed_packet.last_emit = Instant::now();
-                send_packet_to(self.peer_addr, socket, &unconfirmed_packet.packet)?;
-            }
+        self.idle.last_data = now;
+        self.idle.last_sent = None;
+        self.idle.peer_acknowledged = false;
+        if received {
+            self.idle.peer_quiet = false;
         }
+        self.refresh_idle();
+    }
+
+    /// Queue an `Event::IdleStarted` or `Event::IdleEnded` if the idle mode changed since the last time this was called
+    fn refresh_idle(&mut self) {
+        let idle = self.is_idle();
+        if idle != self.idle.active {
+            self.idle.active = idle;
+            self.push_event(if idle {
+                Event::IdleStarted
+            } else {
+                Event::IdleEnded
+            });
+        }
+    }
+
+    /// Tell the peer that we have no data to send, and whether we know the peer has no data either
+    fn send_idle(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        let acknowledged = self.idle.peer_quiet;
+        self.send_packet(socket, &Packet::<TParam::TSend>::Idle { acknowledged })?;
+        self.idle.last_sent = Some(acknowledged);
         Ok(())
     }
 
-    /// Resolve an incoming ping or ping.
-    /// This will request all the messages up to this message, as well as set the last received time.
-    fn resolve_incoming_ping(&mut self, id: Option<NonZeroU64>) {
-        if let Some(last_send_message_id) = id {
-            self.request_message_up_to(last_send_message_id.get());
+    /// Send a ping right away, e.g. to measure the latency when the user asks for it. The round trip time is available from `last_rtt` once the peer answers.
+    ///
+    /// This does not delay the next regular ping, see `ConnectorParam::PING_INTERVAL_S`.
+    pub fn ping_now(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        self.send_ping_packet(socket)
+    }
+
+    /// The round trip time of the last ping that was answered by the peer, or `None` if no ping was answered since the last call to `connect`.
+    pub fn last_rtt(&self) -> Option<Duration> {
+        self.send.last_rtt
+    }
+
+    /// The estimated difference between the clock of the peer and the clock of this machine in seconds, positive when the clock of the peer is ahead. Add this to a local unix timestamp to get the matching timestamp of the peer, e.g. to place the timestamped updates of the peer on the local timeline. Returns `None` until a pong arrived with the time of the peer, which requires `ConnectorParam::CLOCK_SYNC` on the peer.
+    ///
+    /// Every pong with the time of the peer is a measurement, assuming the pong took half the round trip time to arrive. Of the last few measurements, the one with the shortest round trip time is used, as it was delayed the least by queues along the way. The error is at most half of that round trip time, and is larger when the route to the peer is slower in one direction than the other, which can not be measured.
+    pub fn estimated_clock_offset(&self) -> Option<f64> {
+        self.send
+            .clock_samples
+            .iter()
+            .min_by_key(|(rtt, _)| *rtt)
+            .map(|(_, offset)| *offset)
+    }
+
+    /// The id of the last confirmed message that the peer send, as told in its pings and pongs. Returns `None` if the peer did not send a confirmed message since the last call to `connect`, or did not tell us yet.
+    ///
+    /// Together with `last_received_id`, this tells roughly how many confirmed messages of the peer are still on their way, e.g. to show the progress of a large transfer.
+    pub fn peer_last_sent_id(&self) -> Option<NonZeroU64> {
+        self.receive.peer_last_sent_id
+    }
+
+    /// The highest id of the confirmed messages that were received from the peer, or `None` if none were received since the last call to `connect`. Messages with a lower id may still be missing.
+    pub fn last_received_id(&self) -> Option<NonZeroU64> {
+        self.receive.highest_received_id
+    }
+
+    /// Collect the settings this connector runs with, as configured in `ConnectorParam` and `set_timing`. The report can be serialized, e.g. to attach it to a support ticket.
+    pub fn config_report(&self) -> ConfigReport {
+        ConfigReport::of::<TParam>().with_timing(&self.timing)
+    }
+
+    /// The intervals and timeouts this connector runs with. These are the values of `ConnectorParam`, unless they were changed with `set_timing`.
+    pub fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    /// Change the intervals and timeouts of this connector, e.g. to values that were loaded from a configuration file. The matching constants in `ConnectorParam` are only used as the initial values. See `DefaultParam` for more info.
+    ///
+    /// The new values are used from the next call to `update` or `state`, they do not restart any timers.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    /// Change the interval at which pings are send, like `ConnectorParam::PING_INTERVAL_S`. This is a shortcut for changing `Timing::ping_interval_s` with `set_timing`, e.g. to give a single peer a different interval than the others of the same `ConnectorParam`.
+    pub fn set_ping_interval(&mut self, interval: Duration) {
+        self.timing.ping_interval_s = interval.as_secs_f64();
+    }
+
+    /// Change the time without a ping from the peer after which the connection is considered lost, like `ConnectorParam::RECEIVE_PING_TIMEOUT_S`. This is a shortcut for changing `Timing::receive_ping_timeout_s` with `set_timing`, e.g. to give a peer on a mobile network more time than the others.
+    pub fn set_receive_timeout(&mut self, timeout: Duration) {
+        self.timing.receive_ping_timeout_s = timeout.as_secs_f64();
+    }
+
+    /// Check the intervals in `ConnectorParam` against the round trip time that was measured, see `last_rtt`. This is meant to help tuning the settings, e.g. in a debug build.
+    ///
+    /// Returns `Error::IntervalShorterThanRtt` if `ConnectorParam::EMIT_UNCONFIRMED_PACKET_INTERVAL_S` is shorter than the round trip time plus `ConnectorParam::ACK_DELAY_S`, or if `ConnectorParam::REQUEST_MISSING_PACKET_INTERVAL_S` is shorter than the round trip time. Either means packets are resend before the answer of the peer could have arrived. Nothing is checked until a round trip time was measured.
+    pub fn validate(&self) -> Result<()> {
+        let rtt = match self.last_rtt() {
+            Some(rtt) => rtt,
+            None => return Ok(()),
+        };
+        let checks = [
+            (
+                "EMIT_UNCONFIRMED_PACKET_INTERVAL_S",
+                self.timing.emit_unconfirmed_packet_interval_s,
+                rtt.saturating_add(duration_from_secs(self.timing.ack_delay_s)),
+            ),
+            (
+                "REQUEST_MISSING_PACKET_INTERVAL_S",
+                self.timing.request_missing_packet_interval_s,
+                rtt,
+            ),
+        ];
+        for (setting, interval, rtt) in checks {
+            let interval = duration_from_secs(interval);
+            if interval < rtt {
+                return Err(Error::IntervalShorterThanRtt {
+                    setting,
+                    interval,
+                    rtt,
+                }
+                .into());
+            }
         }
-        self.receive.last_ping = Instant::now();
+        Ok(())
     }
 
-    /// Handles incoming data. This will perform internal logic to make sure data is being transmitted correctly,
-    /// and requests missing packets.
+    /// The time without a ping from the peer after which this connector considers the connection lost, as configured by `ConnectorParam::RECEIVE_PING_TIMEOUT_S`, or changed with `set_timing` or `set_receive_timeout`. This is useful to show in logs and tooling.
+    pub fn effective_receive_timeout(&self) -> Duration {
+        duration_from_secs(self.timing.receive_ping_timeout_s)
+    }
+
+    /// The time after sending a ping after which this connector stops considering itself connecting, as configured by `ConnectorParam::SEND_PING_TIMEOUT_S`, or changed with `set_timing`. This is useful to show in logs and tooling.
+    pub fn effective_send_timeout(&self) -> Duration {
+        duration_from_secs(self.timing.send_ping_timeout_s)
+    }
+
+    /// Close the connection. This lets the peer know that we're disconnecting, with an optional reason, so it does not have to wait for a timeout. The peer receives this as an `Event::Disconnected`.
     ///
-    /// Any actual data that was received, will be returned from this function.
-    pub fn handle_incoming_data(
+    /// Any messages that are still buffered are flushed first, see `ConnectorParam::BUFFER_SENDS`, and delayed confirmations are send, see `ConnectorParam::ACK_DELAY_S`. After this the connector is disconnected, until `connect` is called.
+    ///
+    /// While closed, every datagram of the peer is dropped and counted in `Stats::closed_drops`, so it can not change the state of the connector. This includes the handshake of a new session of the peer, only calling `connect` on this connector reopens the connection.
+    pub fn close(
         &mut self,
         socket: &mut dyn Socket,
-        data: &[u8],
-    ) -> Result<Option<TParam::TReceive>> {
-        let packet: Packet<_> = bincode::deserialize(data)?;
-        Ok(match packet {
-            Packet::Ping {
+        reason: Option<DisconnectReason>,
+    ) -> Result<()> {
+        self.flush(socket)?;
+        self.send_pending_acks(socket)?;
+        self.closed = true;
+        self.send_packet(socket, &Packet::<TParam::TSend>::Disconnect { reason })?;
+        self.refresh_state();
+        Ok(())
+    }
+
+    /// Close the connection like `close`, after a best effort to deliver the confirmed messages that the peer did not confirm yet, e.g. when the application shuts down. Buffered messages are flushed, and every unconfirmed message is resend 3 times, spread over the `grace` period. The regular resends of `update` continue as well, so a message can be send more often than that when `ConnectorParam::EMIT_UNCONFIRMED_PACKET_INTERVAL_S` is shorter than a third of `grace`. Once the peer confirmed all messages, or the connection is lost, this stops waiting and sends the `Packet::Disconnect` right away.
+    ///
+    /// Like `reconnect_loop` this blocks, and polls the socket every few milliseconds, so the socket should be non-blocking. Messages that arrive in the meantime are queued, see `pop_message`.
+    pub fn shutdown(&mut self, socket: &mut dyn Socket, grace: Duration) -> Result<()> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(10);
+        const RESENDS: u32 = 3;
+        self.flush(socket)?;
+        let resend_interval = grace / RESENDS;
+        let mut waited = Duration::from_secs(0);
+        let mut resends = 0;
+        loop {
+            self.update_and_queue(socket)?;
+            if self.send.unconfirmed_message_cache.is_empty()
+                || waited >= grace
+                || matches!(
+                    self.state(),
+                    NetworkState::Disconnected | NetworkState::Failed
+                )
+            {
+                break;
+            }
+            if resends < RESENDS && waited >= resend_interval * resends {
+                self.resend_unconfirmed(socket)?;
+                resends += 1;
+            }
+            let step = POLL_INTERVAL.min(grace - waited);
+            self.clock.sleep(step);
+            waited += step;
+        }
+        self.close(socket, None)
+    }
+
+    /// Resend every confirmed message that the peer did not confirm yet, regardless of when it was last send. Stops early when the send buffer of the socket is full.
+    fn resend_unconfirmed(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        let now = self.now();
+        let ids = self
+            .send
+            .unconfirmed_message_cache
+            .iter()
+            .filter(|(_, packet)| !packet.held)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            let bytes = self.send.unconfirmed_message_cache[&id].bytes.clone();
+            match self.send_bytes(socket, &bytes) {
+                Err(ref e) if is_send_buffer_full(e) => {
+                    self.stats.deferred_sends += 1;
+                    break;
+                }
+                result => result?,
+            }
+            if let Some(packet) = self.send.unconfirmed_message_cache.get_mut(&id) {
+                packet.last_emit = now;
+            }
+            self.record_confirmed_send(true);
+        }
+        Ok(())
+    }
+
+    /// Disconnect the peer immediately, e.g. to kick a client that misbehaves. Like `close`, this sends a `Packet::Disconnect` with an optional reason, and the connector is disconnected until `connect` is called.
+    ///
+    /// Unlike `close`, nothing else is send: buffered messages and delayed confirmations are dropped, and the unconfirmed messages are forgotten, so `update` sends nothing after this. Every datagram of the peer is dropped afterwards, including the handshake of a new session, see `close`, so the peer can not get back in through this connector.
+    pub fn force_disconnect(
+        &mut self,
+        socket: &mut dyn Socket,
+        reason: Option<DisconnectReason>,
+    ) -> Result<()> {
+        let now = self.now();
+        self.send.reset(now);
+        self.receive.reset(now);
+        self.closed = true;
+        self.send_packet(socket, &Packet::<TParam::TSend>::Disconnect { reason })?;
+        self.refresh_state();
+        Ok(())
+    }
+
+    /// Pause this connector, e.g. when the application is moved to the background. While paused, `update` does nothing, so no pings are send and no packets are requested or resend.
+    ///
+    /// Time is frozen while the connector is paused, so the connection is not considered lost because of the time spent paused. Call `resume` to continue.
+    pub fn pause(&mut self) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(self.clock.now());
+        }
+    }
+
+    /// Resume a connector that was paused with `pause`. The time of the last send and received ping is set to now, so `state` does not immediately report a timeout. Other timers continue where they left off.
+    pub fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            let now = self.clock.now();
+            let paused = now.saturating_duration_since(paused_at);
+            self.send.last_ping = now;
+            self.receive.last_ping = now;
+            for packet in self.send.unconfirmed_message_cache.values_mut() {
+                packet.last_emit += paused;
+            }
+            for missing in &mut self.receive.missing_message_id_list {
+                missing.last_request += paused;
+            }
+        }
+    }
+
+    /// Returns `true` if this connector is paused. See `pause` for more info.
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    /// The current time of this connector. This is frozen while the connector is paused.
+    fn now(&self) -> Instant {
+        self.paused_at.unwrap_or_else(|| self.clock.now())
+    }
+
+    /// Receive data from the other connector. This will call `handle_incoming_data` internally.
+    ///
+    /// Ideally you would never need this function. Use `update_and_receive` on clients, and `handle_incoming_data` on servers.
+    ///
+    /// Datagrams that are not accepted by `ConnectorParam::ADDRESS_FILTER` are ignored. Datagrams from the local address of the socket are ignored as well, unless this connector is connected to itself with `loopback`.
+    pub fn receive_from(&mut self, socket: &mut dyn Socket) -> Result<Vec<TParam::TReceive>> {
+        let messages = self.receive_tagged(socket)?;
+        Ok(messages
+            .into_iter()
+            .map(|received| received.message)
+            .collect())
+    }
+
+    /// Receive and handle a single datagram from the other connector, like `receive_from`. This allows an event loop to do other work between datagrams, instead of handling every datagram that is waiting at once.
+    ///
+    /// Returns `None` if no datagram was waiting. Otherwise the messages in the datagram are returned, which can be none, e.g. for a ping, or multiple if the peer coalesced them with `flush`.
+    pub fn receive_once(
+        &mut self,
+        socket: &mut dyn Socket,
+    ) -> Result<Option<Vec<TParam::TReceive>>> {
+        self.with_receive_buffer(|connector, buffer| {
+            match connector.receive_datagram(socket, buffer)? {
+                None => Ok(None),
+                Some((0, _)) => Err(std::io::Error::from(ErrorKind::BrokenPipe).into()),
+                Some((count, from)) => {
+                    let messages = connector.handle_received(socket, from, &buffer[..count])?;
+                    Ok(Some(
+                        messages
+                            .into_iter()
+                            .map(|received| received.message)
+                            .collect(),
+                    ))
+                }
+            }
+        })
+    }
+
+    /// Receive data like `receive_from`, returning every message with its reliability
+    fn receive_tagged(
+        &mut self,
+        socket: &mut dyn Socket,
+    ) -> Result<Vec<Received<TParam::TReceive>>> {
+        self.with_receive_buffer(|connector, buffer| {
+            let mut result = Vec::new();
+            let mut had_message = false;
+            loop {
+                let (count, from) = match connector.receive_datagram(socket, buffer)? {
+                    Some((0, _)) if !had_message => {
+                        return Err(std::io::Error::from(ErrorKind::BrokenPipe).into())
+                    }
+                    None | Some((0, _)) => return Ok(result),
+                    Some(received) => received,
+                };
+                had_message = true;
+                result.extend(connector.handle_received(socket, from, &buffer[..count])?);
+            }
+        })
+    }
+
+    /// A buffer that fits the largest datagram that can be received
+    pub(crate) fn receive_buffer() -> Vec<u8> {
+        vec![0u8; TParam::PADDING.padded_size(TParam::MAX_RECEIVE_DATAGRAM_SIZE)]
+    }
+
+    /// Call `f` with the receive buffer of this connector, allocating it on the first call. The buffer is taken out of the connector while `f` runs, so `f` can use the connector as well.
+    fn with_receive_buffer<R>(&mut self, f: impl FnOnce(&mut Self, &mut [u8]) -> R) -> R {
+        let mut buffer = std::mem::take(&mut self.receive_buffer);
+        if buffer.is_empty() {
+            buffer = Self::receive_buffer();
+        }
+        let result = f(self, &mut buffer);
+        self.receive_buffer = buffer;
+        result
+    }
+
+    /// Read the next datagram from the peer into the buffer, skipping datagrams from other addresses. Returns the size of the datagram and the address it came from, which can differ from `bound_addr` with `ConnectorParam::ADDRESS_FILTER`, or `None` if no datagram is waiting.
+    fn receive_datagram(
+        &mut self,
+        socket: &mut dyn Socket,
+        buffer: &mut [u8],
+    ) -> Result<Option<(usize, SocketAddr)>> {
+        let local_addr = *self.local_addr.get_or_insert_with(|| socket.local_addr());
+        let loopback = self.is_loopback();
+        loop {
+            return match socket.recv_from(buffer) {
+                Ok((_, addr)) if !TParam::ADDRESS_FILTER.matches(self.peer_addr, addr) => continue, // ignored
+                Ok((_, addr)) if addr == local_addr && !loopback => continue, // our own datagram
+                Ok(received) => Ok(Some(received)),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+                Err(e) => Err(e.into()),
+            };
+        }
+    }
+
+    /// Handle a datagram that was read by `receive_datagram`
+    fn handle_received(
+        &mut self,
+        socket: &mut dyn Socket,
+        from: SocketAddr,
+        data: &[u8],
+    ) -> Result<Vec<Received<TParam::TReceive>>> {
+        if socket.received_ecn() == Ecn::Ce {
+            self.report_congestion();
+        }
+        let messages = self.handle_datagram(socket, from, data, Self::decode_message)?;
+        Ok(self.queue_messages(messages))
+    }
+
+    /// Queue the messages as `Event::Message` if `ConnectorParam::QUEUE_MESSAGES` is enabled, and return the messages that are not queued
+    fn queue_messages(
+        &mut self,
+        messages: Vec<Received<TParam::TReceive>>,
+    ) -> Vec<Received<TParam::TReceive>> {
+        if !TParam::QUEUE_MESSAGES {
+            return messages;
+        }
+        for received in messages {
+            self.push_event(Event::Message(received.message));
+        }
+        Vec::new()
+    }
+
+    /// Update this connector and receive data from the remote connector, like `update_and_receive`, but only return the messages that were send with the given reliability. The other messages are kept until this is called with their reliability.
+    ///
+    /// This allows processing confirmed and unconfirmed messages at a different rate, e.g. draining movement updates every frame but handling chat messages less often. Make sure to eventually receive both kinds of messages, or the kept messages will pile up.
+    pub fn update_and_receive_only(
+        &mut self,
+        socket: &mut dyn Socket,
+        reliability: Reliability,
+    ) -> Result<Vec<TParam::TReceive>> {
+        self.update(socket)?;
+        let messages = self.receive_tagged(socket)?;
+        self.inbox.extend(messages);
+        let mut result = Vec::new();
+        let mut kept = VecDeque::with_capacity(self.inbox.len());
+        for received in self.inbox.drain(..) {
+            if received.reliability == reliability {
+                result.push(received.message);
+            } else {
+                kept.push_back(received);
+            }
+        }
+        self.inbox = kept;
+        Ok(result)
+    }
+
+    /// Update this connector and receive data from the remote connector, like `update_and_receive`. Instead of returning the received messages, they are added to a queue, so they can be handled one at a time with `peek_message` and `pop_message`.
+    pub fn update_and_queue(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        self.update(socket)?;
+        let messages = self.receive_tagged(socket)?;
+        self.inbox.extend(messages);
+        Ok(())
+    }
+
+    /// Get the oldest message in the queue without removing it, e.g. to decide whether to handle it now. Messages are queued by `update_and_queue`, and by `update_and_receive_only` for the reliability that was not asked for.
+    pub fn peek_message(&self) -> Option<&TParam::TReceive> {
+        self.inbox.front().map(|received| &received.message)
+    }
+
+    /// Remove the oldest message from the queue and return it. See `peek_message` for more info.
+    pub fn pop_message(&mut self) -> Option<TParam::TReceive> {
+        self.inbox.pop_front().map(|received| received.message)
+    }
+
+    /// Update this connector and receive data from the remote connector.
+    pub fn update_and_receive(&mut self, socket: &mut dyn Socket) -> Result<Vec<TParam::TReceive>> {
+        self.update(socket)?;
+        self.receive_from(socket)
+    }
+
+    /// Update this connector and receive data from the remote connector, like `update_and_receive`, returning every message with its id. Only confirmed messages have an id, which can be passed to `acknowledge_processed`.
+    pub fn update_and_receive_with_ids(
+        &mut self,
+        socket: &mut dyn Socket,
+    ) -> Result<Vec<(Option<NonZeroU64>, TParam::TReceive)>> {
+        self.update(socket)?;
+        let messages = self.receive_tagged(socket)?;
+        Ok(messages
+            .into_iter()
+            .map(|received| (received.id, received.message))
+            .collect())
+    }
+
+    /// Update this connector and receive data from the remote connector, like `update_and_receive`. Instead of returning the received messages, they are queued as `Event::Message` and can be retrieved with `drain_events`. To queue the messages of `handle_incoming_data` as well, see `ConnectorParam::QUEUE_MESSAGES`.
+    pub fn poll(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        let messages = self.update_and_receive(socket)?;
+        for message in messages {
+            self.push_event(Event::Message(message));
+        }
+        Ok(())
+    }
+
+    /// Drain all events that were queued since the last call to this function.
+    ///
+    /// Events are queued by `handle_incoming_data`, `update` and `connect`. If more than `ConnectorParam::EVENT_QUEUE_CAPACITY` events are queued, the oldest events are discarded.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = Event<TParam::TReceive>> + '_ {
+        self.events.drain(..)
+    }
+
+    /// Take all events that were queued since the last call to `drain_events` or this function, leaving the queue empty.
+    ///
+    /// This is useful to hand a whole batch of events to another thread at once.
+    pub fn take_events(&mut self) -> Vec<Event<TParam::TReceive>> {
+        std::mem::take(&mut self.events).into()
+    }
+
+    /// Take the ids of the confirmed messages that were confirmed by the peer since the last call to this function, in the order the confirmations arrived.
+    ///
+    /// These are the same ids as in `Event::Acknowledged`, but they are kept separately from the events, so they can be handled by a different part of the application than the messages and other events. If more than `ConnectorParam::EVENT_QUEUE_CAPACITY` ids are waiting, the oldest ids are discarded.
+    pub fn drain_acknowledgements(&mut self) -> Vec<NonZeroU64> {
+        self.acknowledgements.drain(..).collect()
+    }
+
+    /// Let the peer know that the application handled the confirmed message with the given id, which the peer receives as `Event::Processed`. This tells the peer that the message was not only received, but e.g. also stored or executed. The id is returned by `update_and_receive_with_ids` or `handle_incoming_data_with_ids`.
+    ///
+    /// This is send once, and is not resend if it gets lost. The peer should treat a missing `Event::Processed` as unknown, not as failed.
+    pub fn acknowledge_processed(&mut self, socket: &mut dyn Socket, id: NonZeroU64) -> Result<()> {
+        self.send_packet(socket, &Packet::<TParam::TSend>::Processed { id })?;
+        Ok(())
+    }
+
+    fn push_event(&mut self, event: Event<TParam::TReceive>) {
+        #[cfg(any(test, feature = "async"))]
+        if let Event::Acknowledged(id) | Event::MessageExpired(id) = event {
+            if let Some(waiter) = self.send.deliveries.remove(&id) {
+                waiter.resolve(matches!(event, Event::Acknowledged(_)));
+            }
+        }
+        if let Event::Acknowledged(id) = event {
+            if self.acknowledgements.len() >= TParam::EVENT_QUEUE_CAPACITY {
+                self.acknowledgements.pop_front();
+            }
+            self.acknowledgements.push_back(id);
+        }
+        if self.events.len() >= TParam::EVENT_QUEUE_CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Returns `true` if the current session lasted longer than `ConnectorParam::MAX_SESSION_DURATION_S`
+    fn is_session_expired(&self) -> bool {
+        TParam::MAX_SESSION_DURATION_S
+            .is_some_and(|max| seconds_between(self.session_started, self.now()) > max)
+    }
+
+    /// Set a function that decides which missing messages are requested from the peer by `update`, instead of `ConnectorParam::REQUEST_MISSING_PACKET_INTERVAL_S`, `ConnectorParam::MISSING_REQUEST_ORDER` and `ConnectorParam::MAX_REQUESTS_PER_UPDATE`. `None` restores the default behavior.
+    ///
+    /// This allows a custom loss-recovery policy, e.g. only requesting messages that are still relevant for the current game tick. The function is called on every `update` with all missing messages, ordered by id. Returned ids that are not missing are ignored, and every id is requested once, in order of id. Requests still count towards `ConnectorParam::MAX_AMPLIFICATION`.
+    pub fn set_request_selector(&mut self, selector: Option<RequestSelector>) {
+        self.request_selector = selector;
+    }
+
+    /// The ids of the missing messages that should be requested from the peer now
+    fn select_requests(&mut self, now: Instant) -> Vec<NonZeroU64> {
+        let missing_list = &self.receive.missing_message_id_list;
+        if let Some(selector) = self.request_selector.as_mut() {
+            let missing = missing_list
+                .iter()
+                .map(|missing| {
+                    (
+                        missing.id,
+                        now.saturating_duration_since(missing.last_request),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let mut requests = selector(&missing);
+            requests.retain(|id| missing.binary_search_by_key(id, |(id, _)| *id).is_ok());
+            requests.sort_unstable();
+            requests.dedup();
+            return requests;
+        }
+        let mut requests = missing_list
+            .iter()
+            .filter(|missing| {
+                seconds_between(missing.last_request, now)
+                    > self.timing.request_missing_packet_interval_s
+            })
+            .map(|missing| (missing.last_request, missing.id))
+            .collect::<Vec<_>>();
+        match TParam::MISSING_REQUEST_ORDER {
+            // Packets that were requested the longest ago go first, so no packet is starved by `MAX_REQUESTS_PER_UPDATE`
+            MissingRequestOrder::Oldest => requests.sort_unstable(),
+            MissingRequestOrder::Newest => {
+                requests.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)))
+            }
+        }
+        requests.truncate(TParam::MAX_REQUESTS_PER_UPDATE);
+        requests.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// Set a function that is called with the old and new state whenever the state of this connector changes. This replaces the function that was set before.
+    ///
+    /// The state is checked by `update`, `handle_incoming_data`, `connect` and `close`, so a change is noticed the next time one of these is called. The function is called once for every change, right before the matching `Event::StateChanged` is queued.
+    pub fn on_state_change(
+        &mut self,
+        handler: impl FnMut(NetworkState, NetworkState) + Send + Sync + 'static,
+    ) {
+        self.state_change_handler = Some(Box::new(handler));
+    }
+
+    /// Queue an `Event::StateChanged` if the state is different from the last time this was called.
+    fn refresh_state(&mut self) {
+        let state = self.state();
+        if state != self.last_state {
+            let old = std::mem::replace(&mut self.last_state, state);
+            if let Some(handler) = self.state_change_handler.as_mut() {
+                handler(old, state);
+            }
+            self.connected_since = match state {
+                NetworkState::Connected => Some(self.now()),
+                _ => None,
+            };
+            self.push_event(Event::StateChanged(state));
+        }
+    }
+
+    /// Update this connector. This will make sure the connection is still intact and requests any potentially missing packets.
+    ///
+    /// Confirmed messages that are past their deadline are abandoned, even when disconnected. See `send_confirmed_with_deadline` for more info.
+    ///
+    /// When the session lasted longer than `ConnectorParam::MAX_SESSION_DURATION_S`, the connector is closed. The peer is told about this if `ConnectorParam::SESSION_EXPIRED_REASON` is set.
+    ///
+    /// This does nothing while the connector is paused. A connector that failed to connect, see `NetworkState::Failed`, stops sending until `connect` is called again.
+    pub fn update(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        if self.is_paused() {
+            return Ok(());
+        }
+        if !self.closed && self.is_session_expired() {
+            match TParam::SESSION_EXPIRED_REASON {
+                Some(reason) => self.close(socket, Some(reason))?,
+                None => self.closed = true,
+            }
+        }
+        let now = self.now();
+        let mut expired = self
+            .send
+            .unconfirmed_message_cache
+            .iter()
+            .filter(|(_, packet)| packet.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        expired.sort_unstable();
+        for id in expired {
+            self.send.uncache(id);
+            self.push_event(Event::MessageExpired(id));
+        }
+
+        self.send_due_acks(socket)?;
+
+        self.refresh_state();
+        if let NetworkState::Disconnected | NetworkState::Failed = self.state() {
+            return Ok(());
+        }
+        self.release_held(socket)?;
+        let mut due = Vec::new();
+        if seconds_between(self.send.last_ping, now) > self.ping_interval_s() {
+            due.push(Due::Ping);
+        }
+        due.extend(self.select_requests(now).into_iter().map(Due::Request));
+        due.extend(self.select_retransmits().into_iter().map(Due::Retransmit));
+
+        let mut ping_sent = false;
+        for (index, packet) in due.iter().enumerate() {
+            let result = match *packet {
+                Due::Ping => self.send_ping(socket),
+                Due::Request(id) => {
+                    if !self.allow_control_packet() {
+                        continue;
+                    }
+                    self.send_packet(socket, &Packet::<TParam::TSend>::RequestPacket { id })
+                        .map(|_| ())
+                }
+                Due::Retransmit(id) => {
+                    let bytes = self.send.unconfirmed_message_cache[&id].bytes.clone();
+                    self.send_bytes(socket, &bytes)
+                }
+            };
+            match result {
+                // The send buffer of the socket is full. The timers of the packets that were not send are not reset, so they are send in the next update.
+                Err(ref e) if is_send_buffer_full(e) => {
+                    self.stats.deferred_sends += (due.len() - index) as u64;
+                    break;
+                }
+                result => result?,
+            }
+            match *packet {
+                Due::Ping => ping_sent = true,
+                Due::Request(id) => {
+                    if let Ok(index) = self.receive.missing_index(id) {
+                        self.receive.missing_message_id_list[index].last_request = now;
+                    }
+                }
+                Due::Retransmit(id) => {
+                    if let Some(packet) = self.send.unconfirmed_message_cache.get_mut(&id) {
+                        packet.last_emit = now;
+                    }
+                    self.record_confirmed_send(true);
+                }
+            }
+        }
+
+        // The idle offer is repeated with every ping, in case it was lost
+        if !self.is_quiet() {
+            self.idle.last_sent = None;
+        } else if ping_sent || self.idle.last_sent != Some(self.idle.peer_quiet) {
+            self.send_idle(socket)?;
+        }
+        self.refresh_idle();
+        // The new epoch is repeated with every ping, in case it was lost
+        if ping_sent && self.send.wrap_pending {
+            self.send_wrap(socket, self.send.epoch, false)?;
+        }
+        self.refresh_state();
+        Ok(())
+    }
+
+    /// Get the statistics of this connector
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Estimate how many packets are currently lost, as the ratio of retransmissions to all sends of confirmed messages in the last `ConnectorParam::LOSS_WINDOW_S` seconds. This is a value between 0 and 1, and 0 if nothing was send in that window.
+    ///
+    /// Only confirmed messages are taken into account, as there is no way to tell if an unconfirmed message was lost. The peer can report the loss of the messages of `send_unconfirmed_ordered` instead, see `Stats::stream_feedback`.
+    pub fn estimated_loss_rate(&self) -> f64 {
+        let now = self.now();
+        let (sends, retransmits) = self
+            .recent_sends
+            .iter()
+            .filter(|(moment, _)| seconds_between(*moment, now) <= TParam::LOSS_WINDOW_S)
+            .fold((0u64, 0u64), |(sends, retransmits), (_, retransmit)| {
+                (sends + 1, retransmits + u64::from(*retransmit))
+            });
+        if sends == 0 {
+            0.
+        } else {
+            retransmits as f64 / sends as f64
+        }
+    }
+
+    /// Let the connector know that the network is congested. For `ConnectorParam::CONGESTION_BACKOFF_S` seconds after this, unconfirmed packets are resend at half the rate, to make room on the network.
+    ///
+    /// This is called by `receive_from` when the socket reports a datagram with `Ecn::Ce`. Applications that receive datagrams themselves can call this when they get a congestion signal.
+    pub fn report_congestion(&mut self) {
+        self.congested_at = Some(self.now());
+        self.stats.congestion_signals += 1;
+    }
+
+    /// Returns `true` if congestion was reported less than `ConnectorParam::CONGESTION_BACKOFF_S` seconds ago
+    fn is_congested(&self) -> bool {
+        self.congested_at.is_some_and(|congested_at| {
+            seconds_between(congested_at, self.now()) <= TParam::CONGESTION_BACKOFF_S
+        })
+    }
+
+    /// Returns `true` if a control packet may be send without exceeding `ConnectorParam::MAX_AMPLIFICATION`, and counts it in the statistics. Control packets are the packets that are send in reaction to the peer: pongs, confirmations and requests, and replies to requests.
+    fn allow_control_packet(&mut self) -> bool {
+        if let Some(max) = TParam::MAX_AMPLIFICATION {
+            let allowance = (self.stats.received_datagrams + 1).saturating_mul(max);
+            if self.stats.control_packets_sent >= allowance {
+                self.stats.suppressed_control_packets += 1;
+                return false;
+            }
+        }
+        self.stats.control_packets_sent += 1;
+        true
+    }
+
+    /// Count a send of a confirmed message in the statistics
+    fn record_confirmed_send(&mut self, retransmit: bool) {
+        let now = self.now();
+        if retransmit {
+            self.stats.retransmits += 1;
+        } else {
+            self.stats.confirmed_sends += 1;
+        }
+        while let Some((moment, _)) = self.recent_sends.front() {
+            if seconds_between(*moment, now) <= TParam::LOSS_WINDOW_S {
+                break;
+            }
+            self.recent_sends.pop_front();
+        }
+        self.recent_sends.push_back((now, retransmit));
+    }
+
+    /// The amount of bytes it would take to request all missing messages from the peer right now, including `ConnectorParam::PADDING`. Together with `ConnectorParam::MAX_REQUESTS_PER_UPDATE` this gives an idea of how long it takes to recover from a big loss.
+    ///
+    /// This is purely diagnostic, and does not send anything.
+    pub fn missing_request_backlog_bytes(&self) -> usize {
+        let request = Packet::<TParam::TSend>::RequestPacket {
+            id: NonZeroU64::new(1).unwrap(),
+        };
+        // Every id takes the same amount of bytes, so the size of a single request is enough
+        let size = Self::encode(&request).map_or(0, |bytes| bytes.len());
+        let size = match TParam::PADDING {
+            Padding::None => size,
+            padding => padding.padded_size(size),
+        };
+        size * self.receive.missing_message_id_list.len()
+    }
+
+    /// Get the ids of the confirmed messages that will be resend on the next call to `update`, unless they are confirmed first. This takes `ConnectorParam::RETRANSMIT_BUDGET_BYTES` and the channel weights into account.
+    ///
+    /// This is purely diagnostic, and does not send anything.
+    pub fn pending_retransmits(&self) -> Vec<NonZeroU64> {
+        self.select_retransmits()
+    }
+
+    /// Iterate over the confirmed messages that were send but not confirmed by the peer yet, ordered by id. For every message this yields its id and how long ago it was last send.
+    ///
+    /// This is useful for monitoring, or for building a custom retry policy on top of this connector.
+    pub fn unconfirmed(&self) -> impl Iterator<Item = (NonZeroU64, Duration)> + '_ {
+        let now = self.now();
+        self.send
+            .unconfirmed_message_cache
+            .iter()
+            .map(move |(id, packet)| (*id, now.saturating_duration_since(packet.last_emit)))
+    }
+
+    /// The summed size in bytes of the confirmed messages that were send but not confirmed by the peer yet, see `ConnectorParam::MAX_UNCONFIRMED_BYTES`
+    pub fn unconfirmed_bytes(&self) -> usize {
+        self.send.unconfirmed_bytes
+    }
+
+    /// Select the ids of the unconfirmed packets that should be resend, oldest first.
+    ///
+    /// Packets are picked from the channels in a weighted round-robin fashion until `ConnectorParam::RETRANSMIT_BUDGET_BYTES` is used up.
+    fn select_retransmits(&self) -> Vec<NonZeroU64> {
+        let now = self.now();
+        let interval = if self.is_congested() {
+            self.timing.emit_unconfirmed_packet_interval_s * 2.
+        } else {
+            self.timing.emit_unconfirmed_packet_interval_s
+        };
+        let mut due = vec![Vec::new(); TParam::CHANNEL_WEIGHTS.len()];
+        for (id, packet) in self.send.unconfirmed_message_cache.iter() {
+            if !packet.held && seconds_between(packet.last_emit, now) > interval {
+                due[packet.channel].push((*id, packet.bytes.len()));
+            }
+        }
+        let mut channels = due
+            .into_iter()
+            .map(|mut packets| {
+                packets.sort_unstable_by_key(|(id, _)| *id);
+                packets.into_iter().peekable()
+            })
+            .collect::<Vec<_>>();
+
+        let mut budget = TParam::RETRANSMIT_BUDGET_BYTES;
+        let mut result = Vec::new();
+        let mut progressed = true;
+        while progressed {
+            progressed = false;
+            for (channel, weight) in channels.iter_mut().zip(TParam::CHANNEL_WEIGHTS) {
+                for _ in 0..(*weight).max(1) {
+                    match channel.peek() {
+                        Some((_, size)) if *size <= budget => {
+                            let (id, size) = channel.next().unwrap();
+                            budget -= size;
+                            result.push(id);
+                            progressed = true;
+                        }
+                        _ => break,
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Resolve an incoming ping or ping.
+    /// This will request all the messages up to this message, as well as set the last received time.
+    fn resolve_incoming_ping(&mut self, id: Option<NonZeroU64>) -> Result<()> {
+        self.receive.last_ping = self.now();
+        if let Some(last_send_message_id) = id.filter(|id| !self.is_previous_epoch(*id)) {
+            if self.exceeds_id_gap(last_send_message_id) {
+                return self.anomaly(Anomaly::IdGapTooLarge {
+                    id: last_send_message_id,
+                    last: self.receive.highest_received_id,
+                });
+            }
+            // Pings may arrive out of order, an older ping does not lower the id
+            let previous = self.receive.peer_last_sent_id;
+            self.receive.peer_last_sent_id = previous.max(Some(last_send_message_id));
+            self.request_message_up_to(last_send_message_id.get());
+            if let Some(previous) = previous.filter(|previous| *previous > last_send_message_id) {
+                self.anomaly(Anomaly::IdRegression {
+                    id: last_send_message_id,
+                    previous,
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deserialize the message of a compressed `Packet::Data`, after it was decompressed
+    fn decode_message(message: &[u8]) -> Result<TParam::TReceive> {
+        Ok(packet::deserialize(message, TParam::MAX_DESERIALIZE_BYTES)?)
+    }
+
+    /// Count a packet of the peer that does not make sense, and return it as an error if `ConnectorParam::STRICT` is enabled
+    fn anomaly(&mut self, anomaly: Anomaly) -> Result<()> {
+        let counter = match anomaly {
+            Anomaly::UnknownConfirmation { .. } => &mut self.stats.invalid_confirmations,
+            Anomaly::UnexpectedPacketNotFound { .. } => &mut self.stats.unexpected_not_founds,
+            Anomaly::UnmatchedPong { .. } => &mut self.stats.unmatched_pongs,
+            Anomaly::IdRegression { .. } => &mut self.stats.id_regressions,
+            Anomaly::IdGapTooLarge { .. } => &mut self.stats.ids_too_far_ahead,
+        };
+        *counter += 1;
+        if TParam::STRICT {
+            return Err(Error::Anomaly { anomaly }.into());
+        }
+        Ok(())
+    }
+
+    /// Handles incoming data. This will perform internal logic to make sure data is being transmitted correctly,
+    /// and requests missing packets.
+    ///
+    /// Any actual data that was received, will be returned from this function. A single datagram can contain multiple messages if the peer coalesced them with `flush`. Other things that happened, like acknowledgements and lost messages, are queued as events. See `drain_events` for more info.
+    pub fn handle_incoming_data(
+        &mut self,
+        socket: &mut dyn Socket,
+        data: &[u8],
+    ) -> Result<Vec<TParam::TReceive>> {
+        let messages = self.handle_datagram(socket, self.peer_addr, data, Self::decode_message)?;
+        Ok(self
+            .queue_messages(messages)
+            .into_iter()
+            .map(|received| received.message)
+            .collect())
+    }
+
+    /// Handles incoming data like `handle_incoming_data`, returning every message with its id. Only confirmed messages have an id, which can be passed to `acknowledge_processed`.
+    pub fn handle_incoming_data_with_ids(
+        &mut self,
+        socket: &mut dyn Socket,
+        data: &[u8],
+    ) -> Result<Vec<(Option<NonZeroU64>, TParam::TReceive)>> {
+        let messages = self.handle_datagram(socket, self.peer_addr, data, Self::decode_message)?;
+        Ok(self
+            .queue_messages(messages)
+            .into_iter()
+            .map(|received| (received.id, received.message))
+            .collect())
+    }
+
+    /// Handles incoming data like `handle_incoming_data`, as if it was received at the given moment. This is used to replay a `Transcript`, see `Transcript::replay`.
+    ///
+    /// The connector uses `now` as the current time while handling the data, and goes back to the system time afterwards.
+    pub fn handle_incoming_data_at(
+        &mut self,
+        socket: &mut dyn Socket,
+        data: &[u8],
+        now: Instant,
+    ) -> Result<Vec<TParam::TReceive>> {
+        let clock = std::mem::replace(&mut self.clock, Clock::Manual(now));
+        let result = self.handle_incoming_data(socket, data);
+        self.clock = clock;
+        result
+    }
+
+    /// Start capturing every datagram that is send and received by this connector, with a timestamp. Use `take_capture` to get the captured datagrams as a `Transcript`. A capture that is already running is discarded.
+    ///
+    /// This is intended to reproduce bugs: the transcript can be written to a file, and replayed into a fresh connector with `Transcript::replay`.
+    pub fn start_capture(&mut self) {
+        self.capture = Some((self.now(), Transcript::default()));
+    }
+
+    /// Stop the capture that was started with `start_capture`, and return everything that was captured. Returns `None` if no capture was running.
+    pub fn take_capture(&mut self) -> Option<Transcript> {
+        self.capture.take().map(|(_, transcript)| transcript)
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) {
+        let now = self.now();
+        if let Some((start, transcript)) = self.capture.as_mut() {
+            transcript.entries.push(TranscriptEntry {
+                direction,
+                elapsed: now.saturating_duration_since(*start),
+                data: data.to_vec(),
+            });
+        }
+    }
+
+    /// Handles incoming data like `handle_incoming_data`, but deserializes the message as a `T` that can borrow from `data`. This avoids allocating e.g. a `String` for every received message.
+    ///
+    /// `T` must have the exact same serialized format as `ConnectorParam::TReceive`. Usually this is a copy of `TReceive` where owned fields are replaced by their borrowed counterparts, e.g. `String` by `&'a str` and `Vec<u8>` by `&'a [u8]`.
+    ///
+    /// The returned message borrows from `data`, so the buffer can not be reused until the message is dropped. This is why `receive_from` can not be used for this; you need to receive the data from the socket yourself.
+    ///
+    /// ```rust
+    /// # #[macro_use]
+    /// # extern crate serde_derive;
+    /// # extern crate serde;
+    /// #[derive(Serialize, Deserialize)]
+    /// pub enum ClientToServer {
+    ///     Chat { text: String },
+    /// }
+    ///
+    /// // Can be passed to `handle_incoming_data_borrowed` for a connector with `TReceive = ClientToServer`
+    /// #[derive(Deserialize)]
+    /// pub enum BorrowedClientToServer<'a> {
+    ///     Chat { text: &'a str },
+    /// }
+    /// # fn main() {}
+    /// ```
+    pub fn handle_incoming_data_borrowed<'a, T: serde::Deserialize<'a>>(
+        &mut self,
+        socket: &mut dyn Socket,
+        data: &'a [u8],
+    ) -> Result<Vec<T>> {
+        let messages = self.handle_datagram(socket, self.peer_addr, data, |_| {
+            Err(Error::CompressedMessageBorrowed.into())
+        })?;
+        Ok(messages
+            .into_iter()
+            .map(|received| received.message)
+            .collect())
+    }
+
+    /// Handle a single datagram that came from `from`, returning every message in it with its reliability. `decode` reads the message of a compressed `Packet::Data`, see `packet::deserialize_packet`.
+    fn handle_datagram<'a, T: serde::Deserialize<'a>>(
+        &mut self,
+        socket: &mut dyn Socket,
+        from: SocketAddr,
+        data: &'a [u8],
+        decode: fn(&[u8]) -> Result<T>,
+    ) -> Result<Vec<Received<T>>> {
+        // A closed connector ignores the peer, until it connects again
+        if self.closed {
+            self.stats.closed_drops += 1;
+            return Ok(Vec::new());
+        }
+        self.record(Direction::Received, data);
+        self.stats.received_datagrams += 1;
+        let packets = match TParam::PADDING {
+            Padding::None => data,
+            _ => packet::strip_padding(data)
+                .map_err(|e| self.report_malformed(from, data, e.into()))?,
+        };
+        // The peer must not send these unless both sides enabled them
+        if !TParam::CHECKSUMMED_BATCHES && packet::is_checked_batch(packets) {
+            let e = bincode::ErrorKind::Custom("Checked batches are not enabled".to_owned());
+            return Err(self.report_malformed(from, packets, Box::new(e).into()));
+        }
+        let mut result = Vec::new();
+        for (index, data) in packet::split_datagram(packets).enumerate() {
+            if TParam::IGNORE_UNKNOWN_PACKETS
+                && data.as_ref().is_ok_and(|data| packet::is_unknown(data))
+            {
+                self.stats.unknown_packets += 1;
+                continue;
+            }
+            // A batch that can not be split any further is reported as a whole
+            let bytes = *data.as_ref().unwrap_or(&packets);
+            let packet = match data
+                .map_err(Into::into)
+                .and_then(|data| self.read_packet(data, decode))
+            {
+                Ok(packet) => {
+                    packet::count_type(&mut self.stats.received_packet_types, bytes);
+                    packet
+                }
+                Err(e) => {
+                    let e = self.report_malformed(from, bytes, e);
+                    // The other packets of a checked batch are not affected
+                    if TParam::CHECKSUMMED_BATCHES && packet::is_checked_batch(packets) {
+                        self.stats.corrupt_batch_entries += 1;
+                        continue;
+                    }
+                    // The packets before it are already handled, and may have been confirmed to the peer, so they are kept
+                    if index > 0 {
+                        self.stats.malformed_batches += 1;
+                        break;
+                    }
+                    return Err(e);
+                }
+            };
+            // The packets after a `Packet::Disconnect` in the same datagram
+            if self.closed {
+                self.stats.closed_drops += 1;
+                continue;
+            }
+            match self.handle_packet(socket, packet) {
+                Ok(received) => result.extend(received),
+                Err(e) => {
+                    let e = self.report_malformed(from, bytes, e);
+                    // Like a malformed packet, the packets before it are kept and the packets after it are dropped
+                    if index > 0 {
+                        self.stats.malformed_batches += 1;
+                        break;
+                    }
+                    return Err(e);
+                }
+            }
+            if TParam::ANY_PACKET_IS_PING {
+                self.receive.last_ping = self.now();
+            }
+        }
+        self.send_due_acks(socket)?;
+        Ok(result)
+    }
+
+    /// Returns `true` if the datagram contains the handshake of the peer, see `packet::is_handshake`
+    pub(crate) fn is_handshake(data: &[u8]) -> bool {
+        let packets = match TParam::PADDING {
+            Padding::None => Ok(data),
+            _ => packet::strip_padding(data),
+        };
+        packets.is_ok_and(packet::is_handshake)
+    }
+
+    /// Queue an `Event::MalformedPacket` with the bytes that could not be read, and return the error
+    fn report_malformed(
+        &mut self,
+        from: SocketAddr,
+        data: &[u8],
+        error: failure::Error,
+    ) -> failure::Error {
+        self.push_event(Event::MalformedPacket {
+            from,
+            data: data.to_vec(),
+            error: error.to_string(),
+        });
+        error
+    }
+
+    /// Deserialize a single packet of a datagram, with `decode` reading the message of a compressed `Packet::Data`. Compact message ids are expanded based on the ids that were send and received so far, see `ConnectorParam::COMPACT_MESSAGE_IDS`.
+    fn read_packet<'a, T: serde::Deserialize<'a>>(
+        &self,
+        data: &'a [u8],
+        decode: fn(&[u8]) -> Result<T>,
+    ) -> Result<Packet<T>> {
+        if !TParam::RECEIVES_MESSAGES && packet::is_data(data) {
+            return Err(Error::ReceiveNotSupported.into());
+        }
+        let limit = TParam::MAX_DESERIALIZE_BYTES;
+        if TParam::COMPACT_MESSAGE_IDS {
+            packet::deserialize_packet::<T, u32>(data, limit, decode).and_then(|packet| {
+                let own = self.send.next_message_id.map_or(1, NonZeroU64::get);
+                let peer = self.receive.last_message_id.map_or(1, NonZeroU64::get);
+                Ok(packet.expand(own, peer)?)
+            })
+        } else {
+            packet::deserialize_packet(data, limit, decode)
+        }
+    }
+
+    fn handle_packet<T>(
+        &mut self,
+        socket: &mut dyn Socket,
+        packet: Packet<T>,
+    ) -> Result<Option<Received<T>>> {
+        let result = match packet {
+            Packet::Ping {
+                last_send_message_id,
+                nonce,
+            } => {
+                self.resolve_incoming_ping(last_send_message_id)?;
+                if self.allow_control_packet() {
+                    self.send_with_acks(
+                        socket,
+                        &Packet::<TParam::TSend>::Pong {
+                            last_send_message_id: self.send.last_message_id(),
+                            nonce,
+                        },
+                    )?;
+                }
+                None
+            }
+            // A held packet was never send, it is send when the connection is established
+            Packet::RequestPacket { id }
+                if self
+                    .send
+                    .unconfirmed_message_cache
+                    .get(&id)
+                    .is_some_and(|packet| packet.held) =>
+            {
+                None
+            }
+            Packet::RequestPacket { .. } if !self.allow_control_packet() => None,
+            Packet::RequestPacket { id } => {
+                let now = self.now();
+                if let Some(packet) = self.send.unconfirmed_message_cache.get_mut(&id) {
+                    packet.last_emit = now;
+                    let bytes = packet.bytes.clone();
+                    self.send_bytes(socket, &bytes)?;
+                    self.record_confirmed_send(true);
+                } else if self.send.recently_confirmed.contains(&id) {
+                    self.send_packet(socket, &Packet::<TParam::TSend>::AlreadyDelivered { id })?;
+                } else {
+                    self.send_packet(socket, &Packet::<TParam::TSend>::PacketNotFound { id })?;
+                }
+                None
+            }
+            Packet::ConfirmPacket { id }
+            | Packet::ConfirmUpTo { id }
+            | Packet::Processed { id }
+                if !self.was_sent(id) =>
+            {
+                self.anomaly(Anomaly::UnknownConfirmation { id })?;
+                None
+            }
+            Packet::ConfirmPacket { id } => {
+                if self.send.uncache(id) {
+                    self.send.remember_confirmed(id);
+                    self.push_event(Event::Acknowledged(id));
+                }
+                None
+            }
+            Packet::ConfirmUpTo { id } => {
+                for id in self.send.uncache_up_to(id) {
+                    self.send.remember_confirmed(id);
+                    self.push_event(Event::Acknowledged(id));
+                }
+                None
+            }
+            Packet::Processed { id } => {
+                // The confirmation may have been lost, but the message clearly arrived
+                if self.send.uncache(id) {
+                    self.send.remember_confirmed(id);
+                    self.push_event(Event::Acknowledged(id));
+                }
+                self.push_event(Event::Processed(id));
+                None
+            }
+            Packet::Time { nonce, time_us } => {
+                self.send.peer_time = Some((nonce, time_us));
+                None
+            }
+            // The peer called `connect`, so its message ids start at 1 again
+            Packet::Hello { session } => {
+                if self.receive.peer_session != Some(session) {
+                    self.receive.reset(self.now());
+                    self.receive.peer_session = Some(session);
+                }
+                None
+            }
+            Packet::SequenceAck {
+                stream,
+                sequence,
+                received,
+            } => {
+                // Only a stream we send on, so the peer can not make up streams to grow the statistics
+                if self
+                    .next_sequences
+                    .get(&stream)
+                    .is_some_and(|next| sequence < *next)
+                {
+                    self.stats
+                        .stream_feedback
+                        .entry(stream)
+                        .or_default()
+                        .report(sequence, received);
+                }
+                None
+            }
+            Packet::Capabilities {
+                flags,
+                acknowledged,
+            } => {
+                self.receive.peer_capabilities = Some(flags);
+                self.receive.capabilities_acknowledged |= acknowledged;
+                if TParam::NEGOTIATE_CUMULATIVE_ACKS && !acknowledged {
+                    self.send_capabilities(socket)?;
+                }
+                None
+            }
+            // The message arrived after all, we only have to stop requesting it
+            Packet::AlreadyDelivered { id } => {
+                self.receive.remove_missing(id);
+                None
+            }
+            Packet::PacketNotFound { id } => {
+                if self.receive.remove_missing(id) {
+                    self.push_event(Event::MessageLost(id));
+                } else {
+                    self.anomaly(Anomaly::UnexpectedPacketNotFound { id })?;
+                }
+                None
+            }
+            Packet::Pong {
                 last_send_message_id,
+                nonce,
             } => {
-                self.resolve_incoming_ping(last_send_message_id);
-                send_packet_to::<TParam::TSend>(
-                    self.peer_addr,
-                    socket,
-                    &Packet::Pong {
-                        last_send_message_id: self.send.next_message_id,
-                    },
-                )?;
+                // A pong that does not answer a recent ping is stray or replayed, and says nothing about the connection
+                if let Some(send) = self.take_outstanding_ping(nonce) {
+                    let rtt = self.now().saturating_duration_since(send);
+                    self.send.last_rtt = Some(rtt);
+                    if let Some((_, time_us)) =
+                        self.send.peer_time.take().filter(|(n, _)| *n == nonce)
+                    {
+                        self.add_clock_sample(rtt, time_us);
+                    }
+                    self.resolve_incoming_ping(last_send_message_id)?;
+                    if self.handshake_started.take().is_some() {
+                        self.resend_latest_unconfirmed(socket)?;
+                        self.release_held(socket)?;
+                    }
+                } else {
+                    self.anomaly(Anomaly::UnmatchedPong { nonce })?;
+                }
                 None
             }
-            Packet::RequestPacket { id } => {
-                if let Some(packet) = self.send.unconfirmed_message_cache.get_mut(&id) {
-                    packet.last_emit = Instant::now();
-                    send_packet_to(self.peer_addr, socket, &packet.packet)?;
-                } else {
-                    send_packet_to::<TParam::TSend>(
-                        self.peer_addr,
-                        socket,
-                        &Packet::PacketNotFound { id },
-                    )?;
+            // A message that was send before the peer wrapped its ids arrived late
+            Packet::Data {
+                message_id: Some(message_id),
+                ..
+            } if self.is_previous_epoch(message_id) => None,
+            // Every id before it would be tracked as missing
+            Packet::Data {
+                message_id: Some(message_id),
+                ..
+            } if self.exceeds_id_gap(message_id)
+                || self.missing_ids_before(message_id) > self.missing_allowance() =>
+            {
+                self.anomaly(Anomaly::IdGapTooLarge {
+                    id: message_id,
+                    last: self.receive.highest_received_id,
+                })?;
+                None
+            }
+            // Our confirmation was lost, so the peer resend the message
+            Packet::Data {
+                message_id: Some(message_id),
+                ..
+            } if self.was_received(message_id) => {
+                self.wake(true);
+                self.stats.duplicate_messages += 1;
+                self.confirm(socket, message_id)?;
+                None
+            }
+            Packet::Data {
+                message_id, data, ..
+            } => {
+                self.wake(true);
+                if let Some(message_id) = message_id {
+                    self.request_message_up_to(message_id.get() - 1);
+                    self.receive.remove_missing(message_id);
+                    if self.receive.last_message_id < Some(message_id) {
+                        self.receive.last_message_id = Some(message_id);
+                    }
+                    if self.receive.highest_received_id < Some(message_id) {
+                        self.receive.highest_received_id = Some(message_id);
+                    }
+                    self.confirm(socket, message_id)?;
+                }
+                let reliability = match message_id {
+                    Some(_) => Reliability::Confirmed,
+                    None => Reliability::Unconfirmed,
+                };
+                Some(Received {
+                    reliability,
+                    id: message_id,
+                    message: data,
+                })
+            }
+            // The newest sequence of every stream is kept until the peer connects again
+            Packet::OrderedData { stream, .. }
+                if self.receive.latest_sequences.len() >= TParam::MAX_STREAMS
+                    && !self.receive.latest_sequences.contains_key(&stream) =>
+            {
+                self.stats.stream_limit_drops += 1;
+                None
+            }
+            Packet::OrderedData {
+                stream,
+                sequence,
+                data,
+            } => {
+                self.wake(true);
+                *self.receive.stream_received.entry(stream).or_insert(0) += 1;
+                match self.receive.latest_sequences.get(&stream) {
+                    Some(latest) if *latest >= sequence => {
+                        self.stats.out_of_order_drops += 1;
+                        None
+                    }
+                    _ => {
+                        self.receive.latest_sequences.insert(stream, sequence);
+                        Some(Received {
+                            reliability: Reliability::UnconfirmedOrdered,
+                            id: None,
+                            message: data,
+                        })
+                    }
+                }
+            }
+            Packet::Idle { acknowledged } => {
+                self.idle.peer_quiet = true;
+                self.idle.peer_acknowledged = acknowledged;
+                // Let the peer know right away that we expect its pings less often, so it can go idle
+                if self.is_quiet()
+                    && self.idle.last_sent != Some(true)
+                    && self.allow_control_packet()
+                {
+                    self.send_idle(socket)?;
                 }
+                self.refresh_idle();
                 None
             }
-            Packet::ConfirmPacket { id } => {
-                self.send.unconfirmed_message_cache.remove(&id);
+            Packet::Wrap {
+                epoch,
+                acknowledged: false,
+            } => {
+                // The peer only wraps its ids after we confirmed all its messages, so there is nothing left to track
+                if epoch != self.receive.epoch {
+                    self.receive.epoch = epoch;
+                    self.receive.last_message_id = None;
+                    self.receive.highest_received_id = None;
+                    self.receive.peer_last_sent_id = None;
+                    self.receive.missing_message_id_list.clear();
+                    self.receive.pending_acks.clear();
+                    self.receive.pending_acks_since = None;
+                }
+                if self.allow_control_packet() {
+                    self.send_wrap(socket, epoch, true)?;
+                }
                 None
             }
-            Packet::PacketNotFound { id } => {
-                self.receive.missing_message_id_list.retain(|i| i.id != id);
+            Packet::Wrap {
+                epoch,
+                acknowledged: true,
+            } => {
+                if epoch == self.send.epoch {
+                    self.send.wrap_pending = false;
+                }
                 None
             }
-            Packet::Pong {
-                last_send_message_id,
-            } => {
-                self.resolve_incoming_ping(last_send_message_id);
+            Packet::Disconnect { reason } => {
+                self.closed = true;
+                self.push_event(Event::Disconnected { reason });
                 None
             }
-            Packet::Data { message_id, data } => {
-                if let Some(message_id) = message_id {
-                    self.request_message_up_to(message_id.get() - 1);
-                    send_packet_to::<TParam::TSend>(
-                        self.peer_addr,
-                        socket,
-                        &Packet::ConfirmPacket { id: message_id },
-                    )?;
-                }
-                self.receive.last_message_id = message_id;
-                Some(data)
+            // Batches are split up by `handle_incoming_data`, a batch in a batch is never send
+            Packet::Batch { .. } | Packet::CheckedBatch { .. } => None,
+        };
+        self.refresh_state();
+        Ok(result)
+    }
+
+    /// Confirm a received message to the peer, right away, after `ConnectorParam::ACK_DELAY_S`, or with the next ping or pong, see `ConnectorParam::ACKS_IN_PINGS`
+    fn confirm(&mut self, socket: &mut dyn Socket, id: NonZeroU64) -> Result<()> {
+        if self.acks_in_pings() {
+            self.receive.pending_acks.push(id);
+        } else if self.timing.ack_delay_s > 0. {
+            let now = self.now();
+            self.receive.pending_acks.push(id);
+            self.receive.pending_acks_since.get_or_insert(now);
+        } else if TParam::NEGOTIATE_CUMULATIVE_ACKS && self.cumulative_acks() {
+            // Confirmed once the whole datagram is handled, so a contiguous run in it is confirmed at once
+            let now = self.now();
+            self.receive.pending_acks.push(id);
+            self.receive.pending_acks_since.get_or_insert(now);
+        } else if self.allow_control_packet() {
+            let confirm = self.confirmation_for(id);
+            self.send_packet(socket, &confirm)?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the confirmed message with the given id was already received since the last call to `connect`. Without `ConnectorParam::ENABLE_MISSING_REQUESTS` the missing messages are not tracked, so this is always `false`.
+    fn was_received(&self, id: NonZeroU64) -> bool {
+        TParam::ENABLE_MISSING_REQUESTS
+            && self.receive.last_message_id >= Some(id)
+            && self.receive.missing_index(id).is_err()
+    }
+
+    /// Returns `true` if the message id of the peer can only belong to the previous epoch. After the peer wrapped its ids, the new ids are far below the ids of the previous epoch. See `ConnectorParam::WRAP_MESSAGE_IDS`.
+    fn is_previous_epoch(&self, id: NonZeroU64) -> bool {
+        let last = self.receive.last_message_id.map_or(0, NonZeroU64::get);
+        self.receive.epoch != 0 && id.get().saturating_sub(last) > u64::MAX / 2
+    }
+
+    /// Returns `true` if the message id of the peer is more than `ConnectorParam::MAX_ID_GAP` ahead of the highest message that arrived. The ids that pings told us about do not count, or a peer could step its pings forward and grow the gap without bounds.
+    fn exceeds_id_gap(&self, id: NonZeroU64) -> bool {
+        let last = self.receive.highest_received_id.map_or(0, NonZeroU64::get);
+        TParam::MAX_ID_GAP.is_some_and(|gap| id.get().saturating_sub(last) > gap)
+    }
+
+    /// The amount of ids before the given message id that are not tracked yet, and would be tracked as missing when it arrives
+    fn missing_ids_before(&self, id: NonZeroU64) -> u64 {
+        let last = self.receive.last_message_id.map_or(0, NonZeroU64::get);
+        (id.get() - 1).saturating_sub(last)
+    }
+
+    /// The amount of ids that can still be tracked as missing without exceeding `ConnectorParam::MAX_AMPLIFICATION`. Every missing id is requested at least once, so tracking more ids than requests are allowed only grows the list.
+    fn missing_allowance(&self) -> u64 {
+        match TParam::MAX_AMPLIFICATION {
+            Some(max) if TParam::ENABLE_MISSING_REQUESTS => (self.stats.received_datagrams + 1)
+                .saturating_mul(max)
+                .saturating_sub(self.stats.control_packets_sent)
+                .saturating_sub(self.receive.missing_message_id_list.len() as u64),
+            _ => u64::MAX,
+        }
+    }
+
+    /// Returns `true` if a confirmed message with the given id was send since the last call to `connect`
+    fn was_sent(&self, id: NonZeroU64) -> bool {
+        self.send.last_message_id().is_some_and(|last| id <= last)
+    }
+
+    /// Returns `true` if received messages are confirmed together with all messages before them, see `ConnectorParam::CUMULATIVE_ACKS` and `ConnectorParam::NEGOTIATE_CUMULATIVE_ACKS`
+    fn cumulative_acks(&self) -> bool {
+        let negotiated = TParam::NEGOTIATE_CUMULATIVE_ACKS
+            && self
+                .receive
+                .peer_capabilities
+                .is_some_and(|flags| flags & CAPABILITY_CUMULATIVE_ACKS != 0);
+        // Without missing requests, messages that never arrived are not tracked as missing
+        TParam::ENABLE_MISSING_REQUESTS && (TParam::CUMULATIVE_ACKS || negotiated)
+    }
+
+    /// The packet that confirms the message with the given id. With cumulative confirmations this confirms every message up to the first missing message at once, see `cumulative_acks`.
+    fn confirmation_for(&self, id: NonZeroU64) -> Packet<TParam::TSend> {
+        if self.cumulative_acks() {
+            if let Some(up_to) = self.confirmed_up_to().filter(|up_to| *up_to >= id) {
+                return Packet::ConfirmUpTo { id: up_to };
             }
-        })
+        }
+        Packet::ConfirmPacket { id }
+    }
+
+    /// The id up to which all messages of the peer were received, the id before the first missing message
+    fn confirmed_up_to(&self) -> Option<NonZeroU64> {
+        match self
+            .receive
+            .missing_message_id_list
+            .first()
+            .map(|missing| missing.id)
+        {
+            Some(first_missing) => NonZeroU64::new(first_missing.get() - 1),
+            None => self.receive.last_message_id,
+        }
+    }
+
+    /// Send the pending confirmations if the first of them waited for `ConnectorParam::ACK_DELAY_S`
+    fn send_due_acks(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        let now = self.now();
+        if self
+            .receive
+            .pending_acks_since
+            .is_some_and(|since| seconds_between(since, now) >= self.timing.ack_delay_s)
+        {
+            self.send_pending_acks(socket)?;
+        }
+        Ok(())
+    }
+
+    /// Confirm the messages that were received during the last `ConnectorParam::ACK_DELAY_S`. The confirmations are packed into as few datagrams as possible.
+    fn send_pending_acks(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        self.receive.pending_acks_since = None;
+        let mut packets = Vec::new();
+        let mut confirmed_up_to = None;
+        for id in std::mem::take(&mut self.receive.pending_acks) {
+            if confirmed_up_to >= Some(id) {
+                continue;
+            }
+            let confirm = self.confirmation_for(id);
+            if let Packet::ConfirmUpTo { id } = confirm {
+                confirmed_up_to = Some(id);
+            }
+            if self.allow_control_packet() {
+                packets.push(Self::encode(&confirm)?);
+            }
+        }
+        self.send_packed(socket, packets)
+    }
+
+    /// Resend the latest unconfirmed message per key, so the peer is up to date right after reconnecting. See `ConnectorParam::RESEND_LATEST_UNCONFIRMED_ON_RECONNECT`.
+    fn resend_latest_unconfirmed(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        let latest = std::mem::take(&mut self.latest_unconfirmed);
+        let result = latest
+            .values()
+            .try_for_each(|bytes| self.send_bytes(socket, bytes));
+        self.latest_unconfirmed = latest;
+        result
     }
 
     fn send_ping(&mut self, socket: &mut dyn Socket) -> Result<()> {
-        self.send.last_ping = Instant::now();
-        send_packet_to::<TParam::TSend>(
-            self.peer_addr,
+        let now = self.now();
+        self.send_ping_packet(socket)?;
+        self.send.last_ping = now;
+        if TParam::NEGOTIATE_CUMULATIVE_ACKS && !self.receive.capabilities_acknowledged {
+            self.send_capabilities(socket)?;
+        }
+        Ok(())
+    }
+
+    /// Announce our capabilities to the peer, in a datagram of its own so a peer that does not understand it only drops the announcement. See `ConnectorParam::NEGOTIATE_CUMULATIVE_ACKS`.
+    fn send_capabilities(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        let capabilities = Packet::<TParam::TSend>::Capabilities {
+            flags: CAPABILITY_CUMULATIVE_ACKS,
+            acknowledged: self.receive.peer_capabilities.is_some(),
+        };
+        self.send_packet(socket, &capabilities)?;
+        Ok(())
+    }
+
+    /// Send a ping that can be answered by the peer, without changing when the next regular ping is send
+    fn send_ping_packet(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        let now = self.now();
+        let nonce = self.next_ping_nonce;
+        self.next_ping_nonce = nonce.wrapping_add(1);
+        self.forget_expired_pings();
+        self.send.outstanding_pings.push((nonce, now));
+        let last_send_message_id = self.send.last_message_id();
+        self.send_with_acks(
             socket,
-            &Packet::Ping {
-                last_send_message_id: self
-                    .send
-                    .next_message_id
-                    .map(|id| unsafe { NonZeroU64::new_unchecked(id.get() - 1) }),
+            &Packet::<TParam::TSend>::Ping {
+                last_send_message_id,
+                nonce,
             },
         )
     }
 
+    /// Send a ping or pong, in the same datagram as the confirmation of the messages that were received since the last ping or pong, see `ConnectorParam::ACKS_IN_PINGS`. A ping is preceded by our session until the handshake completed, see `Packet::Hello`. A pong is preceded by our time, see `ConnectorParam::CLOCK_SYNC`, and followed by the received sequences, see `ConnectorParam::SEQUENCE_ACKS`.
+    fn send_with_acks(
+        &mut self,
+        socket: &mut dyn Socket,
+        packet: &Packet<TParam::TSend>,
+    ) -> Result<()> {
+        let mut packets = Vec::new();
+        if let (Packet::Ping { .. }, Some(_)) = (packet, self.handshake_started) {
+            let hello = Packet::<TParam::TSend>::Hello {
+                session: self.send.session,
+            };
+            packets.push(Self::encode(&hello)?);
+        }
+        if let (Packet::Pong { nonce, .. }, true) = (packet, TParam::CLOCK_SYNC) {
+            let time_us = unix_time().as_micros() as u64;
+            let time = Packet::<TParam::TSend>::Time {
+                nonce: *nonce,
+                time_us,
+            };
+            packets.push(Self::encode(&time)?);
+        }
+        packets.push(Self::encode(packet)?);
+        if let (Packet::Pong { .. }, true) = (packet, TParam::SEQUENCE_ACKS) {
+            let latest = &self.receive.latest_sequences;
+            let first = self.receive.next_sequence_ack_stream;
+            let streams = latest
+                .range(first..)
+                .chain(latest.range(..first))
+                .map(|(stream, sequence)| (*stream, *sequence))
+                .take(TParam::MAX_SEQUENCE_ACKS)
+                .collect::<Vec<_>>();
+            for (stream, sequence) in streams {
+                if !self.allow_control_packet() {
+                    break;
+                }
+                let sequence_ack = Packet::<TParam::TSend>::SequenceAck {
+                    stream,
+                    sequence,
+                    received: self.receive.stream_received[&stream],
+                };
+                packets.push(Self::encode(&sequence_ack)?);
+                self.receive.next_sequence_ack_stream = stream.wrapping_add(1);
+            }
+        }
+        if !self.receive.pending_acks.is_empty() && self.acks_in_pings() {
+            self.receive.pending_acks.clear();
+            // Messages after a missing message are confirmed once the missing message arrives
+            if let Some(id) = self.confirmed_up_to() {
+                packets.push(Self::encode(&Packet::<TParam::TSend>::ConfirmUpTo { id })?);
+            }
+        }
+        self.send_packed(socket, packets)
+    }
+
+    /// Measure the clock offset of the peer from its time that arrived with a pong, see `estimated_clock_offset`
+    fn add_clock_sample(&mut self, rtt: Duration, time_us: u64) {
+        let peer = Duration::from_micros(time_us).as_secs_f64();
+        // The peer read its clock about half a round trip ago
+        let local = unix_time().as_secs_f64() - rtt.as_secs_f64() / 2.;
+        let samples = &mut self.send.clock_samples;
+        if samples.len() >= CLOCK_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back((rtt, peer - local));
+    }
+
+    /// Returns `true` if received messages are confirmed together with pings and pongs, see `ConnectorParam::ACKS_IN_PINGS`
+    fn acks_in_pings(&self) -> bool {
+        TParam::ACKS_IN_PINGS && TParam::ENABLE_MISSING_REQUESTS
+    }
+
+    /// Forget the pings that were send longer than `ConnectorParam::RECEIVE_PING_TIMEOUT_S` ago. A pong that arrives this late does not prove the connection is alive.
+    fn forget_expired_pings(&mut self) {
+        let now = self.now();
+        let timeout = self.timing.receive_ping_timeout_s;
+        self.send
+            .outstanding_pings
+            .retain(|(_, send)| seconds_between(*send, now) <= timeout);
+    }
+
+    /// Returns when the ping was send, if the nonce belongs to a recently send ping that was not answered yet. The ping is then considered answered.
+    fn take_outstanding_ping(&mut self, nonce: u32) -> Option<Instant> {
+        self.forget_expired_pings();
+        let outstanding = &mut self.send.outstanding_pings;
+        let index = outstanding.iter().position(|(n, _)| *n == nonce)?;
+        Some(outstanding.remove(index).1)
+    }
+
     fn request_message_up_to(&mut self, id: u64) {
-        let mut start = if let Some(id) = self.receive.last_message_id {
-            id
-        } else {
-            unsafe { NonZeroU64::new_unchecked(1) }
+        let start = match self.receive.last_message_id {
+            Some(last) if last.get() >= id => return,
+            // `last` is smaller than `id`, so this can not overflow
+            Some(last) => last.get() + 1,
+            None => 1,
         };
-        while start.get() <= id {
-            if self
-                .receive
-                .missing_message_id_list
-                .iter()
-                .find(|id| id.id == start)
-                .is_none()
-            {
-                self.receive
-                    .missing_message_id_list
-                    .push(MissingId::new(start));
-            }
-            start = unsafe { NonZeroU64::new_unchecked(start.get() + 1) };
+        // The ids beyond the allowance are tracked when a later datagram of the peer raises it
+        let id = id.min((start - 1).saturating_add(self.missing_allowance()));
+        if id < start {
+            return;
+        }
+        // Every missing id is at most `last_message_id`, so the new ids are not in the list yet and keep it sorted
+        if TParam::ENABLE_MISSING_REQUESTS {
+            let now = self.now();
+            self.receive.missing_message_id_list.extend(
+                (start..=id)
+                    .filter_map(NonZeroU64::new)
+                    .map(|missing| MissingId::new(missing, now)),
+            );
         }
         self.receive.last_message_id = NonZeroU64::new(id);
     }
@@ -380,56 +2590,438 @@ impl<TParam: ConnectorParam> Connector<TParam> {
     /// Send an unconfirmed message to the other connector. It is not guaranteed that this message will ever arrive.
     ///
     /// This is useful for data that does not have to arrive. Think of things like player movements, frames of a lossy video stream, etc.
+    ///
+    /// If `ConnectorParam::BUFFER_SENDS` is enabled, the message is not send until `flush` is called. While the connector is disconnected this returns `Error::NotConnected`, see `state`.
     pub fn send_unconfirmed<T: Into<TParam::TSend>>(
         &mut self,
         socket: &mut dyn Socket,
         msg: T,
     ) -> Result<()> {
-        send_packet_to(
-            self.peer_addr,
-            socket,
-            &Packet::Data {
-                data: msg.into(),
-                message_id: None,
-            },
-        )?;
+        self.send_unconfirmed_inner(socket, None, msg.into())
+    }
+
+    /// Send an unconfirmed message to the other connector, where only the latest message with the same `key` matters. This is useful for replicating state, e.g. the position of each player, where the key identifies the player.
+    ///
+    /// If `ConnectorParam::BUFFER_SENDS` is enabled, this replaces a message with the same key that is still waiting for `flush`, so only the newest value is send. Otherwise this behaves the same as `send_unconfirmed`.
+    pub fn send_unconfirmed_keyed<T: Into<TParam::TSend>>(
+        &mut self,
+        socket: &mut dyn Socket,
+        key: u32,
+        msg: T,
+    ) -> Result<()> {
+        self.send_unconfirmed_inner(socket, Some(key), msg.into())
+    }
+
+    /// Send an unconfirmed message on the given stream. Like `send_unconfirmed` it is not guaranteed that this message will arrive, but the peer also drops this message if a newer message on the same stream arrived before it. This is useful for data that must be handled in order, but where a late message is useless, like frames of an audio stream.
+    ///
+    /// Every stream has its own order, so a stream can be used for every kind of data. The peer receives these messages with `Reliability::UnconfirmedOrdered`.
+    pub fn send_unconfirmed_ordered<T: Into<TParam::TSend>>(
+        &mut self,
+        socket: &mut dyn Socket,
+        stream: u32,
+        msg: T,
+    ) -> Result<()> {
+        self.ensure_connected()?;
+        let sequence = self.next_sequences.entry(stream).or_insert(0);
+        let packet = Packet::OrderedData {
+            stream,
+            sequence: *sequence,
+            data: msg.into(),
+        };
+        *sequence += 1;
+        self.send_or_buffer(socket, None, &packet)?;
+        Ok(())
+    }
+
+    fn send_unconfirmed_inner(
+        &mut self,
+        socket: &mut dyn Socket,
+        key: Option<u32>,
+        msg: TParam::TSend,
+    ) -> Result<()> {
+        self.ensure_connected()?;
+        let packet = Packet::Data {
+            data: msg,
+            compressed: false,
+            message_id: None,
+        };
+        if TParam::RESEND_LATEST_UNCONFIRMED_ON_RECONNECT {
+            self.latest_unconfirmed.insert(key, Self::encode(&packet)?);
+        }
+        self.send_or_buffer(socket, key, &packet)?;
         Ok(())
     }
 
     /// Send a confirmed message to the other connector. The connector will try to make sure this message arrives. It is not guaranteed that messages will arrive in the same order at the other side.
+    ///
+    /// Returns the id of the message. This id is reported in `Event::Acknowledged` once the other connector confirms it received the message.
+    ///
+    /// If `ConnectorParam::BUFFER_SENDS` is enabled, the message is not send until `flush` is called. While the connector is disconnected this returns `Error::NotConnected`, and the message is not cached, see `state`.
     pub fn send_confirmed<T: Into<TParam::TSend>>(
         &mut self,
         socket: &mut dyn Socket,
         msg: T,
-    ) -> Result<()> {
-        let sending_id = if let Some(id) = self.send.next_message_id {
-            id
-        } else {
-            unsafe { NonZeroU64::new_unchecked(1) }
+    ) -> Result<NonZeroU64> {
+        self.send_confirmed_on(socket, 0, msg)
+    }
+
+    /// Send a confirmed message to the other connector on the given channel. This behaves the same as `send_confirmed`, but the message is resend with the priority of the given channel. See `ConnectorParam::CHANNEL_WEIGHTS` for more info.
+    pub fn send_confirmed_on<T: Into<TParam::TSend>>(
+        &mut self,
+        socket: &mut dyn Socket,
+        channel: usize,
+        msg: T,
+    ) -> Result<NonZeroU64> {
+        self.send_confirmed_inner(socket, channel, msg.into(), None, false)
+    }
+
+    /// Send a confirmed message like `send_confirmed`, compressed with deflate. The peer decompresses the message before it is deserialized, and receives it like any other confirmed message.
+    ///
+    /// This is worth it for large messages that compress well, like text or a snapshot of a game world. Small messages are better send with `send_confirmed`, as compression costs time and can even make them larger. The peer must be running a version of this crate that understands compressed messages, and can not receive them with `handle_incoming_data_borrowed`.
+    pub fn send_confirmed_compressed<T: Into<TParam::TSend>>(
+        &mut self,
+        socket: &mut dyn Socket,
+        msg: T,
+    ) -> Result<NonZeroU64> {
+        self.send_confirmed_inner(socket, 0, msg.into(), None, true)
+    }
+
+    /// Send a confirmed message to the other connector, that is only useful if it arrives before the given deadline. This behaves the same as `send_confirmed`, but if the message is not confirmed by the peer before the deadline, it is no longer resend and an `Event::MessageExpired` is queued instead.
+    pub fn send_confirmed_with_deadline<T: Into<TParam::TSend>>(
+        &mut self,
+        socket: &mut dyn Socket,
+        msg: T,
+        deadline: Instant,
+    ) -> Result<NonZeroU64> {
+        self.send_confirmed_inner(socket, 0, msg.into(), Some(deadline), false)
+    }
+
+    /// Send a confirmed message like `send_confirmed`, and return a future that resolves once the peer confirmed the message. This is only available with the `async` feature enabled.
+    ///
+    /// The future does not borrow the connector, which has to be updated and receive data as usual for the confirmation to arrive. The future resolves with `Error::NotDelivered` if the message will never be confirmed, e.g. because `connect` is called or the connector is dropped. There is no timeout, use `send_confirmed_await_with_deadline` or the timeout of your runtime for that.
+    #[cfg(any(test, feature = "async"))]
+    pub fn send_confirmed_await<T: Into<TParam::TSend>>(
+        &mut self,
+        socket: &mut dyn Socket,
+        msg: T,
+    ) -> Result<Delivery> {
+        let id = self.send_confirmed(socket, msg)?;
+        Ok(self.await_delivery(id))
+    }
+
+    /// Send a confirmed message like `send_confirmed_with_deadline`, and return a future that resolves once the peer confirmed the message. The future resolves with `Error::NotDelivered` if the message expires. See `send_confirmed_await` for more info.
+    #[cfg(any(test, feature = "async"))]
+    pub fn send_confirmed_await_with_deadline<T: Into<TParam::TSend>>(
+        &mut self,
+        socket: &mut dyn Socket,
+        msg: T,
+        deadline: Instant,
+    ) -> Result<Delivery> {
+        let id = self.send_confirmed_with_deadline(socket, msg, deadline)?;
+        Ok(self.await_delivery(id))
+    }
+
+    #[cfg(any(test, feature = "async"))]
+    fn await_delivery(&mut self, id: NonZeroU64) -> Delivery {
+        let (delivery, waiter) = delivery::delivery(id);
+        self.send.deliveries.insert(id, waiter);
+        delivery
+    }
+
+    fn send_confirmed_inner(
+        &mut self,
+        socket: &mut dyn Socket,
+        channel: usize,
+        msg: TParam::TSend,
+        deadline: Option<Instant>,
+        compressed: bool,
+    ) -> Result<NonZeroU64> {
+        if channel >= TParam::CHANNEL_WEIGHTS.len() {
+            return Err(Error::UnknownChannel { channel }.into());
+        }
+        self.ensure_connected()?;
+        let held = self.is_before_connected()
+            && match TParam::SEND_BEFORE_CONNECTED {
+                SendBeforeConnected::Send => false,
+                SendBeforeConnected::Buffer => true,
+                SendBeforeConnected::Reject => {
+                    return Err(Error::NotConnected {
+                        state: NetworkState::Connecting,
+                    }
+                    .into())
+                }
+            };
+        self.ensure_message_id(socket)?;
+        let sending_id = self
+            .send
+            .next_message_id
+            .unwrap_or_else(|| NonZeroU64::new(1).unwrap());
+        let unconfirmed_bytes = self.send.unconfirmed_bytes;
+        let check_room = |size: usize| match TParam::MAX_UNCONFIRMED_BYTES {
+            Some(max) if unconfirmed_bytes + size > max => Err(Error::CacheFull {
+                bytes: unconfirmed_bytes,
+            }),
+            _ => Ok(()),
         };
-        let data = Packet::Data {
-            data: msg.into(),
+        // Every packet takes at least one byte, so a full cache is rejected before the message is encoded and compressed
+        check_room(1)?;
+        let bytes = Self::encode(&Packet::Data {
+            data: msg,
+            compressed,
             message_id: Some(sending_id),
-        };
-        send_packet_to(self.peer_addr, socket, &data)?;
-        self.send.unconfirmed_message_cache.insert(
+        })?;
+        check_room(bytes.len())?;
+        if !held {
+            self.send_or_buffer_bytes(socket, None, bytes.clone())?;
+            self.record_confirmed_send(false);
+        }
+        self.send.cache(
             sending_id,
             CachedPacket {
-                packet: data,
-                last_emit: Instant::now(),
+                bytes,
+                last_emit: self.now(),
+                channel,
+                deadline,
+                held,
             },
         );
-        self.send.next_message_id = NonZeroU64::new(sending_id.get() + 1);
+        match sending_id.get().checked_add(1) {
+            Some(next) => self.send.next_message_id = NonZeroU64::new(next),
+            None => self.send.ids_exhausted = true,
+        }
+        Ok(sending_id)
+    }
+
+    /// Returns `Error::MessageIdsExhausted` if there is no id for the next confirmed message. If the ids may wrap and the peer confirmed every message, this starts a new epoch, see `ConnectorParam::WRAP_MESSAGE_IDS`.
+    fn ensure_message_id(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        if self.send.ids_exhausted
+            && TParam::WRAP_MESSAGE_IDS
+            && self.send.unconfirmed_message_cache.is_empty()
+        {
+            self.send.epoch = self.send.epoch.wrapping_add(1);
+            self.send.next_message_id = None;
+            self.send.ids_exhausted = false;
+            self.send.wrap_pending = true;
+            self.send_wrap(socket, self.send.epoch, false)?;
+        }
+        if self.send.ids_exhausted || self.send.wrap_pending {
+            return Err(Error::MessageIdsExhausted.into());
+        }
         Ok(())
     }
-}
 
-fn send_packet_to<TSend: serde::Serialize>(
-    peer_addr: SocketAddr,
-    socket: &mut dyn Socket,
-    packet: &Packet<TSend>,
-) -> Result<()> {
-    let bytes = bincode::serialize(packet)?;
-    socket.send_to(&bytes, peer_addr)?;
-    Ok(())
+    fn send_wrap(&mut self, socket: &mut dyn Socket, epoch: u32, acknowledged: bool) -> Result<()> {
+        self.send_packet(
+            socket,
+            &Packet::<TParam::TSend>::Wrap {
+                epoch,
+                acknowledged,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Returns `true` if the connection is not established yet: a handshake is in progress, or the state is not `NetworkState::Connected`
+    fn is_before_connected(&self) -> bool {
+        self.handshake_started.is_some() || self.state() != NetworkState::Connected
+    }
+
+    /// Send the confirmed messages that were kept until the connection is established, see `ConnectorParam::SEND_BEFORE_CONNECTED`
+    fn release_held(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        if self.is_before_connected() {
+            return Ok(());
+        }
+        let mut held = self
+            .send
+            .unconfirmed_message_cache
+            .iter()
+            .filter(|(_, packet)| packet.held)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        held.sort_unstable();
+        let now = self.now();
+        for id in held {
+            let bytes = self.send.unconfirmed_message_cache[&id].bytes.clone();
+            self.wake(false);
+            if TParam::BUFFER_SENDS {
+                self.send.outgoing_buffer.push((None, bytes));
+            } else {
+                self.send_bytes(socket, &bytes)?;
+            }
+            if let Some(packet) = self.send.unconfirmed_message_cache.get_mut(&id) {
+                packet.held = false;
+                packet.last_emit = now;
+            }
+            self.record_confirmed_send(false);
+        }
+        Ok(())
+    }
+
+    /// Returns `Error::NotConnected` if messages can not be send in the current state, because the peer is considered gone
+    fn ensure_connected(&self) -> Result<()> {
+        match self.state() {
+            state @ NetworkState::Disconnected | state @ NetworkState::Failed => {
+                Err(Error::NotConnected { state }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Set a function that is called with every datagram right before it is send to the peer, and decides what happens with it. Pass `None` to remove the interceptor.
+    ///
+    /// This is intended to test how an application behaves on a bad connection. It is only available in tests and with the `chaos` feature enabled.
+    #[cfg(any(test, feature = "chaos"))]
+    pub fn set_send_interceptor(&mut self, interceptor: Option<SendInterceptor>) {
+        self.send_interceptor = interceptor;
+    }
+
+    /// Send all messages that were buffered because `ConnectorParam::BUFFER_SENDS` is enabled. The messages are packed into as few datagrams as possible, each at most `ConnectorParam::MAX_SEND_DATAGRAM_SIZE` bytes. A message that is larger than that on its own is send in a datagram of its own.
+    ///
+    /// This does nothing if no messages are buffered.
+    pub fn flush(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        let packets = std::mem::take(&mut self.send.outgoing_buffer);
+        self.send_packed(
+            socket,
+            packets.into_iter().map(|(_, packet)| packet).collect(),
+        )
+    }
+
+    /// Send the given serialized packets in as few datagrams as possible, each at most `ConnectorParam::MAX_SEND_DATAGRAM_SIZE` bytes
+    fn send_packed(&mut self, socket: &mut dyn Socket, packets: Vec<Vec<u8>>) -> Result<()> {
+        let mut datagram = Vec::new();
+        let mut datagram_size = packet::BATCH_OVERHEAD;
+        let entry_overhead = if TParam::CHECKSUMMED_BATCHES {
+            packet::CHECKED_BATCH_ENTRY_OVERHEAD
+        } else {
+            packet::BATCH_ENTRY_OVERHEAD
+        };
+        for packet in packets {
+            let size = entry_overhead + packet.len();
+            if !datagram.is_empty() && datagram_size + size > TParam::MAX_SEND_DATAGRAM_SIZE {
+                self.send_datagram(socket, std::mem::take(&mut datagram))?;
+                datagram_size = packet::BATCH_OVERHEAD;
+            }
+            datagram_size += size;
+            datagram.push(packet);
+        }
+        if !datagram.is_empty() {
+            self.send_datagram(socket, datagram)?;
+        }
+        Ok(())
+    }
+
+    /// Send the given packets in a single datagram. A single packet is send as-is, multiple packets are wrapped in a `Packet::Batch`, or a `Packet::CheckedBatch` if `ConnectorParam::CHECKSUMMED_BATCHES` is enabled.
+    fn send_datagram(&mut self, socket: &mut dyn Socket, mut packets: Vec<Vec<u8>>) -> Result<()> {
+        if packets.len() == 1 {
+            let packet = packets.pop().unwrap();
+            self.send_bytes(socket, &packet)
+        } else {
+            // A batch contains no message ids, so it does not have to be compacted
+            let batch = if TParam::CHECKSUMMED_BATCHES {
+                Packet::<TParam::TSend>::CheckedBatch {
+                    packets: packets.into_iter().map(packet::checked_entry).collect(),
+                }
+            } else {
+                Packet::Batch { packets }
+            };
+            let bytes = bincode::serialize(&batch)?;
+            self.send_bytes(socket, &bytes)
+        }
+    }
+
+    /// Send the packet to the peer, or add it to the outgoing buffer if `ConnectorParam::BUFFER_SENDS` is enabled. A buffered packet replaces a buffered packet with the same key. Returns the size of the packet in bytes.
+    fn send_or_buffer<TSend: serde::Serialize>(
+        &mut self,
+        socket: &mut dyn Socket,
+        key: Option<u32>,
+        packet: &Packet<TSend>,
+    ) -> Result<usize> {
+        let bytes = Self::encode(packet)?;
+        let size = bytes.len();
+        self.send_or_buffer_bytes(socket, key, bytes)?;
+        Ok(size)
+    }
+
+    /// Send an encoded packet to the peer, or add it to the outgoing buffer, see `send_or_buffer`
+    fn send_or_buffer_bytes(
+        &mut self,
+        socket: &mut dyn Socket,
+        key: Option<u32>,
+        bytes: Vec<u8>,
+    ) -> Result<()> {
+        self.wake(false);
+        if TParam::BUFFER_SENDS {
+            let buffer = &mut self.send.outgoing_buffer;
+            match buffer.iter_mut().find(|(k, _)| key.is_some() && *k == key) {
+                Some(buffered) => buffered.1 = bytes,
+                None => buffer.push((key, bytes)),
+            }
+            Ok(())
+        } else {
+            self.send_bytes(socket, &bytes)
+        }
+    }
+
+    /// Send the packet to the peer, returning the amount of bytes that were send
+    fn send_packet<TSend: serde::Serialize>(
+        &mut self,
+        socket: &mut dyn Socket,
+        packet: &Packet<TSend>,
+    ) -> Result<usize> {
+        let bytes = Self::encode(packet)?;
+        self.send_bytes(socket, &bytes)?;
+        Ok(bytes.len())
+    }
+
+    /// Serialize a packet, with compact message ids if `ConnectorParam::COMPACT_MESSAGE_IDS` is enabled. The message of a compressed `Packet::Data` is serialized and compressed on its own, see `packet::compress`.
+    fn encode<TSend: serde::Serialize>(packet: &Packet<TSend>) -> Result<Vec<u8>> {
+        match packet {
+            Packet::Data {
+                message_id,
+                compressed: true,
+                data,
+            } => Self::serialize(&Packet::Data {
+                message_id: *message_id,
+                compressed: true,
+                data: packet::compress(&bincode::serialize(data)?),
+            }),
+            packet => Self::serialize(packet),
+        }
+    }
+
+    fn serialize<TSend: serde::Serialize>(packet: &Packet<TSend>) -> Result<Vec<u8>> {
+        Ok(if TParam::COMPACT_MESSAGE_IDS {
+            bincode::serialize(&packet.compact())?
+        } else {
+            bincode::serialize(packet)?
+        })
+    }
+
+    /// Send a serialized packet to the peer
+    fn send_bytes(&mut self, socket: &mut dyn Socket, bytes: &[u8]) -> Result<()> {
+        for packet in packet::split_datagram(bytes).flatten() {
+            packet::count_type(&mut self.stats.sent_packet_types, packet);
+        }
+        let padded;
+        let bytes = match TParam::PADDING {
+            Padding::None => bytes,
+            padding => {
+                padded = packet::pad(bytes, padding);
+                &padded
+            }
+        };
+        self.record(Direction::Sent, bytes);
+        #[cfg(any(test, feature = "chaos"))]
+        {
+            if let Some(interceptor) = self.send_interceptor.as_mut() {
+                match interceptor(bytes) {
+                    SendAction::Send => {}
+                    SendAction::Drop => return Ok(()),
+                    SendAction::Duplicate => socket.send_to(bytes, self.peer_addr)?,
+                }
+            }
+        }
+        socket.send_to(bytes, self.peer_addr)?;
+        Ok(())
+    }
 }
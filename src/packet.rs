@@ -1,26 +1,707 @@
+use crate::{PacketTypeCounts, Padding};
+use bincode::Options;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind;
 use std::num::NonZeroU64;
 
+/// A packet that is send between connectors. Message ids are `NonZeroU64`, or `u32` on the wire when `ConnectorParam::COMPACT_MESSAGE_IDS` is enabled, see `Packet::compact`.
+///
+/// Every packet starts with its bincode variant index, and fills the rest of its datagram or `Batch` entry. New variants must be added at the end, and `LAST_TAG` updated, so older connectors can recognize and skip them, see `ConnectorParam::IGNORE_UNKNOWN_PACKETS`.
+#[allow(clippy::enum_variant_names)]
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
-pub enum Packet<TContent> {
+pub enum Packet<TContent, TId = NonZeroU64> {
     Ping {
-        last_send_message_id: Option<NonZeroU64>,
+        last_send_message_id: Option<TId>,
+        nonce: u32,
     },
+    /// The reply to a `Ping`, with the same nonce
     Pong {
-        last_send_message_id: Option<NonZeroU64>,
+        last_send_message_id: Option<TId>,
+        nonce: u32,
     },
     PacketNotFound {
-        id: NonZeroU64,
+        id: TId,
     },
     RequestPacket {
-        id: NonZeroU64,
+        id: TId,
     },
     ConfirmPacket {
-        id: NonZeroU64,
+        id: TId,
     },
     Data {
-        message_id: Option<NonZeroU64>,
-        #[serde(bound(deserialize = "TContent: Serialize + for<'a> Deserialize<'a>"))]
+        message_id: Option<TId>,
+        /// Set when the message is compressed with deflate, see `Connector::send_confirmed_compressed`. On the wire the message is then the compressed bytes of the serialized message, which `deserialize_packet` reads back into the message.
+        compressed: bool,
+        #[serde(bound(deserialize = "TContent: Deserialize<'de>"))]
         data: TContent,
     },
+    Disconnect {
+        reason: Option<DisconnectReason>,
+    },
+    /// Multiple serialized packets that were coalesced into a single datagram by `Connector::flush`
+    Batch {
+        packets: Vec<Vec<u8>>,
+    },
+    /// Confirms all packets up to and including `id`, see `ConnectorParam::CUMULATIVE_ACKS`
+    ConfirmUpTo {
+        id: TId,
+    },
+    /// An unconfirmed message that is dropped when a newer message on the same stream already arrived, see `Connector::send_unconfirmed_ordered`
+    OrderedData {
+        stream: u32,
+        sequence: u64,
+        #[serde(bound(deserialize = "TContent: Deserialize<'de>"))]
+        data: TContent,
+    },
+    /// Tells the peer that we have no data to send, see `ConnectorParam::IDLE_AFTER_S`. `acknowledged` is set when we know the peer has no data to send either.
+    Idle {
+        acknowledged: bool,
+    },
+    /// Tells the peer that our message ids start at 1 again in a new epoch, see `ConnectorParam::WRAP_MESSAGE_IDS`. The peer replies with `acknowledged` set.
+    Wrap {
+        epoch: u32,
+        acknowledged: bool,
+    },
+    /// Like `Batch`, with a checksum of every packet and its length, see `ConnectorParam::CHECKSUMMED_BATCHES`. Made with `checked_entry`, and split up by `split_datagram`.
+    CheckedBatch {
+        packets: Vec<(u32, Vec<u8>)>,
+    },
+    /// Tells the peer that the application handled its confirmed message, see `Connector::acknowledge_processed`
+    Processed {
+        id: TId,
+    },
+    /// The reply to a `RequestPacket` for a message that the peer already confirmed, so the request crossed the confirmation. Unlike `PacketNotFound`, the message is not lost.
+    AlreadyDelivered {
+        id: TId,
+    },
+    /// The time of the machine that answers a `Ping`, in microseconds since the unix epoch. This is send right before the `Pong` with the same nonce, see `ConnectorParam::CLOCK_SYNC`.
+    Time {
+        nonce: u32,
+        time_us: u64,
+    },
+    /// The features that the sender understands, a combination of the `CAPABILITY_` flags, see `ConnectorParam::NEGOTIATE_CUMULATIVE_ACKS`. `acknowledged` is set when the sender already knows the capabilities of the receiver, so it does not have to reply.
+    Capabilities {
+        flags: u32,
+        acknowledged: bool,
+    },
+    /// The highest sequence that was received on a stream of `OrderedData`, and the amount of messages that were received on it. This is send right after a `Pong`, see `ConnectorParam::SEQUENCE_ACKS`.
+    SequenceAck {
+        stream: u32,
+        sequence: u64,
+        received: u64,
+    },
+    /// Send right before every `Ping` after `Connector::connect`, until the peer answered one. `session` is a random number that is picked on every `connect`, so when it changes the peer knows our message ids start at 1 again, and forgets the messages it received from us before.
+    Hello {
+        session: u32,
+    },
+}
+
+impl<TContent> Packet<TContent> {
+    /// This packet with only the lowest 32 bits of every message id, see `ConnectorParam::COMPACT_MESSAGE_IDS`
+    pub fn compact(&self) -> Packet<&TContent, u32> {
+        let compact = |id: NonZeroU64| id.get() as u32;
+        match self {
+            Packet::Ping {
+                last_send_message_id,
+                nonce,
+            } => Packet::Ping {
+                last_send_message_id: last_send_message_id.map(compact),
+                nonce: *nonce,
+            },
+            Packet::Pong {
+                last_send_message_id,
+                nonce,
+            } => Packet::Pong {
+                last_send_message_id: last_send_message_id.map(compact),
+                nonce: *nonce,
+            },
+            Packet::PacketNotFound { id } => Packet::PacketNotFound { id: compact(*id) },
+            Packet::RequestPacket { id } => Packet::RequestPacket { id: compact(*id) },
+            Packet::ConfirmPacket { id } => Packet::ConfirmPacket { id: compact(*id) },
+            Packet::Data {
+                message_id,
+                compressed,
+                data,
+            } => Packet::Data {
+                message_id: message_id.map(compact),
+                compressed: *compressed,
+                data,
+            },
+            Packet::Disconnect { reason } => Packet::Disconnect {
+                reason: reason.clone(),
+            },
+            Packet::Batch { packets } => Packet::Batch {
+                packets: packets.clone(),
+            },
+            Packet::ConfirmUpTo { id } => Packet::ConfirmUpTo { id: compact(*id) },
+            Packet::OrderedData {
+                stream,
+                sequence,
+                data,
+            } => Packet::OrderedData {
+                stream: *stream,
+                sequence: *sequence,
+                data,
+            },
+            Packet::Idle { acknowledged } => Packet::Idle {
+                acknowledged: *acknowledged,
+            },
+            Packet::Wrap {
+                epoch,
+                acknowledged,
+            } => Packet::Wrap {
+                epoch: *epoch,
+                acknowledged: *acknowledged,
+            },
+            Packet::CheckedBatch { packets } => Packet::CheckedBatch {
+                packets: packets.clone(),
+            },
+            Packet::Processed { id } => Packet::Processed { id: compact(*id) },
+            Packet::AlreadyDelivered { id } => Packet::AlreadyDelivered { id: compact(*id) },
+            Packet::Time { nonce, time_us } => Packet::Time {
+                nonce: *nonce,
+                time_us: *time_us,
+            },
+            Packet::Capabilities {
+                flags,
+                acknowledged,
+            } => Packet::Capabilities {
+                flags: *flags,
+                acknowledged: *acknowledged,
+            },
+            Packet::SequenceAck {
+                stream,
+                sequence,
+                received,
+            } => Packet::SequenceAck {
+                stream: *stream,
+                sequence: *sequence,
+                received: *received,
+            },
+            Packet::Hello { session } => Packet::Hello { session: *session },
+        }
+    }
+}
+
+impl<TContent> Packet<TContent, u32> {
+    /// Restore the full message ids of a packet that was made with `compact`. Every id is assumed to be the id closest to a reference id with the same lowest 32 bits. `own` is the reference for ids of messages that we send, and `peer` for ids of messages that the peer send.
+    pub fn expand(self, own: u64, peer: u64) -> bincode::Result<Packet<TContent>> {
+        let own = |id| expand_id(id, own);
+        let peer = |id| expand_id(id, peer);
+        Ok(match self {
+            Packet::Ping {
+                last_send_message_id,
+                nonce,
+            } => Packet::Ping {
+                last_send_message_id: last_send_message_id.map(peer).transpose()?,
+                nonce,
+            },
+            Packet::Pong {
+                last_send_message_id,
+                nonce,
+            } => Packet::Pong {
+                last_send_message_id: last_send_message_id.map(peer).transpose()?,
+                nonce,
+            },
+            Packet::PacketNotFound { id } => Packet::PacketNotFound { id: peer(id)? },
+            Packet::RequestPacket { id } => Packet::RequestPacket { id: own(id)? },
+            Packet::ConfirmPacket { id } => Packet::ConfirmPacket { id: own(id)? },
+            Packet::Data {
+                message_id,
+                compressed,
+                data,
+            } => Packet::Data {
+                message_id: message_id.map(peer).transpose()?,
+                compressed,
+                data,
+            },
+            Packet::Disconnect { reason } => Packet::Disconnect { reason },
+            Packet::Batch { packets } => Packet::Batch { packets },
+            Packet::ConfirmUpTo { id } => Packet::ConfirmUpTo { id: own(id)? },
+            Packet::OrderedData {
+                stream,
+                sequence,
+                data,
+            } => Packet::OrderedData {
+                stream,
+                sequence,
+                data,
+            },
+            Packet::Idle { acknowledged } => Packet::Idle { acknowledged },
+            Packet::Wrap {
+                epoch,
+                acknowledged,
+            } => Packet::Wrap {
+                epoch,
+                acknowledged,
+            },
+            Packet::CheckedBatch { packets } => Packet::CheckedBatch { packets },
+            Packet::Processed { id } => Packet::Processed { id: own(id)? },
+            Packet::AlreadyDelivered { id } => Packet::AlreadyDelivered { id: peer(id)? },
+            Packet::Time { nonce, time_us } => Packet::Time { nonce, time_us },
+            Packet::Capabilities {
+                flags,
+                acknowledged,
+            } => Packet::Capabilities {
+                flags,
+                acknowledged,
+            },
+            Packet::SequenceAck {
+                stream,
+                sequence,
+                received,
+            } => Packet::SequenceAck {
+                stream,
+                sequence,
+                received,
+            },
+            Packet::Hello { session } => Packet::Hello { session },
+        })
+    }
+}
+
+/// A short description of the packet for logs, e.g. `Data(id=5, 120 bytes)`. Only the size of the message is shown, not its contents.
+impl<TContent: Serialize, TId: fmt::Display> fmt::Display for Packet<TContent, TId> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = |data: &TContent| bincode::serialized_size(data).map_err(|_| fmt::Error);
+        let id = |id: &Option<TId>| match id {
+            Some(id) => id.to_string(),
+            None => "none".to_owned(),
+        };
+        match self {
+            Packet::Ping {
+                last_send_message_id,
+                ..
+            } => write!(f, "Ping(last={})", id(last_send_message_id)),
+            Packet::Pong {
+                last_send_message_id,
+                ..
+            } => write!(f, "Pong(last={})", id(last_send_message_id)),
+            Packet::PacketNotFound { id } => write!(f, "PacketNotFound(id={})", id),
+            Packet::RequestPacket { id } => write!(f, "RequestPacket(id={})", id),
+            Packet::ConfirmPacket { id } => write!(f, "ConfirmPacket(id={})", id),
+            Packet::Data {
+                message_id: Some(id),
+                compressed,
+                data,
+            } => write!(
+                f,
+                "Data(id={}, {} bytes{})",
+                id,
+                size(data)?,
+                if *compressed { ", compressed" } else { "" }
+            ),
+            Packet::Data {
+                message_id: None,
+                data,
+                ..
+            } => write!(f, "Data(unconfirmed, {} bytes)", size(data)?),
+            Packet::Disconnect {
+                reason: Some(reason),
+            } => {
+                write!(f, "Disconnect(code={})", reason.code)
+            }
+            Packet::Disconnect { reason: None } => write!(f, "Disconnect"),
+            Packet::Batch { packets } => write!(f, "Batch({} packets)", packets.len()),
+            Packet::ConfirmUpTo { id } => write!(f, "ConfirmUpTo(id={})", id),
+            Packet::OrderedData {
+                stream,
+                sequence,
+                data,
+            } => write!(
+                f,
+                "OrderedData(stream={}, sequence={}, {} bytes)",
+                stream,
+                sequence,
+                size(data)?
+            ),
+            Packet::Idle { acknowledged } => write!(f, "Idle(acknowledged={})", acknowledged),
+            Packet::Wrap {
+                epoch,
+                acknowledged,
+            } => write!(f, "Wrap(epoch={}, acknowledged={})", epoch, acknowledged),
+            Packet::CheckedBatch { packets } => {
+                write!(f, "CheckedBatch({} packets)", packets.len())
+            }
+            Packet::Processed { id } => write!(f, "Processed(id={})", id),
+            Packet::AlreadyDelivered { id } => write!(f, "AlreadyDelivered(id={})", id),
+            Packet::Time { nonce, .. } => write!(f, "Time(nonce={})", nonce),
+            Packet::Capabilities {
+                flags,
+                acknowledged,
+            } => write!(
+                f,
+                "Capabilities(flags={:#x}, acknowledged={})",
+                flags, acknowledged
+            ),
+            Packet::SequenceAck {
+                stream,
+                sequence,
+                received,
+            } => write!(
+                f,
+                "SequenceAck(stream={}, sequence={}, received={})",
+                stream, sequence, received
+            ),
+            Packet::Hello { session } => write!(f, "Hello(session={:#x})", session),
+        }
+    }
+}
+
+/// The id closest to `reference` that has `id` as its lowest 32 bits
+fn expand_id(id: u32, reference: u64) -> bincode::Result<NonZeroU64> {
+    const WRAP: u64 = 1 << 32;
+    let candidate = (reference & !(WRAP - 1)) | u64::from(id);
+    let expanded = if candidate.saturating_add(WRAP / 2) < reference {
+        candidate.checked_add(WRAP)
+    } else if candidate > reference.saturating_add(WRAP / 2) {
+        candidate.checked_sub(WRAP)
+    } else {
+        Some(candidate)
+    };
+    expanded.and_then(NonZeroU64::new).ok_or_else(|| {
+        Box::new(bincode::ErrorKind::Custom(format!(
+            "Invalid message id {}",
+            id
+        )))
+    })
+}
+
+/// The bincode variant index of `Packet::Ping`
+const PING_TAG: u32 = 0;
+
+/// The bincode variant index of `Packet::Pong`
+const PONG_TAG: u32 = 1;
+
+/// The bincode variant index of `Packet::PacketNotFound`
+const PACKET_NOT_FOUND_TAG: u32 = 2;
+
+/// The bincode variant index of `Packet::RequestPacket`
+const REQUEST_PACKET_TAG: u32 = 3;
+
+/// The bincode variant index of `Packet::ConfirmPacket`
+const CONFIRM_PACKET_TAG: u32 = 4;
+
+/// The bincode variant index of `Packet::Data`
+const DATA_TAG: u32 = 5;
+
+/// The bincode variant index of `Packet::Batch`
+const BATCH_TAG: u32 = 7;
+
+/// The bincode variant index of `Packet::ConfirmUpTo`
+const CONFIRM_UP_TO_TAG: u32 = 8;
+
+/// The bincode variant index of `Packet::OrderedData`
+const ORDERED_DATA_TAG: u32 = 9;
+
+/// The bincode variant index of `Packet::CheckedBatch`
+const CHECKED_BATCH_TAG: u32 = 12;
+
+/// The bincode variant index of `Packet::Hello`
+const HELLO_TAG: u32 = 18;
+
+/// The highest bincode variant index that this version knows, the index of `Packet::Hello`
+pub(crate) const LAST_TAG: u32 = 18;
+
+/// The `Packet::Capabilities` flag of a connector that understands `Packet::ConfirmUpTo`
+pub const CAPABILITY_CUMULATIVE_ACKS: u32 = 1;
+
+/// The amount of bytes a `Packet::Batch` takes, without the packets in it
+pub const BATCH_OVERHEAD: usize = 4 + 8;
+
+/// The amount of bytes every packet in a `Packet::Batch` takes, on top of the packet itself
+pub const BATCH_ENTRY_OVERHEAD: usize = 8;
+
+/// The amount of bytes every packet in a `Packet::CheckedBatch` takes, on top of the packet itself
+pub const CHECKED_BATCH_ENTRY_OVERHEAD: usize = 4 + 8;
+
+/// The maximum amount of positions in a `Packet::CheckedBatch` that are tried to find the next valid packet after a corrupt one. Every try calculates a checksum, so without a limit a datagram full of garbage takes quadratic time.
+const CHECKED_BATCH_RESYNC_LIMIT: usize = 256;
+
+/// The reason a connector was closed. This is send to the peer by `Connector::close`, and reported to the peer as `Event::Disconnected`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct DisconnectReason {
+    /// A code that identifies the reason, e.g. kicked, banned or server shutdown. The meaning of the code is up to the application.
+    pub code: u16,
+
+    /// An optional message that explains the reason, e.g. to show to a user.
+    pub message: Option<String>,
+}
+
+impl DisconnectReason {
+    /// Create a reason with the given code and no message
+    pub fn new(code: u16) -> DisconnectReason {
+        DisconnectReason {
+            code,
+            message: None,
+        }
+    }
+
+    /// Create a reason with the given code and message
+    pub fn with_message(code: u16, message: impl Into<String>) -> DisconnectReason {
+        DisconnectReason {
+            code,
+            message: Some(message.into()),
+        }
+    }
+}
+
+/// Deserialize a packet with the same format as `bincode::deserialize`. Packets that are larger than `limit` bytes are rejected with `bincode::ErrorKind::SizeLimit`.
+///
+/// bincode never reads past the end of the given slice, so a packet that claims to contain a huge `Vec` or `String` fails with an unexpected EOF instead of allocating the claimed size. This means the limit on the size of the packet also limits how much can be allocated while deserializing it. The limit is set on the bincode options as well, and the other options are spelled out, so the format does not change with the defaults of bincode.
+pub fn deserialize<'a, T: Deserialize<'a>>(data: &'a [u8], limit: u64) -> bincode::Result<T> {
+    if data.len() as u64 > limit {
+        return Err(Box::new(bincode::ErrorKind::SizeLimit));
+    }
+    bincode::options()
+        .with_limit(limit)
+        .with_fixint_encoding()
+        .with_little_endian()
+        .allow_trailing_bytes()
+        .deserialize(data)
+}
+
+/// Deserialize a packet like `deserialize`. The message of a `Packet::Data` that is compressed is decompressed first, and then read by `decode`, so the message can not borrow from `data`.
+pub fn deserialize_packet<'a, T: Deserialize<'a>, TId: DeserializeOwned>(
+    data: &'a [u8],
+    limit: u64,
+    decode: fn(&[u8]) -> crate::Result<T>,
+) -> crate::Result<Packet<T, TId>> {
+    if read_u32(&mut &data[..]) == Some(DATA_TAG) {
+        let (_, _, compressed) = deserialize::<(u32, Option<TId>, bool)>(data, limit)?;
+        if compressed {
+            let (_, message_id, _, message) =
+                deserialize::<(u32, Option<TId>, bool, Vec<u8>)>(data, limit)?;
+            return Ok(Packet::Data {
+                message_id,
+                compressed,
+                data: decode(&decompress(&message, limit)?)?,
+            });
+        }
+    }
+    Ok(deserialize(data, limit)?)
+}
+
+/// Split a datagram into the packets it contains. A `Packet::Batch` or `Packet::CheckedBatch` is split into the packets it wraps, any other datagram is returned as a single packet.
+///
+/// The packets are read one by one, so the packets before a malformed packet in a batch are still returned. After an error in a `Packet::Batch` no more packets are returned. A corrupt packet in a `Packet::CheckedBatch` is returned as an error, after which the next packet with a valid checksum is searched, see `is_checked_batch`. At most `CHECKED_BATCH_RESYNC_LIMIT` positions are tried for the whole datagram, the rest of the datagram is dropped after that.
+///
+/// The returned packets borrow from `data`, so they can be deserialized into types that borrow from the datagram.
+pub fn split_datagram(data: &[u8]) -> SplitDatagram<'_> {
+    let mut rest = data;
+    match read_u32(&mut rest) {
+        Some(BATCH_TAG) => {}
+        // The amount of packets may be corrupt as well, so the packets are read until the end of the datagram
+        Some(CHECKED_BATCH_TAG) => {
+            return match take(&mut rest, 8) {
+                Some(_) => SplitDatagram::CheckedBatch {
+                    rest,
+                    resync_budget: CHECKED_BATCH_RESYNC_LIMIT,
+                },
+                None => SplitDatagram::Failed(Some(unexpected_eof())),
+            };
+        }
+        _ => return SplitDatagram::Single(Some(data)),
+    }
+    match read_u64(&mut rest) {
+        Some(remaining) => SplitDatagram::Batch { rest, remaining },
+        None => SplitDatagram::Failed(Some(unexpected_eof())),
+    }
+}
+
+/// The packets in a datagram, see `split_datagram`
+pub enum SplitDatagram<'a> {
+    /// A datagram that is not a batch
+    Single(Option<&'a [u8]>),
+
+    /// A batch with `remaining` packets left in `rest`
+    Batch { rest: &'a [u8], remaining: u64 },
+
+    /// A checked batch with the packets that are left in `rest`, and the amount of positions that may still be tried after a corrupt packet
+    CheckedBatch {
+        rest: &'a [u8],
+        resync_budget: usize,
+    },
+
+    /// The datagram is malformed, the error is returned once
+    Failed(Option<bincode::Error>),
+}
+
+impl<'a> Iterator for SplitDatagram<'a> {
+    type Item = bincode::Result<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SplitDatagram::Single(packet) => packet.take().map(Ok),
+            SplitDatagram::Batch { remaining: 0, .. } => None,
+            SplitDatagram::Batch { rest, remaining } => {
+                *remaining -= 1;
+                let packet = read_u64(rest)
+                    .and_then(|len| usize::try_from(len).ok())
+                    .and_then(|len| take(rest, len));
+                match packet {
+                    Some(packet) => Some(Ok(packet)),
+                    None => {
+                        *self = SplitDatagram::Failed(None);
+                        Some(Err(unexpected_eof()))
+                    }
+                }
+            }
+            SplitDatagram::CheckedBatch { rest: [], .. } => None,
+            SplitDatagram::CheckedBatch {
+                rest,
+                resync_budget,
+            } => {
+                if let Some(packet) = read_checked_entry(rest) {
+                    return Some(Ok(packet));
+                }
+                // Skip to the next position where a packet with a valid checksum starts
+                let end = rest.len().min(*resync_budget + 1);
+                let next =
+                    (1..end).find(|start| read_checked_entry(&mut &rest[*start..]).is_some());
+                *resync_budget -= next.unwrap_or(end - 1);
+                *rest = &rest[next.unwrap_or(rest.len())..];
+                Some(Err(Box::new(bincode::ErrorKind::Custom(
+                    "Packet in batch has an invalid checksum".to_owned(),
+                ))))
+            }
+            SplitDatagram::Failed(error) => error.take().map(Err),
+        }
+    }
+}
+
+/// Returns `true` if the datagram is a `Packet::CheckedBatch`. A malformed packet in such a batch does not affect the other packets, so they can still be handled.
+pub fn is_checked_batch(data: &[u8]) -> bool {
+    read_u32(&mut &data[..]) == Some(CHECKED_BATCH_TAG)
+}
+
+/// The entry of a packet in a `Packet::CheckedBatch`, with the checksum of the packet and its length
+pub fn checked_entry(packet: Vec<u8>) -> (u32, Vec<u8>) {
+    (entry_checksum(&packet), packet)
+}
+
+/// Read a packet of a `Packet::CheckedBatch` from the start of `data`, if its checksum is valid
+fn read_checked_entry<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let mut rest = *data;
+    let checksum = read_u32(&mut rest)?;
+    let len = usize::try_from(read_u64(&mut rest)?).ok()?;
+    let packet = take(&mut rest, len)?;
+    if entry_checksum(packet) != checksum {
+        return None;
+    }
+    *data = rest;
+    Some(packet)
+}
+
+/// The checksum of a packet in a `Packet::CheckedBatch`. The length is included, so a corrupt length is detected as well.
+fn entry_checksum(packet: &[u8]) -> u32 {
+    let len = (packet.len() as u64).to_le_bytes();
+    !crc32(crc32(!0, &len), packet)
+}
+
+/// Update a CRC-32 (IEEE) with the given bytes
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+/// Returns `true` if the packet is a `Packet::Data` or `Packet::OrderedData`, without deserializing it
+pub fn is_data(data: &[u8]) -> bool {
+    matches!(
+        read_u32(&mut &data[..]),
+        Some(DATA_TAG) | Some(ORDERED_DATA_TAG)
+    )
+}
+
+/// Returns `true` if the datagram contains a `Packet::Hello`, the handshake of a peer that connects, without deserializing it
+pub fn is_handshake(data: &[u8]) -> bool {
+    split_datagram(data)
+        .flatten()
+        .any(|packet| read_u32(&mut &packet[..]) == Some(HELLO_TAG))
+}
+
+/// Compress a serialized message for a `Packet::Data` that is compressed
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(data, 6)
+}
+
+/// Decompress the message of a `Packet::Data` that is compressed. A message that is larger than `limit` bytes when decompressed is an error, so a small packet can not make us allocate a lot of memory.
+pub fn decompress(data: &[u8], limit: u64) -> bincode::Result<Vec<u8>> {
+    let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+    miniz_oxide::inflate::decompress_to_vec_with_limit(data, limit).map_err(|e| {
+        Box::new(bincode::ErrorKind::Custom(format!(
+            "Could not decompress message: {}",
+            e
+        )))
+    })
+}
+
+/// Increment the counter of the type of the packet, without deserializing it. `data` must be a single packet, not a batched datagram.
+pub fn count_type(counts: &mut PacketTypeCounts, data: &[u8]) {
+    let counter = match read_u32(&mut &data[..]) {
+        Some(PING_TAG) => &mut counts.pings,
+        Some(PONG_TAG) => &mut counts.pongs,
+        Some(REQUEST_PACKET_TAG) => &mut counts.requests,
+        Some(CONFIRM_PACKET_TAG) | Some(CONFIRM_UP_TO_TAG) => &mut counts.confirms,
+        Some(PACKET_NOT_FOUND_TAG) => &mut counts.not_founds,
+        Some(DATA_TAG) | Some(ORDERED_DATA_TAG) => &mut counts.data,
+        _ => &mut counts.other,
+    };
+    *counter += 1;
+}
+
+/// Returns `true` if the packet has a variant index that is newer than this version, without deserializing it. The packet was send by a newer version of this crate.
+pub fn is_unknown(data: &[u8]) -> bool {
+    read_u32(&mut &data[..]).is_some_and(|tag| tag > LAST_TAG)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if data.len() < len {
+        return None;
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Some(head)
+}
+
+fn read_u32(data: &mut &[u8]) -> Option<u32> {
+    take(data, 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &mut &[u8]) -> Option<u64> {
+    take(data, 8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn unexpected_eof() -> bincode::Error {
+    Box::new(bincode::ErrorKind::Io(ErrorKind::UnexpectedEof.into()))
+}
+
+/// Prefix the datagram with its length, and pad it with random bytes to the size decided by `padding`
+pub fn pad(data: &[u8], padding: Padding) -> Vec<u8> {
+    let mut padded = Vec::with_capacity(padding.padded_size(data.len()));
+    padded.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    padded.extend_from_slice(data);
+    let mut random = RandomState::new().build_hasher();
+    while padded.len() < padding.padded_size(data.len()) {
+        random.write_u8(0);
+        padded.push(random.finish() as u8);
+    }
+    padded
+}
+
+/// Strip the padding that was added by `pad`
+pub fn strip_padding(data: &[u8]) -> bincode::Result<&[u8]> {
+    let mut rest = data;
+    let len = read_u32(&mut rest).ok_or_else(unexpected_eof)?;
+    take(&mut rest, len as usize).ok_or_else(unexpected_eof)
 }
@@ -1,4 +1,6 @@
+use crate::DisconnectReason;
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 
 /// Settings that are set up for a Connector. This can be used to tweak your Connector at compile-time
 pub trait ConnectorParam {
@@ -53,6 +55,8 @@ pub trait ConnectorParam {
     /// }
     /// # fn main() {}
     /// ```
+    ///
+    /// A connector that only sends messages can use an empty enum, like `enum Never {}`, together with disabling `RECEIVES_MESSAGES`.
     type TReceive: for<'a> Deserialize<'a> + Serialize;
 
     /// The interval at which pings are being emitted to the other connector. This should be set in relation to `RECEIVE_PING_TIMEOUT_S` and `SEND_PING_TIMEOUT_S`, and how often you expect to lose packets.
@@ -61,12 +65,266 @@ pub trait ConnectorParam {
     /// The interval at which missing packets are being requested from the connector
     const REQUEST_MISSING_PACKET_INTERVAL_S: f64 = 1.;
 
+    /// When disabled, missing packets are never requested from the other connector. Confirmed messages then only arrive because the other connector resends them every `EMIT_UNCONFIRMED_PACKET_INTERVAL_S`, and `Event::MessageLost` is never queued.
+    ///
+    /// This saves the uplink of the receiving side, at the cost of slower recovery of lost packets.
+    const ENABLE_MISSING_REQUESTS: bool = true;
+
+    /// The time in seconds that received confirmed messages are collected before they are confirmed to the peer. The collected confirmations are send by `Connector::update` once this time has passed, packed into as few datagrams as possible. Together with `CUMULATIVE_ACKS` this greatly reduces the amount of confirmations for a sender that sends bursts of messages. `0` confirms every message right away.
+    ///
+    /// The peer resends a message that is not confirmed within `EMIT_UNCONFIRMED_PACKET_INTERVAL_S`, so this should be well below that interval minus the round trip time. Otherwise the peer resends messages that already arrived.
+    const ACK_DELAY_S: f64 = 0.;
+
+    /// When enabled, message ids are send as 4 bytes instead of 8, which saves bandwidth on every confirmed message, confirmation and request. Both connectors must use the same setting.
+    ///
+    /// Only the lowest 32 bits of an id are send. The receiver restores the full id by picking the id closest to the ids it has seen so far, so this works as long as the ids in flight are less than 2^31 apart.
+    const COMPACT_MESSAGE_IDS: bool = false;
+
+    /// When enabled, a received confirmed message is confirmed together with all messages before it, if none of those are missing. The peer can then forget all these messages at once, instead of handling a confirmation for every message.
+    ///
+    /// The peer must be running a version of this crate that understands these confirmations. This has no effect when `ENABLE_MISSING_REQUESTS` is disabled, because missing messages are not tracked then.
+    const CUMULATIVE_ACKS: bool = false;
+
+    /// When enabled, the connector announces to the peer that it understands cumulative confirmations, and confirms like `CUMULATIVE_ACKS` once the peer announced the same. Until then, and with a peer that never announces it, every message is confirmed with a `Packet::ConfirmPacket` of its own. A contiguous run of messages that arrives in a single datagram, e.g. the resends after a burst of lost datagrams, is then confirmed with a single `Packet::ConfirmUpTo`.
+    ///
+    /// The announcement is send with the pings, in a datagram of its own, until the peer answered it. A peer with an older version of this crate drops that datagram, and keeps receiving individual confirmations. Like `CUMULATIVE_ACKS`, this has no effect when `ENABLE_MISSING_REQUESTS` is disabled.
+    const NEGOTIATE_CUMULATIVE_ACKS: bool = false;
+
+    /// When enabled, received confirmed messages are not confirmed with packets of their own. Instead, the next ping or pong is send together with a `Packet::ConfirmUpTo` for all messages up to the first missing message, in the same datagram. This sends far less datagrams, at the cost of confirming messages up to `PING_INTERVAL_S` later, so `EMIT_UNCONFIRMED_PACKET_INTERVAL_S` should be longer than that to prevent needless resends.
+    ///
+    /// This replaces `ACK_DELAY_S`. Like `CUMULATIVE_ACKS`, the peer must understand these confirmations, and this has no effect when `ENABLE_MISSING_REQUESTS` is disabled.
+    const ACKS_IN_PINGS: bool = false;
+
+    /// When enabled, every pong is send together with the current time of this machine, in the same datagram. The peer uses this to estimate the difference between the clocks of both machines, see `Connector::estimated_clock_offset`.
+    ///
+    /// This is about the peer measuring our clock, so it has to be enabled on the side that answers the pings. The peer must be running a version of this crate that understands these packets.
+    const CLOCK_SYNC: bool = false;
+
+    /// When enabled, every pong is send together with the highest sequence that was received on every stream of `Connector::send_unconfirmed_ordered`, and the amount of messages that were received on it. The peer uses this to estimate how many of these messages are lost, see `Stats::stream_feedback`, e.g. to lower the bitrate of a video stream, while the messages themselves are still not resend.
+    ///
+    /// Like `CLOCK_SYNC`, this has to be enabled on the side that receives the streams and answers the pings. The peer must be running a version of this crate that understands these packets.
+    const SEQUENCE_ACKS: bool = false;
+
+    /// The maximum amount of streams that are reported with every pong, see `SEQUENCE_ACKS`. When more streams were received, every pong reports the streams after the ones that the previous pong reported.
+    const MAX_SEQUENCE_ACKS: usize = 16;
+
+    /// The maximum amount of streams of `Connector::send_unconfirmed_ordered` that are tracked for the peer. The newest sequence of every stream is kept until the peer connects again, so a message on a new stream beyond this limit is dropped, and counted in `Stats::stream_limit_drops`.
+    const MAX_STREAMS: usize = 256;
+
+    /// When enabled, the ids of confirmed messages start at 1 again after `u64::MAX` was used. When disabled, sending a confirmed message after that returns `Error::MessageIdsExhausted`. Only connections that live long enough to send 2^64 confirmed messages need this.
+    ///
+    /// The ids only wrap once the peer confirmed every message, so a new id can never be confused with a message that is still in flight. The connector then starts a new epoch, and tells the peer with a `Packet::Wrap`. Until the peer acknowledged the new epoch, which usually takes a single round trip, sending a confirmed message returns `Error::MessageIdsExhausted`. Packets of the previous epoch that arrive late are ignored by the peer.
+    ///
+    /// The peer must be running a version of this crate that understands these packets.
+    const WRAP_MESSAGE_IDS: bool = false;
+
+    /// When enabled, packets of a kind that is unknown to this version are skipped, and counted in `Stats::unknown_packets`. This allows a peer that runs a newer version of this crate to connect, as long as it only relies on the packets that both versions know, e.g. during a rolling upgrade.
+    ///
+    /// When disabled, an unknown packet is an error, like any other malformed datagram.
+    const IGNORE_UNKNOWN_PACKETS: bool = true;
+
+    /// When enabled, a packet from the peer that does not make sense is an error, returned from `Connector::handle_incoming_data`. See `Anomaly` for the kinds of packets this applies to. The packet is counted in `Stats` either way.
+    ///
+    /// Some of these anomalies can also be caused by an unreliable network, e.g. a pong that arrives after `SEND_PING_TIMEOUT_S` or pings that arrive out of order. This is meant for tests and for debugging a peer, not for production.
+    const STRICT: bool = false;
+
+    /// When disabled, this connector only sends messages. A message from the peer is not deserialized, but returned from `Connector::handle_incoming_data` as `Error::ReceiveNotSupported`. Other packets, like pings and confirmations, are still handled.
+    ///
+    /// Disable this when `TReceive` is an uninhabited type like an empty enum, which can never be deserialized.
+    const RECEIVES_MESSAGES: bool = true;
+
+    /// When enabled, every packet that is received from the peer counts as a ping for `RECEIVE_PING_TIMEOUT_S`, instead of only pings and pongs. A peer that keeps sending data is then considered connected, even if its pings get lost between all that data.
+    ///
+    /// Packets that could not be read never count, so garbage from the peer address does not keep the connection alive.
+    const ANY_PACKET_IS_PING: bool = false;
+
+    /// When enabled, the connector remembers the latest unconfirmed message per key, and resends these messages as soon as the handshake completes after `Connector::connect`. This lets the peer resync quickly, instead of waiting for the next update.
+    ///
+    /// Messages send with `Connector::send_unconfirmed` share a single key, messages send with `Connector::send_unconfirmed_keyed` are remembered per key. Only enable this if unconfirmed messages describe state, like the position of a player.
+    const RESEND_LATEST_UNCONFIRMED_ON_RECONNECT: bool = false;
+
+    /// The time in seconds without sending or receiving data after which the connector offers the peer to go idle. When the peer has no data either, both sides send pings every `IDLE_PING_INTERVAL_S` instead of `PING_INTERVAL_S`, and wait correspondingly longer for pings of the other side. As soon as a message is send or received, the connector leaves idle mode. This saves power on devices that are idle most of the time.
+    ///
+    /// A side only slows down its pings after the peer told it that it expects them, so this is safe when only one side enables it. `None` never goes idle.
+    const IDLE_AFTER_S: Option<f64> = None;
+
+    /// The interval in seconds at which pings are send while idle, see `IDLE_AFTER_S`
+    const IDLE_PING_INTERVAL_S: f64 = 10.;
+
+    /// The maximum amount of missing packets that are requested in a single call to `Connector::update`. The other missing packets are requested in a later update.
+    const MAX_REQUESTS_PER_UPDATE: usize = usize::MAX;
+
+    /// The maximum amount of control packets that are send for every datagram received from the peer. Control packets are pongs, confirmations, requests for missing packets, and replies to requests. Control packets beyond this limit are not send, see `Stats::suppressed_control_packets`.
+    ///
+    /// This prevents a malicious peer from using the connector as a packet amplifier, e.g. by claiming it has send thousands of messages so we request all of them. `None` disables the limit.
+    ///
+    /// The limit also bounds the missing ids that are tracked, because every one of them is requested: a ping only marks as many ids missing as could still be requested, and the next datagrams of the peer mark the rest. A message that would make us track more is rejected like a message beyond `MAX_ID_GAP`, the peer resends it later. `MAX_ID_GAP` still applies on top of this, and is the only bound when this is `None`.
+    const MAX_AMPLIFICATION: Option<u64> = None;
+
+    /// The maximum amount of ids that a confirmed message of the peer can be ahead of the highest message that arrived. The ids reported in pings do not move this forward. A message or ping that is further ahead is rejected, and counted in `Stats::ids_too_far_ahead`, see `Anomaly::IdGapTooLarge`.
+    ///
+    /// Every id in the gap is tracked as missing, so without this limit a malicious peer can send a message with an id close to `u64::MAX` and make us track an enormous amount of missing messages. `MAX_AMPLIFICATION` can bound the tracked ids further. The peer must not send more confirmed messages than this while the connection is down. `None` disables the limit, which is only safe with a trusted peer.
+    const MAX_ID_GAP: Option<u64> = Some(65_536);
+
+    /// The maximum summed size in bytes of the confirmed messages that are waiting for a confirmation of the peer. A confirmed message that would exceed this is not send, and `Connector::send_confirmed` returns `Error::CacheFull` instead.
+    ///
+    /// This bounds the memory that is used when the peer stops confirming messages, e.g. because it is gone or malicious. `None` disables the limit.
+    const MAX_UNCONFIRMED_BYTES: Option<usize> = None;
+
+    /// The order in which missing packets are requested, when more packets are missing than `MAX_REQUESTS_PER_UPDATE`. Packets that were requested the longest ago always go first, this decides the order between packets that were requested at the same time.
+    const MISSING_REQUEST_ORDER: MissingRequestOrder = MissingRequestOrder::Oldest;
+
     /// The interval at which unconfirmed packets are being send to the other connector
     const EMIT_UNCONFIRMED_PACKET_INTERVAL_S: f64 = 1.;
 
+    /// The time in seconds the peer has to answer a ping after `Connector::connect` is called. If the peer does not answer in time, the state becomes `NetworkState::Failed`. `None` waits forever.
+    const CONNECT_TIMEOUT_S: Option<f64> = None;
+
+    /// The maximum time in seconds a single session may last. A session starts when the connector is created, or when `Connector::connect` is called. Once the session lasted longer than this, the connector is disconnected and `Connector::update` closes it, e.g. to force the peer to authenticate again. `None` lets sessions last forever.
+    const MAX_SESSION_DURATION_S: Option<f64> = None;
+
+    /// The reason that is send to the peer when the session lasted longer than `MAX_SESSION_DURATION_S`. When this is `None`, the connector is closed without telling the peer.
+    const SESSION_EXPIRED_REASON: Option<DisconnectReason> = None;
+
     /// The time that it takes before this connector assumes it has lost connection to the other connector
     const RECEIVE_PING_TIMEOUT_S: f64 = Self::PING_INTERVAL_S * 3.;
 
     /// The time that it takes before this connector assumes it has lost connection to the other connector
     const SEND_PING_TIMEOUT_S: f64 = Self::PING_INTERVAL_S * 3.;
+
+    /// The weights of the channels that confirmed messages can be send on, see `Connector::send_confirmed_on`. The amount of channels is the length of this slice.
+    ///
+    /// When unconfirmed packets are being resend, the `RETRANSMIT_BUDGET_BYTES` is divided over the channels in a weighted round-robin fashion. A channel with weight 3 gets to resend 3 packets for every packet of a channel with weight 1, so a backlogged channel can not starve the other channels.
+    const CHANNEL_WEIGHTS: &'static [u32] = &[1];
+
+    /// The maximum amount of bytes of unconfirmed packets that are resend in a single call to `Connector::update`. Packets that do not fit in this budget are resend in a later update.
+    const RETRANSMIT_BUDGET_BYTES: usize = usize::MAX;
+
+    /// How long in seconds unconfirmed packets are resend at half the rate after congestion was reported. See `Connector::report_congestion`.
+    const CONGESTION_BACKOFF_S: f64 = 1.;
+
+    /// The length in seconds of the sliding window that `Connector::estimated_loss_rate` looks at. A shorter window reacts faster to changing conditions, but is more noisy.
+    const LOSS_WINDOW_S: f64 = 10.;
+
+    /// Decides which datagrams are accepted by `Connector::receive_from`, based on the address they were received from. By default only datagrams from the exact peer address are accepted.
+    const ADDRESS_FILTER: AddressFilter = AddressFilter::Exact;
+
+    /// The maximum size in bytes of a single incoming packet. Larger packets are rejected by `Connector::handle_incoming_data` with an error.
+    ///
+    /// Deserializing never reads past the end of a packet, so this also limits how much memory a malicious packet can make us allocate, e.g. by claiming to contain a `String` with a huge length. The default allows any packet that fits in a UDP datagram.
+    const MAX_DESERIALIZE_BYTES: u64 = 64 * 1024;
+
+    /// The maximum size in bytes of a datagram in both directions. This is the default of `MAX_SEND_DATAGRAM_SIZE` and `MAX_RECEIVE_DATAGRAM_SIZE`, set those instead when the link has a different MTU in each direction.
+    ///
+    /// The default is safely below the MTU of most networks, so datagrams are not fragmented on the way.
+    const MAX_DATAGRAM_SIZE: usize = 1024;
+
+    /// The maximum size in bytes of a datagram that is send. `Connector::flush` packs buffered packets into datagrams of at most this size, and a `ReliableStream` splits its data into chunks that fit in it.
+    const MAX_SEND_DATAGRAM_SIZE: usize = Self::MAX_DATAGRAM_SIZE;
+
+    /// The maximum size in bytes of a datagram that is received. `Connector::receive_from` can receive datagrams up to this size, larger datagrams are cut off by the socket. This should be at least the `MAX_SEND_DATAGRAM_SIZE` of the peer.
+    const MAX_RECEIVE_DATAGRAM_SIZE: usize = Self::MAX_DATAGRAM_SIZE;
+
+    /// When enabled, `Connector::send_unconfirmed` and `Connector::send_confirmed` do not send the message right away, but add it to an outgoing buffer. Call `Connector::flush` to send all buffered messages in as few datagrams as possible, e.g. once per frame.
+    ///
+    /// Control packets like pings and confirmations are always send right away.
+    const BUFFER_SENDS: bool = false;
+
+    /// What happens with a confirmed message that is send before the connection is established: after `Connector::connect` until the peer answered the first ping, or while `Connector::state` is not `NetworkState::Connected`. The peer may not know us yet, so the message could be ignored or dropped.
+    ///
+    /// By default the message is send right away, and resend until the peer confirms it. `SendBeforeConnected::Buffer` is convenient for a client that sends its login before the handshake completed.
+    const SEND_BEFORE_CONNECTED: SendBeforeConnected = SendBeforeConnected::Send;
+
+    /// When enabled, every packet in a datagram with multiple packets gets a checksum, see `Connector::flush`. When a packet in such a datagram is corrupted on the way, only that packet is dropped and counted in `Stats::corrupt_batch_entries`, the other packets are still handled. Without checksums, a corrupt packet can make the packets after it unreadable.
+    ///
+    /// This takes 4 more bytes per packet. The peer must enable this as well, a connector without it rejects these datagrams as malformed.
+    const CHECKSUMMED_BATCHES: bool = false;
+
+    /// Pads every outgoing datagram with random bytes, so the size of a datagram does not leak information about its content. Both connectors must use the same setting, as padded datagrams start with a length prefix that is used to strip the padding.
+    ///
+    /// This costs bandwidth, so it is disabled by default.
+    const PADDING: Padding = Padding::None;
+
+    /// The maximum amount of messages that are kept while waiting for an earlier message, when messages are delivered in order by a `ReliableStream`. If more messages arrive out of order, the earlier message is considered lost for good, and the connection is closed with `Error::ReorderBufferFull`.
+    const MAX_REORDER_BUFFER: usize = 1024;
+
+    /// The maximum amount of events that are kept by the connector until they are drained. When this amount is exceeded, the oldest events are discarded.
+    const EVENT_QUEUE_CAPACITY: usize = 1024;
+
+    /// Queue the received messages as `Event::Message`, instead of returning them from `Connector::handle_incoming_data` and the other functions that receive data. Only `Connector::handle_incoming_data_borrowed` still returns its messages, as they borrow from the datagram. This lets one part of the application do the I/O, while another part handles the messages together with the other events, see `Connector::drain_events`.
+    const QUEUE_MESSAGES: bool = false;
+}
+
+/// Decides which datagrams are accepted by `Connector::receive_from`, based on the address they were received from. See `ConnectorParam::ADDRESS_FILTER`.
+#[derive(Debug, Clone, Copy)]
+pub enum AddressFilter {
+    /// Only accept datagrams from exactly the peer address
+    Exact,
+
+    /// Accept datagrams from the IP address of the peer, from any port. This is useful when the peer is behind a NAT or load balancer that may change the source port.
+    IpOnly,
+
+    /// Accept datagrams when the function returns `true`. The function is called with the peer address and the address the datagram was received from.
+    Custom(fn(SocketAddr, SocketAddr) -> bool),
+}
+
+impl AddressFilter {
+    /// Returns `true` if a datagram from `source` is accepted for a connector that is bound to `peer`
+    pub fn matches(&self, peer: SocketAddr, source: SocketAddr) -> bool {
+        match self {
+            AddressFilter::Exact => peer == source,
+            AddressFilter::IpOnly => peer.ip() == source.ip(),
+            AddressFilter::Custom(filter) => filter(peer, source),
+        }
+    }
+}
+
+/// What happens with a confirmed message that is send before the connection is established. See `ConnectorParam::SEND_BEFORE_CONNECTED`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SendBeforeConnected {
+    /// The message is send right away, and resend until the peer confirms it
+    Send,
+
+    /// The message is kept, and send once the connection is established. It already gets its id, so it can be tracked like any other message.
+    Buffer,
+
+    /// The message is not send, and `Error::NotConnected` is returned
+    Reject,
+}
+
+/// The order in which missing packets are requested. See `ConnectorParam::MISSING_REQUEST_ORDER`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MissingRequestOrder {
+    /// Request the packet with the lowest id first
+    Oldest,
+
+    /// Request the packet with the highest id first, even if it was requested more recently than the others. This minimizes the latency of the most recent data, but with `ConnectorParam::MAX_REQUESTS_PER_UPDATE` older packets wait until the newer ones arrived.
+    Newest,
+}
+
+/// How outgoing datagrams are padded. See `ConnectorParam::PADDING`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Padding {
+    /// Datagrams are not padded
+    None,
+
+    /// Datagrams are padded to a multiple of the given size. Datagrams that are not larger than this size all have the same size.
+    Fixed(usize),
+
+    /// Datagrams are padded to the next power of two, so only the rough size of a datagram is visible
+    PowerOfTwo,
+}
+
+impl Padding {
+    /// The size in bytes of a datagram of `len` bytes after it is padded, including the 4 byte length prefix
+    pub fn padded_size(&self, len: usize) -> usize {
+        match self {
+            Padding::None => len,
+            Padding::Fixed(size) => {
+                let size = (*size).max(1);
+                (len + 4).div_ceil(size) * size
+            }
+            Padding::PowerOfTwo => (len + 4).next_power_of_two(),
+        }
+    }
 }
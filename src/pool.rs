@@ -0,0 +1,200 @@
+use crate::{Connector, ConnectorParam, DisconnectReason, Event, NetworkState, Result, Socket};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+
+/// A collection of connectors for a server, with a connector for every peer address.
+///
+/// The server receives datagrams from its own socket, and passes them to `handle_incoming_data` with the address they were received from. A connector is created for a peer once its handshake arrives, see `Packet::Hello`.
+///
+/// The application can also give a peer a key of its own, like the id of the user that logged in, with `associate_key`. The connector can then be found with `get_by_key`, without knowing the address of the peer.
+pub struct ConnectorPool<TParam: ConnectorParam, TKey = ()> {
+    connectors: HashMap<SocketAddr, Connector<TParam>>,
+    keys: HashMap<TKey, SocketAddr>,
+    /// The buffer that `receive_from` reads every datagram into, shared by all connectors
+    buffer: Vec<u8>,
+    /// The maximum amount of peers, see `set_max_peers`
+    max_peers: Option<usize>,
+}
+
+impl<TParam: ConnectorParam, TKey> Default for ConnectorPool<TParam, TKey> {
+    fn default() -> Self {
+        ConnectorPool {
+            connectors: HashMap::new(),
+            keys: HashMap::new(),
+            buffer: Connector::<TParam>::receive_buffer(),
+            max_peers: None,
+        }
+    }
+}
+
+impl<TParam: ConnectorParam, TKey: Hash + Eq> ConnectorPool<TParam, TKey> {
+    /// Create an empty pool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum amount of peers in the pool. While the pool is full, the handshake of a new peer is ignored by `handle_incoming_data`, so a flood of connecting peers can not take up unbounded memory. Peers that are already in the pool are not removed. `None`, the default, disables the limit.
+    pub fn set_max_peers(&mut self, max_peers: Option<usize>) {
+        self.max_peers = max_peers;
+    }
+
+    /// Get the connector of the given peer, if there is one
+    pub fn get(&self, peer_addr: SocketAddr) -> Option<&Connector<TParam>> {
+        self.connectors.get(&peer_addr)
+    }
+
+    /// Get the connector of the given peer, if there is one
+    pub fn get_mut(&mut self, peer_addr: SocketAddr) -> Option<&mut Connector<TParam>> {
+        self.connectors.get_mut(&peer_addr)
+    }
+
+    /// Get the connector of the given peer, creating it with `Connector::bound_to` if there is none
+    pub fn get_or_insert(&mut self, peer_addr: SocketAddr) -> &mut Connector<TParam> {
+        self.connectors
+            .entry(peer_addr)
+            .or_insert_with(|| Connector::bound_to(peer_addr))
+    }
+
+    /// Remove the connector of the given peer from the pool, e.g. after it disconnected. The key of the peer is removed as well, see `associate_key`.
+    pub fn remove(&mut self, peer_addr: SocketAddr) -> Option<Connector<TParam>> {
+        self.keys.retain(|_, addr| *addr != peer_addr);
+        self.connectors.remove(&peer_addr)
+    }
+
+    /// Give the peer a key of the application, e.g. the id of the user after it logged in, so its connector can be found with `get_by_key`. A peer has at most one key, and a key belongs to at most one peer, so an older association of either is replaced.
+    ///
+    /// Returns `false` if the peer is not in the pool, in which case nothing is associated.
+    pub fn associate_key(&mut self, peer_addr: SocketAddr, key: TKey) -> bool {
+        if !self.connectors.contains_key(&peer_addr) {
+            return false;
+        }
+        self.keys.retain(|_, addr| *addr != peer_addr);
+        self.keys.insert(key, peer_addr);
+        true
+    }
+
+    /// Get the address of the peer with the given key, see `associate_key`
+    pub fn peer_addr_of(&self, key: &TKey) -> Option<SocketAddr> {
+        self.keys.get(key).copied()
+    }
+
+    /// Get the connector of the peer with the given key, see `associate_key`
+    pub fn get_by_key(&self, key: &TKey) -> Option<&Connector<TParam>> {
+        self.connectors.get(self.keys.get(key)?)
+    }
+
+    /// Get the connector of the peer with the given key, see `associate_key`
+    pub fn get_by_key_mut(&mut self, key: &TKey) -> Option<&mut Connector<TParam>> {
+        self.connectors.get_mut(self.keys.get(key)?)
+    }
+
+    /// Disconnect the given peer immediately with `Connector::force_disconnect`, and remove its connector from the pool. Returns the removed connector, or `None` if the peer was not in the pool.
+    ///
+    /// The connector is only removed once the `Packet::Disconnect` is send. If sending fails, the error is returned and the connector stays in the pool, closed, so the call can be retried.
+    ///
+    /// A datagram that is received from the peer afterwards creates a new connector, so an application that bans the peer should stop passing its datagrams to the pool.
+    pub fn force_disconnect(
+        &mut self,
+        socket: &mut dyn Socket,
+        peer_addr: SocketAddr,
+        reason: Option<DisconnectReason>,
+    ) -> Result<Option<Connector<TParam>>> {
+        match self.connectors.get_mut(&peer_addr) {
+            Some(connector) => {
+                connector.force_disconnect(socket, reason)?;
+                Ok(self.remove(peer_addr))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Handle a datagram that was received from the given peer, see `Connector::handle_incoming_data`.
+    ///
+    /// A connector is only created for a peer that is not in the pool yet when the datagram contains its handshake, see `Connector::connect`, the connector could handle it, and the pool is not full, see `set_max_peers`. The connector then queues an `Event::NewPeer`, so the application can set up the state of the peer. Other datagrams of unknown peers are ignored, so stray or spoofed datagrams do not take up a connector.
+    pub fn handle_incoming_data(
+        &mut self,
+        socket: &mut dyn Socket,
+        peer_addr: SocketAddr,
+        data: &[u8],
+    ) -> Result<Vec<TParam::TReceive>> {
+        if let Some(connector) = self.connectors.get_mut(&peer_addr) {
+            return connector.handle_incoming_data(socket, data);
+        }
+        let full = self
+            .max_peers
+            .is_some_and(|max| self.connectors.len() >= max);
+        if full || !Connector::<TParam>::is_handshake(data) {
+            return Ok(Vec::new());
+        }
+        let mut connector = Connector::bound_to(peer_addr);
+        connector.push_event(Event::NewPeer(peer_addr));
+        let messages = connector.handle_incoming_data(socket, data)?;
+        // The handshake was in the datagram, but it may have been dropped as corrupt
+        if connector.receive.peer_session.is_none() {
+            return Ok(Vec::new());
+        }
+        self.connectors.insert(peer_addr, connector);
+        Ok(messages)
+    }
+
+    /// Receive every datagram that is waiting on the socket, and pass it to the connector of the peer it came from, like `handle_incoming_data`. Returns the received messages with the address of the peer that send them.
+    ///
+    /// All datagrams are read into a single buffer that is owned by the pool, so a server with many peers does not need a buffer for every connector. A datagram that a connector can not handle is skipped, and reported as `Event::MalformedPacket` by that connector. Only an error of the socket itself is returned.
+    pub fn receive_from(
+        &mut self,
+        socket: &mut dyn Socket,
+    ) -> Result<Vec<(SocketAddr, TParam::TReceive)>> {
+        let local_addr = socket.local_addr();
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let mut result = Vec::new();
+        let error = loop {
+            let (count, peer_addr) = match socket.recv_from(&mut buffer) {
+                Ok((_, peer_addr)) if peer_addr == local_addr => continue,
+                Ok(received) => received,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break None,
+                Err(e) => break Some(e),
+            };
+            if let Ok(messages) = self.handle_incoming_data(socket, peer_addr, &buffer[..count]) {
+                result.extend(messages.into_iter().map(|message| (peer_addr, message)));
+            }
+        };
+        self.buffer = buffer;
+        match error {
+            Some(e) => Err(e.into()),
+            None => Ok(result),
+        }
+    }
+
+    /// Update every connector in the pool, see `Connector::update`. A connector that fails does not keep the others from being updated, the first error is returned once every connector was updated.
+    pub fn update(&mut self, socket: &mut dyn Socket) -> Result<()> {
+        let mut result = Ok(());
+        for connector in self.connectors.values_mut() {
+            if let (Err(e), Ok(())) = (connector.update(socket), &result) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Iterate over the connectors in the pool with their peer address, in no particular order
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (SocketAddr, &mut Connector<TParam>)> {
+        self.connectors
+            .iter_mut()
+            .map(|(peer_addr, connector)| (*peer_addr, connector))
+    }
+
+    /// Iterate over the addresses of the peers that are connected, in no particular order. See `Connector::state` for more info.
+    pub fn connected_peers(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.connectors
+            .iter()
+            .filter(|(_, connector)| connector.state() == NetworkState::Connected)
+            .map(|(peer_addr, _)| *peer_addr)
+    }
+
+    /// The amount of peers in the pool, whether they are connected or not
+    pub fn peer_count(&self) -> usize {
+        self.connectors.len()
+    }
+}
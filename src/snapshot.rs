@@ -0,0 +1,272 @@
+use crate::clock::Clock;
+use crate::{
+    CachedPacket, Connector, ConnectorParam, MissingId, NetworkState, Result, Stats, Timing,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::num::NonZeroU64;
+use std::time::{Duration, Instant};
+
+/// The state of a connector, as written by `Connector::serialize_state`. Every moment is stored as its age, the time between that moment and the moment the snapshot was made, so it can be restored in a process with a different clock.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    peer_addr: SocketAddr,
+    local_addr: Option<SocketAddr>,
+    timing: Timing,
+    stats: Stats,
+    closed: bool,
+    connect_count: u32,
+    last_state: NetworkState,
+    connected_since: Option<Duration>,
+    session_started: Duration,
+    handshake_started: Option<Duration>,
+    congested_at: Option<Duration>,
+    next_ping_nonce: u32,
+    next_sequences: BTreeMap<u32, u64>,
+    latest_unconfirmed: BTreeMap<Option<u32>, Vec<u8>>,
+    recent_sends: Vec<(Duration, bool)>,
+
+    // The sending half
+    unconfirmed: Vec<CachedSnapshot>,
+    next_message_id: Option<NonZeroU64>,
+    ids_exhausted: bool,
+    send_epoch: u32,
+    wrap_pending: bool,
+    session: u32,
+    last_ping_sent: Duration,
+    outstanding_pings: Vec<(u32, Duration)>,
+    last_rtt: Option<Duration>,
+    clock_samples: Vec<(Duration, f64)>,
+    outgoing_buffer: Vec<(Option<u32>, Vec<u8>)>,
+    recently_confirmed: Vec<NonZeroU64>,
+
+    // The receiving half
+    last_message_id: Option<NonZeroU64>,
+    highest_received_id: Option<NonZeroU64>,
+    peer_last_sent_id: Option<NonZeroU64>,
+    missing: Vec<(NonZeroU64, Duration)>,
+    last_ping_received: Duration,
+    pending_acks: Vec<NonZeroU64>,
+    pending_acks_since: Option<Duration>,
+    latest_sequences: BTreeMap<u32, u64>,
+    stream_received: BTreeMap<u32, u64>,
+    next_sequence_ack_stream: u32,
+    receive_epoch: u32,
+    peer_session: Option<u32>,
+    peer_capabilities: Option<u32>,
+    capabilities_acknowledged: bool,
+
+    // Idle mode
+    last_data: Duration,
+    peer_quiet: bool,
+    peer_acknowledged: bool,
+    idle_last_sent: Option<bool>,
+    idle_active: bool,
+}
+
+/// A confirmed message that was not confirmed yet, see `CachedPacket`
+#[derive(Serialize, Deserialize)]
+struct CachedSnapshot {
+    id: NonZeroU64,
+    /// The encoded `Packet`, see `CachedPacket::bytes`
+    bytes: Vec<u8>,
+    last_emit: Duration,
+    channel: usize,
+    /// The time that was left until the deadline, which is 0 if it already passed
+    deadline: Option<Duration>,
+    held: bool,
+}
+
+impl<TParam: ConnectorParam> Connector<TParam> {
+    /// Serialize the state of this connector, so it can be restored with `deserialize_state`, e.g. after reloading the code during development. The peer does not notice this, as long as the connector is restored before the connection times out.
+    ///
+    /// This includes the message ids, the confirmed messages that were not confirmed yet, the ids the peer confirmed recently, the pings that were not answered yet, the measurements of `estimated_clock_offset`, the missing messages, the ids that still have to be confirmed to the peer, the timing, the stats and the messages that are waiting for `flush`. Every timer is stored as the time that passed since it started, and continues from there when restored.
+    ///
+    /// Not included are queued messages and events, so drain those first, and the acknowledgements of `drain_acknowledgements`. The callbacks set with `on_state_change`, `set_request_selector` and `set_send_interceptor` have to be set again, and a capture that is running is stopped. The futures of `send_confirmed_await` fail, but the messages themselves are still delivered. A paused connector is restored as resumed, with its timers frozen at the moment it was paused.
+    pub fn serialize_state(&self) -> Result<Vec<u8>> {
+        let now = self.now();
+        let age = |moment: Instant| now.saturating_duration_since(moment);
+        let unconfirmed = self
+            .send
+            .unconfirmed_message_cache
+            .iter()
+            .map(|(id, cached)| {
+                Ok(CachedSnapshot {
+                    id: *id,
+                    bytes: cached.bytes.clone(),
+                    last_emit: age(cached.last_emit),
+                    channel: cached.channel,
+                    deadline: cached
+                        .deadline
+                        .map(|deadline| deadline.saturating_duration_since(now)),
+                    held: cached.held,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let snapshot = Snapshot {
+            peer_addr: self.peer_addr,
+            local_addr: self.local_addr,
+            timing: self.timing,
+            stats: self.stats.clone(),
+            closed: self.closed,
+            connect_count: self.connect_count,
+            last_state: self.last_state,
+            connected_since: self.connected_since.map(age),
+            session_started: age(self.session_started),
+            handshake_started: self.handshake_started.map(age),
+            congested_at: self.congested_at.map(age),
+            next_ping_nonce: self.next_ping_nonce,
+            next_sequences: self.next_sequences.clone(),
+            latest_unconfirmed: self.latest_unconfirmed.clone(),
+            recent_sends: self
+                .recent_sends
+                .iter()
+                .map(|(moment, retransmit)| (age(*moment), *retransmit))
+                .collect(),
+            unconfirmed,
+            next_message_id: self.send.next_message_id,
+            ids_exhausted: self.send.ids_exhausted,
+            send_epoch: self.send.epoch,
+            wrap_pending: self.send.wrap_pending,
+            session: self.send.session,
+            last_ping_sent: age(self.send.last_ping),
+            outstanding_pings: self
+                .send
+                .outstanding_pings
+                .iter()
+                .map(|(nonce, send)| (*nonce, age(*send)))
+                .collect(),
+            last_rtt: self.send.last_rtt,
+            clock_samples: self.send.clock_samples.iter().copied().collect(),
+            outgoing_buffer: self.send.outgoing_buffer.clone(),
+            recently_confirmed: self.send.recently_confirmed.iter().copied().collect(),
+            last_message_id: self.receive.last_message_id,
+            highest_received_id: self.receive.highest_received_id,
+            peer_last_sent_id: self.receive.peer_last_sent_id,
+            missing: self
+                .receive
+                .missing_message_id_list
+                .iter()
+                .map(|missing| (missing.id, age(missing.last_request)))
+                .collect(),
+            last_ping_received: age(self.receive.last_ping),
+            pending_acks: self.receive.pending_acks.clone(),
+            pending_acks_since: self.receive.pending_acks_since.map(age),
+            latest_sequences: self.receive.latest_sequences.clone(),
+            stream_received: self.receive.stream_received.clone(),
+            next_sequence_ack_stream: self.receive.next_sequence_ack_stream,
+            receive_epoch: self.receive.epoch,
+            peer_session: self.receive.peer_session,
+            peer_capabilities: self.receive.peer_capabilities,
+            capabilities_acknowledged: self.receive.capabilities_acknowledged,
+            last_data: age(self.idle.last_data),
+            peer_quiet: self.idle.peer_quiet,
+            peer_acknowledged: self.idle.peer_acknowledged,
+            idle_last_sent: self.idle.last_sent,
+            idle_active: self.idle.active,
+        };
+        Ok(bincode::serialize(&snapshot)?)
+    }
+
+    /// Restore a connector from the state that was serialized with `serialize_state`. See there for what is restored.
+    ///
+    /// The state must be serialized by the same version of this crate, with the same `ConnectorParam`. Otherwise this returns an error, or restores a connector that does not match the peer.
+    pub fn deserialize_state(bytes: &[u8]) -> Result<Self> {
+        Self::restore(bytes, Clock::System)
+    }
+
+    /// Restore a connector like `deserialize_state`, with the given clock
+    pub(crate) fn restore(bytes: &[u8], clock: Clock) -> Result<Self> {
+        let snapshot: Snapshot = bincode::deserialize(bytes)?;
+        let now = clock.now();
+        // A moment from before the clock started is clamped to the start
+        let moment = |age: Duration| now.checked_sub(age).unwrap_or(now);
+
+        let mut connector = Self::bound_to(snapshot.peer_addr);
+        connector.clock = clock;
+        connector.local_addr = snapshot.local_addr;
+        connector.timing = snapshot.timing;
+        connector.stats = snapshot.stats;
+        connector.closed = snapshot.closed;
+        connector.connect_count = snapshot.connect_count;
+        connector.last_state = snapshot.last_state;
+        connector.connected_since = snapshot.connected_since.map(moment);
+        connector.session_started = moment(snapshot.session_started);
+        connector.handshake_started = snapshot.handshake_started.map(moment);
+        connector.congested_at = snapshot.congested_at.map(moment);
+        connector.next_ping_nonce = snapshot.next_ping_nonce;
+        connector.next_sequences = snapshot.next_sequences;
+        connector.latest_unconfirmed = snapshot.latest_unconfirmed;
+        connector.recent_sends = snapshot
+            .recent_sends
+            .into_iter()
+            .map(|(age, retransmit)| (moment(age), retransmit))
+            .collect::<VecDeque<_>>();
+
+        let send = &mut connector.send;
+        for cached in snapshot.unconfirmed {
+            send.cache(
+                cached.id,
+                CachedPacket {
+                    bytes: cached.bytes,
+                    last_emit: moment(cached.last_emit),
+                    channel: cached.channel,
+                    deadline: cached.deadline.map(|left| now + left),
+                    held: cached.held,
+                },
+            );
+        }
+        send.next_message_id = snapshot.next_message_id;
+        send.ids_exhausted = snapshot.ids_exhausted;
+        send.epoch = snapshot.send_epoch;
+        send.wrap_pending = snapshot.wrap_pending;
+        send.session = snapshot.session;
+        send.last_ping = moment(snapshot.last_ping_sent);
+        send.outstanding_pings = snapshot
+            .outstanding_pings
+            .into_iter()
+            .map(|(nonce, age)| (nonce, moment(age)))
+            .collect();
+        send.last_rtt = snapshot.last_rtt;
+        send.clock_samples = snapshot.clock_samples.into();
+        send.outgoing_buffer = snapshot.outgoing_buffer;
+        send.recently_confirmed = snapshot.recently_confirmed.into();
+
+        let receive = &mut connector.receive;
+        receive.last_message_id = snapshot.last_message_id;
+        receive.highest_received_id = snapshot.highest_received_id;
+        receive.peer_last_sent_id = snapshot.peer_last_sent_id;
+        receive.missing_message_id_list = snapshot
+            .missing
+            .into_iter()
+            .map(|(id, age)| MissingId {
+                id,
+                last_request: moment(age),
+            })
+            .collect();
+        receive
+            .missing_message_id_list
+            .sort_unstable_by_key(|missing| missing.id);
+        receive
+            .missing_message_id_list
+            .dedup_by_key(|missing| missing.id);
+        receive.last_ping = moment(snapshot.last_ping_received);
+        receive.pending_acks = snapshot.pending_acks;
+        receive.pending_acks_since = snapshot.pending_acks_since.map(moment);
+        receive.latest_sequences = snapshot.latest_sequences;
+        receive.stream_received = snapshot.stream_received;
+        receive.next_sequence_ack_stream = snapshot.next_sequence_ack_stream;
+        receive.epoch = snapshot.receive_epoch;
+        receive.peer_session = snapshot.peer_session;
+        receive.peer_capabilities = snapshot.peer_capabilities;
+        receive.capabilities_acknowledged = snapshot.capabilities_acknowledged;
+
+        let idle = &mut connector.idle;
+        idle.last_data = moment(snapshot.last_data);
+        idle.peer_quiet = snapshot.peer_quiet;
+        idle.peer_acknowledged = snapshot.peer_acknowledged;
+        idle.last_sent = snapshot.idle_last_sent;
+        idle.active = snapshot.idle_active;
+        Ok(connector)
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+/// Statistics about the traffic of a connector. See `Connector::stats`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The amount of packets that `Connector::update` could not send because the send buffer of the socket was full. These packets are send in a later update instead.
+    pub deferred_sends: u64,
+
+    /// The amount of confirmed messages that were send, not counting retransmissions
+    pub confirmed_sends: u64,
+
+    /// The amount of times a confirmed message was resend, because it was not confirmed in time or because the peer requested it
+    pub retransmits: u64,
+
+    /// The amount of times congestion was reported, see `Connector::report_congestion`
+    pub congestion_signals: u64,
+
+    /// The amount of datagrams that were received from the peer
+    pub received_datagrams: u64,
+
+    /// The amount of control packets that were send: pongs, confirmations, requests for missing packets, and replies to requests of the peer
+    pub control_packets_sent: u64,
+
+    /// The amount of control packets that were not send, because they would exceed `ConnectorParam::MAX_AMPLIFICATION`
+    pub suppressed_control_packets: u64,
+
+    /// The amount of batched datagrams that contained a malformed packet, or a packet that failed to be handled. The packets before that packet are handled, the packets after it are dropped.
+    pub malformed_batches: u64,
+
+    /// The amount of confirmations that were ignored, because they confirmed a message that was never send. This means the peer is buggy or malicious.
+    pub invalid_confirmations: u64,
+
+    /// The amount of times the peer reported that a message is not available, while it was not requested, see `Anomaly::UnexpectedPacketNotFound`
+    pub unexpected_not_founds: u64,
+
+    /// The amount of pongs that did not answer a recent ping, see `Anomaly::UnmatchedPong`
+    pub unmatched_pongs: u64,
+
+    /// The amount of times the peer reported a lower last send message id than before, e.g. because its pings arrived out of order. See `Anomaly::IdRegression`.
+    pub id_regressions: u64,
+
+    /// The amount of messages and pings that were rejected because their id was too far ahead of the last received message, or would make us track more missing ids than `ConnectorParam::MAX_AMPLIFICATION` allows, see `Anomaly::IdGapTooLarge`
+    pub ids_too_far_ahead: u64,
+
+    /// The amount of messages that were dropped because a newer message on the same stream arrived first, see `Connector::send_unconfirmed_ordered`
+    pub out_of_order_drops: u64,
+
+    /// The amount of messages that were dropped because they were send on a new stream while `ConnectorParam::MAX_STREAMS` streams were already tracked
+    pub stream_limit_drops: u64,
+
+    /// The amount of datagrams, or packets after a `Packet::Disconnect` in the same datagram, that were dropped because the connector was closed, see `Connector::close`
+    pub closed_drops: u64,
+
+    /// The amount of confirmed messages that were received again, because the peer did not receive our confirmation. These are confirmed again, but not returned a second time.
+    pub duplicate_messages: u64,
+
+    /// The amount of packets that were skipped because they are unknown to this version, see `ConnectorParam::IGNORE_UNKNOWN_PACKETS`
+    pub unknown_packets: u64,
+
+    /// The amount of packets that were dropped from a batched datagram because they were corrupt, see `ConnectorParam::CHECKSUMMED_BATCHES`
+    pub corrupt_batch_entries: u64,
+
+    /// The amount of packets that were send, by type. Every packet in a batched datagram is counted separately.
+    pub sent_packet_types: PacketTypeCounts,
+
+    /// The amount of packets that were received and could be read, by type. Every packet in a batched datagram is counted separately.
+    pub received_packet_types: PacketTypeCounts,
+
+    /// What the peer reported about the messages it received on every stream of `Connector::send_unconfirmed_ordered`, see `ConnectorParam::SEQUENCE_ACKS`
+    pub stream_feedback: BTreeMap<u32, StreamFeedback>,
+}
+
+/// The amount of packets of every type, in one direction. See `Stats::sent_packet_types` and `Stats::received_packet_types`.
+///
+/// This shows what the traffic consists of, e.g. a connection that mostly sends requests for missing packets is losing a lot of packets.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketTypeCounts {
+    /// The amount of pings
+    pub pings: u64,
+
+    /// The amount of pongs, the replies to pings
+    pub pongs: u64,
+
+    /// The amount of requests for a missing packet
+    pub requests: u64,
+
+    /// The amount of confirmations of received messages, both of single messages and of every message up to an id
+    pub confirms: u64,
+
+    /// The amount of replies that a requested packet is no longer available
+    pub not_founds: u64,
+
+    /// The amount of packets with a message, confirmed, unconfirmed and ordered
+    pub data: u64,
+
+    /// The amount of other packets, e.g. disconnects
+    pub other: u64,
+}
+
+/// What the peer reported about the messages it received on a stream of `Connector::send_unconfirmed_ordered`, see `ConnectorParam::SEQUENCE_ACKS` and `Stats::stream_feedback`.
+///
+/// This is useful to adapt the amount of data that is send on the stream, e.g. the bitrate of a video stream, without making the stream reliable.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamFeedback {
+    /// The highest sequence number of the messages that the peer received. The first message on a stream has sequence 0.
+    pub highest_sequence: u64,
+
+    /// The amount of messages on this stream that the peer received, including messages that it dropped because they arrived out of order
+    pub received: u64,
+
+    /// The amount of messages that were send between the last two reports of the peer
+    pub window_sent: u64,
+
+    /// The amount of messages that the peer received between its last two reports
+    pub window_received: u64,
+}
+
+impl StreamFeedback {
+    /// The ratio of the messages that were lost between the last two reports of the peer. This is a value between 0 and 1.
+    pub fn loss_rate(&self) -> f64 {
+        if self.window_sent == 0 {
+            return 0.;
+        }
+        1. - self.window_received.min(self.window_sent) as f64 / self.window_sent as f64
+    }
+
+    /// Update this feedback with a new report of the peer. A report that is not newer than the last one, e.g. because it arrived out of order, is ignored.
+    pub(crate) fn report(&mut self, sequence: u64, received: u64) {
+        if self.received == 0 {
+            *self = StreamFeedback {
+                highest_sequence: sequence,
+                received,
+                window_sent: sequence.saturating_add(1),
+                window_received: received,
+            };
+        } else if sequence > self.highest_sequence {
+            *self = StreamFeedback {
+                highest_sequence: sequence,
+                received,
+                window_sent: sequence - self.highest_sequence,
+                // The peer started counting again after a reconnect
+                window_received: received.checked_sub(self.received).unwrap_or(received),
+            };
+        }
+    }
+}
+
+impl PacketTypeCounts {
+    /// The amount of packets of all types
+    pub fn total(&self) -> u64 {
+        self.pings
+            + self.pongs
+            + self.requests
+            + self.confirms
+            + self.not_founds
+            + self.data
+            + self.other
+    }
+}
+
+impl Stats {
+    /// The amount of control packets that were send for every datagram that was received. A high value means the peer makes us send a lot more than it sends itself, see `ConnectorParam::MAX_AMPLIFICATION`.
+    pub fn amplification_factor(&self) -> f64 {
+        self.control_packets_sent as f64 / self.received_datagrams.max(1) as f64
+    }
+}
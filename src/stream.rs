@@ -0,0 +1,172 @@
+use crate::{Connector, ConnectorParam, Error, Socket};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+
+/// A piece of a byte stream that is send by a `ReliableStream`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StreamChunk {
+    /// The position of the first byte of this chunk in the stream
+    pub offset: u64,
+
+    /// The bytes in this chunk
+    pub data: Vec<u8>,
+}
+
+/// The amount of bytes a `StreamChunk` message takes in a datagram, without the bytes of the chunk itself
+const CHUNK_OVERHEAD: usize = 4 + 9 + 1 + 8 + 8;
+
+/// An adapter that implements `Read` and `Write` on top of a connector, for code that expects a stream like a `TcpStream`.
+///
+/// Written bytes are split into chunks that fit in a single datagram, and every chunk is send as a confirmed message. Confirmed messages may arrive in any order, so every chunk contains its position in the stream, and the reading side buffers chunks until all bytes before them have arrived. A chunk is resend with the same message id and offset, so a chunk that arrives more than once is placed at the same position, and its bytes are only read once.
+///
+/// Both sides of the connection must use a `ReliableStream`, with a connector that sends and receives `StreamChunk`. At most `ConnectorParam::MAX_REORDER_BUFFER` chunks are kept while waiting for an earlier chunk.
+///
+/// Like a non-blocking socket, `read` returns an error with `ErrorKind::WouldBlock` when no bytes are available yet, and `write` never waits for the peer. `flush` sends any messages that are buffered by `ConnectorParam::BUFFER_SENDS`, but does not wait for the peer to confirm them.
+pub struct ReliableStream<TParam: ConnectorParam, TSocket: Socket> {
+    connector: Connector<TParam>,
+    socket: TSocket,
+    write_offset: u64,
+    read_offset: u64,
+    /// Chunks that arrived before all bytes before them arrived
+    pending: BTreeMap<u64, Vec<u8>>,
+    /// Bytes that arrived in order, but were not read yet
+    readable: VecDeque<u8>,
+}
+
+impl<TParam, TSocket> ReliableStream<TParam, TSocket>
+where
+    TParam: ConnectorParam<TSend = StreamChunk, TReceive = StreamChunk>,
+    TSocket: Socket,
+{
+    /// Create a stream that sends and receives through the given connector and socket
+    pub fn new(connector: Connector<TParam>, socket: TSocket) -> Self {
+        ReliableStream {
+            connector,
+            socket,
+            write_offset: 0,
+            read_offset: 0,
+            pending: BTreeMap::new(),
+            readable: VecDeque::new(),
+        }
+    }
+
+    /// Get a reference to the connector of this stream
+    pub fn connector(&self) -> &Connector<TParam> {
+        &self.connector
+    }
+
+    /// Get a mutable reference to the connector of this stream
+    pub fn connector_mut(&mut self) -> &mut Connector<TParam> {
+        &mut self.connector
+    }
+
+    /// Get a mutable reference to the socket of this stream
+    pub fn socket_mut(&mut self) -> &mut TSocket {
+        &mut self.socket
+    }
+
+    /// Consume this stream, returning the connector and socket. Any bytes that were received but not read yet are lost.
+    pub fn into_inner(self) -> (Connector<TParam>, TSocket) {
+        (self.connector, self.socket)
+    }
+
+    /// The maximum amount of bytes in a single chunk, so that every chunk fits in a single datagram
+    fn chunk_size() -> usize {
+        TParam::MAX_SEND_DATAGRAM_SIZE
+            .saturating_sub(CHUNK_OVERHEAD)
+            .max(1)
+    }
+
+    /// Update the connector, and move all bytes that can be read in order to `readable`.
+    ///
+    /// When more than `ConnectorParam::MAX_REORDER_BUFFER` chunks are waiting for an earlier chunk, the connector is closed and `Error::ReorderBufferFull` is returned. The chunks can not be delivered out of order, as that would corrupt the stream.
+    fn receive(&mut self) -> io::Result<()> {
+        let chunks = self
+            .connector
+            .update_and_receive(&mut self.socket)
+            .map_err(to_io_error)?;
+        for chunk in chunks {
+            // Chunks that are already read were received twice
+            if chunk.offset + chunk.data.len() as u64 > self.read_offset {
+                self.pending.insert(chunk.offset, chunk.data);
+            }
+        }
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() > self.read_offset {
+                break;
+            }
+            let skip = (self.read_offset - *entry.key()) as usize;
+            let data = entry.remove();
+            if skip < data.len() {
+                self.readable.extend(&data[skip..]);
+                self.read_offset += (data.len() - skip) as u64;
+            }
+        }
+        if self.pending.len() > TParam::MAX_REORDER_BUFFER {
+            self.connector
+                .close(&mut self.socket, None)
+                .map_err(to_io_error)?;
+            return Err(to_io_error(
+                Error::ReorderBufferFull {
+                    len: self.pending.len(),
+                }
+                .into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<TParam, TSocket> Read for ReliableStream<TParam, TSocket>
+where
+    TParam: ConnectorParam<TSend = StreamChunk, TReceive = StreamChunk>,
+    TSocket: Socket,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.readable.is_empty() {
+            self.receive()?;
+        }
+        if self.readable.is_empty() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        let count = self.readable.read(buf)?;
+        Ok(count)
+    }
+}
+
+impl<TParam, TSocket> Write for ReliableStream<TParam, TSocket>
+where
+    TParam: ConnectorParam<TSend = StreamChunk, TReceive = StreamChunk>,
+    TSocket: Socket,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        for data in buf.chunks(Self::chunk_size()) {
+            let chunk = StreamChunk {
+                offset: self.write_offset,
+                data: data.to_vec(),
+            };
+            if let Err(e) = self.connector.send_confirmed(&mut self.socket, chunk) {
+                // Report the chunks that were send, the caller will retry the rest
+                return match written {
+                    0 => Err(to_io_error(e)),
+                    written => Ok(written),
+                };
+            }
+            self.write_offset += data.len() as u64;
+            written += data.len();
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.connector.flush(&mut self.socket).map_err(to_io_error)
+    }
+}
+
+fn to_io_error(error: failure::Error) -> io::Error {
+    io::Error::other(error.compat())
+}
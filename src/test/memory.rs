@@ -0,0 +1,74 @@
+use crate::*;
+use std::collections::VecDeque;
+
+/// A socket that does not touch the network. Everything that is send is stored in `sent`, and `recv_from` reads from `incoming`.
+pub struct MemorySocket {
+    pub local_addr: SocketAddr,
+    pub sent: Vec<(Vec<u8>, SocketAddr)>,
+    pub incoming: VecDeque<(Vec<u8>, SocketAddr)>,
+    /// The amount of datagrams that can be send before `send_to` fails with `WouldBlock`, as if the send buffer is full. `None` means there is no limit.
+    pub send_capacity: Option<usize>,
+    /// The ECN marking that is reported for every received datagram
+    pub ecn: Ecn,
+}
+
+impl MemorySocket {
+    pub fn new(local_addr: &str) -> MemorySocket {
+        MemorySocket {
+            local_addr: local_addr.parse().unwrap(),
+            sent: Vec::new(),
+            incoming: VecDeque::new(),
+            send_capacity: None,
+            ecn: Ecn::NotEct,
+        }
+    }
+
+    /// Decode and remove all the packets that were sent by this socket
+    pub fn take_sent<T: for<'a> serde::Deserialize<'a>>(&mut self) -> Vec<Packet<T>> {
+        self.sent
+            .drain(..)
+            .map(|(bytes, _)| bincode::deserialize(&bytes).expect("Could not deserialize packet"))
+            .collect()
+    }
+
+    /// Decode and remove all the packets that were sent by this socket, like `take_sent`, with the packets in a `Packet::Batch` in place of the batch
+    pub fn take_packets<T: for<'a> serde::Deserialize<'a>>(&mut self) -> Vec<Packet<T>> {
+        self.take_sent()
+            .into_iter()
+            .flat_map(|packet| match packet {
+                Packet::Batch { packets } => packets
+                    .iter()
+                    .map(|bytes| bincode::deserialize(bytes).expect("Could not deserialize packet"))
+                    .collect(),
+                packet => vec![packet],
+            })
+            .collect()
+    }
+}
+
+impl Socket for MemorySocket {
+    fn recv_from(&mut self, buffer: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        match self.incoming.pop_front() {
+            Some((data, addr)) => {
+                buffer[..data.len()].copy_from_slice(&data);
+                Ok((data.len(), addr))
+            }
+            None => Err(ErrorKind::WouldBlock.into()),
+        }
+    }
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+    fn received_ecn(&self) -> Ecn {
+        self.ecn
+    }
+    fn send_to(&mut self, buffer: &[u8], target: SocketAddr) -> std::io::Result<()> {
+        match self.send_capacity.as_mut() {
+            Some(0) => return Err(ErrorKind::WouldBlock.into()),
+            Some(capacity) => *capacity -= 1,
+            None => {}
+        }
+        self.sent.push((buffer.to_vec(), target));
+        Ok(())
+    }
+}
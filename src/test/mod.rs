@@ -1,7 +1,14 @@
+mod memory;
 mod proxy;
+mod recording;
+mod transcript;
 
-use self::proxy::{ClientToServer, Proxy};
+use self::memory::MemorySocket;
+use self::proxy::{ClientToServer, Proxy, ServerToClient};
+use self::recording::RecordingSocket;
+use self::transcript::Recorded;
 use crate::*;
+use std::io::ErrorKind;
 use std::num::NonZeroU64;
 use std::thread;
 use std::time::Duration;
@@ -12,9 +19,12 @@ fn test_timeout() {
 
     // Client-server should start connected (else the test_setup failed)
     assert_eq!(NetworkState::Connected, proxy.client.connector.state());
-    thread::sleep(Duration::from_secs(3));
 
-    // Client has not received a message in 5 seconds.
+    // The client is connected until it did not receive a ping for `RECEIVE_PING_TIMEOUT_S`
+    proxy.advance(Duration::from_millis(1500));
+    assert_eq!(NetworkState::Connected, proxy.client.connector.state());
+    proxy.advance(Duration::from_millis(1));
+
     // This means the client should update it's state to Disconnected
     let result = proxy
         .client
@@ -24,48 +34,15 @@ fn test_timeout() {
     assert!(result.is_empty());
     assert_eq!(NetworkState::Disconnected, proxy.client.connector.state());
 
-    // Attempt to reconnect
-    proxy
-        .client
-        .connector
-        .connect(&mut proxy.client.socket)
-        .expect("Could not reconnect");
-    assert_eq!(NetworkState::Connected, proxy.client.connector.state());
-    let message = proxy.handle_one_message_from_client();
-    assert_eq!(
-        Packet::Ping {
-            last_send_message_id: None
-        },
-        message
-    );
-
-    // Server needs to be polled to answer this message
-    let result = proxy
-        .server
-        .connector
-        .receive_from(&mut proxy.server.socket)
-        .expect("Could not update server");
-    assert!(result.is_empty());
-    let message = proxy.handle_one_message_from_server();
-    assert_eq!(
-        Packet::Pong {
-            last_send_message_id: None
-        },
-        message
-    );
-
-    // Client needs to receive this message
-    assert_eq!(NetworkState::Connected, proxy.client.connector.state());
-    proxy
-        .client
-        .connector
-        .update_and_receive(&mut proxy.client.socket)
-        .expect("Could not update client");
-
-    // Now they should be connected again
-    assert_eq!(NetworkState::Connected, proxy.client.connector.state());
+    // The ping interval passed as well, so the client pinged once more before it noticed the timeout
+    assert!(matches!(
+        proxy.drop_one_message_from_client(),
+        Packet::Ping { .. }
+    ));
     assert!(proxy.client_has_no_pending_messages());
-    assert!(proxy.server_has_no_pending_messages());
+
+    // Attempt to reconnect, after which they should be connected again. The first packet the client sends is exactly the handshake and its ping.
+    proxy.complete_handshake();
 }
 
 #[test]
@@ -87,6 +64,7 @@ fn test_confirmed_message() {
     assert_eq!(
         Packet::Data {
             message_id: NonZeroU64::new(1),
+            compressed: false,
             data: ClientToServer::SendMessage {
                 name: String::from("test"),
             }
@@ -120,3 +98,5064 @@ fn test_confirmed_message() {
     assert!(proxy.client_has_no_pending_messages());
     assert!(proxy.server_has_no_pending_messages());
 }
+
+#[test]
+fn test_drain_events() {
+    let mut proxy = Proxy::default();
+
+    let id = proxy
+        .client
+        .connector
+        .send_confirmed(
+            &mut proxy.client.socket,
+            ClientToServer::SendMessage {
+                name: String::from("test"),
+            },
+        )
+        .expect("Could not send message");
+    proxy.handle_one_message_from_client();
+
+    let message = proxy
+        .server
+        .connector
+        .receive_from(&mut proxy.server.socket)
+        .expect("Could not receive from server");
+    assert_eq!(1, message.len());
+    assert_eq!(0, proxy.server.connector.drain_events().count());
+
+    proxy.handle_one_message_from_server();
+    proxy
+        .client
+        .connector
+        .receive_from(&mut proxy.client.socket)
+        .expect("Could not receive from client");
+    let events: Vec<_> = proxy.client.connector.drain_events().collect();
+    assert_eq!(vec![Event::Acknowledged(id)], events);
+    assert_eq!(0, proxy.client.connector.drain_events().count());
+
+    assert!(proxy.client_has_no_pending_messages());
+    assert!(proxy.server_has_no_pending_messages());
+}
+
+#[test]
+fn test_connect_to_address_family_mismatch() {
+    let mut socket = std::net::UdpSocket::bind("127.0.0.1:0").expect("Could not bind socket");
+    let result = Connector::<proxy::Client>::connect_to("[::1]:1234".parse().unwrap(), &mut socket);
+    let error = result
+        .err()
+        .expect("Connecting to an IPv6 peer should fail");
+    match error.downcast_ref::<Error>() {
+        Some(Error::AddressFamilyMismatch { .. }) => {}
+        e => panic!("Unexpected error: {:?}", e),
+    }
+}
+
+#[test]
+fn test_connect_to_unspecified_address() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    for peer in ["0.0.0.0:0", "0.0.0.0:1234", "127.0.0.1:0", "[::]:1234"] {
+        let peer = peer.parse().unwrap();
+        let mut connector = Connector::<ImmediateRequests>::bound_to(peer);
+        let error = connector
+            .connect(&mut socket)
+            .expect_err("Connecting to an unspecified address should fail");
+        match error.downcast_ref::<Error>() {
+            Some(Error::InvalidPeerAddress { peer: p }) if *p == peer => {}
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+    assert!(socket.sent.is_empty());
+    assert!(
+        Connector::<ImmediateRequests>::connect_to("0.0.0.0:0".parse().unwrap(), &mut socket)
+            .is_err()
+    );
+}
+
+struct WeightedChannels;
+impl ConnectorParam for WeightedChannels {
+    type TSend = u32;
+    type TReceive = u32;
+    const EMIT_UNCONFIRMED_PACKET_INTERVAL_S: f64 = 0.;
+    const CHANNEL_WEIGHTS: &'static [u32] = &[3, 1];
+    // Room for exactly 4 data packets of 18 bytes
+    const RETRANSMIT_BUDGET_BYTES: usize = 4 * 18;
+}
+
+#[test]
+fn test_weighted_retransmission() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = Connector::<WeightedChannels>::bound_to("127.0.0.1:2".parse().unwrap());
+
+    for channel in &[0, 1] {
+        for i in 0..6u32 {
+            connector
+                .send_confirmed_on(&mut socket, *channel, i)
+                .expect("Could not send message");
+        }
+    }
+    let sent = socket.take_sent::<u32>();
+    assert_eq!(12, sent.len());
+    assert_eq!(18, bincode::serialize(&sent[0]).unwrap().len());
+
+    connector.update(&mut socket).expect("Could not update");
+    let resent: Vec<u64> = socket
+        .take_sent::<u32>()
+        .into_iter()
+        .filter_map(|packet| match packet {
+            Packet::Data { message_id, .. } => message_id.map(NonZeroU64::get),
+            _ => None,
+        })
+        .collect();
+    // Ids 1..=6 are send on channel 0, ids 7..=12 on channel 1
+    assert_eq!(vec![1, 2, 3, 7], resent);
+}
+
+#[test]
+fn test_pending_retransmits() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = Connector::<WeightedChannels>::bound_to("127.0.0.1:2".parse().unwrap());
+    for channel in &[0, 1] {
+        for i in 0..6u32 {
+            connector
+                .send_confirmed_on(&mut socket, *channel, i)
+                .expect("Could not send message");
+        }
+    }
+    socket.sent.clear();
+
+    // The preview sends nothing, and matches what the next update resends
+    let pending: Vec<u64> = connector
+        .pending_retransmits()
+        .into_iter()
+        .map(NonZeroU64::get)
+        .collect();
+    assert!(socket.sent.is_empty());
+    connector.update(&mut socket).expect("Could not update");
+    let resent: Vec<u64> = socket
+        .take_sent::<u32>()
+        .into_iter()
+        .filter_map(|packet| match packet {
+            Packet::Data { message_id, .. } => message_id.map(NonZeroU64::get),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(vec![1, 2, 3, 7], pending);
+    assert_eq!(pending, resent);
+}
+
+#[test]
+fn test_send_interceptor() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = Connector::<proxy::Client>::bound_to("127.0.0.1:2".parse().unwrap());
+
+    let mut datagram_count = 0;
+    connector.set_send_interceptor(Some(Box::new(move |_| {
+        datagram_count += 1;
+        match datagram_count {
+            1 => SendAction::Drop,
+            2 => SendAction::Duplicate,
+            _ => SendAction::Send,
+        }
+    })));
+
+    connector.connect(&mut socket).expect("Could not connect");
+    for _ in 0..2 {
+        connector
+            .send_unconfirmed(
+                &mut socket,
+                ClientToServer::SendMessage {
+                    name: String::from("test"),
+                },
+            )
+            .expect("Could not send message");
+    }
+
+    // The ping is dropped, the first message is duplicated
+    let sent = socket.take_sent::<ClientToServer>();
+    assert_eq!(3, sent.len());
+    assert!(sent
+        .iter()
+        .all(|packet| matches!(packet, Packet::Data { .. })));
+
+    connector.set_send_interceptor(None);
+    connector.connect(&mut socket).expect("Could not connect");
+    assert_eq!(1, socket.take_sent::<ClientToServer>().len());
+}
+
+#[test]
+fn test_handle_incoming_data_borrowed() {
+    #[derive(Deserialize, Debug)]
+    enum BorrowedClientToServer<'a> {
+        SendMessage { name: &'a str },
+    }
+
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = Connector::<proxy::Server>::bound_to("127.0.0.1:2".parse().unwrap());
+
+    let data = bincode::serialize(&Packet::<_>::Data {
+        message_id: None,
+        compressed: false,
+        data: ClientToServer::SendMessage {
+            name: String::from("test"),
+        },
+    })
+    .unwrap();
+    let message = connector
+        .handle_incoming_data_borrowed::<BorrowedClientToServer>(&mut socket, &data)
+        .expect("Could not handle data")
+        .pop()
+        .expect("Should have received a message");
+
+    let BorrowedClientToServer::SendMessage { name } = message;
+    assert_eq!("test", name);
+    // The name points into the received buffer, instead of a new allocation
+    assert!(data.as_ptr_range().contains(&name.as_ptr()));
+}
+
+struct ImmediateRequests;
+impl ConnectorParam for ImmediateRequests {
+    type TSend = u32;
+    type TReceive = u32;
+    const REQUEST_MISSING_PACKET_INTERVAL_S: f64 = 0.;
+}
+
+fn requested_ids(socket: &mut MemorySocket) -> Vec<u64> {
+    socket
+        .take_sent::<u32>()
+        .into_iter()
+        .filter_map(|packet| match packet {
+            Packet::RequestPacket { id } => Some(id.get()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_packet_not_found() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = Connector::<ImmediateRequests>::bound_to("127.0.0.1:2".parse().unwrap());
+
+    // The peer tells us it has send messages 1 to 5, which we never received
+    let ping = bincode::serialize(&Packet::<u32>::Ping {
+        last_send_message_id: NonZeroU64::new(5),
+        nonce: 0,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &ping)
+        .expect("Could not handle ping");
+    socket.sent.clear();
+
+    connector.update(&mut socket).expect("Could not update");
+    assert_eq!(vec![1, 2, 3, 4, 5], requested_ids(&mut socket));
+
+    // The peer already evicted message 5
+    let not_found = bincode::serialize(&Packet::<u32>::PacketNotFound {
+        id: NonZeroU64::new(5).unwrap(),
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &not_found)
+        .expect("Could not handle PacketNotFound");
+    assert_eq!(
+        vec![Event::MessageLost(NonZeroU64::new(5).unwrap())],
+        connector.drain_events().collect::<Vec<_>>()
+    );
+
+    for _ in 0..3 {
+        connector.update(&mut socket).expect("Could not update");
+        assert_eq!(vec![1, 2, 3, 4], requested_ids(&mut socket));
+    }
+
+    // A repeated PacketNotFound does not report the message as lost again
+    connector
+        .handle_incoming_data(&mut socket, &not_found)
+        .expect("Could not handle PacketNotFound");
+    assert_eq!(0, connector.drain_events().count());
+}
+
+#[test]
+fn test_inject_packet() {
+    let mut connector = manual_connector::<ImmediateRequests>();
+    // The replies of the connector are returned, without a socket
+    let (messages, replies) = inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: None,
+            nonce: 3,
+        },
+    );
+    assert!(messages.is_empty());
+    assert!(matches!(replies[..], [Packet::Pong { nonce: 3, .. }]));
+
+    let (messages, replies) = inject_packet(
+        &mut connector,
+        Packet::Data {
+            message_id: None,
+            compressed: false,
+            data: 5,
+        },
+    );
+    assert_eq!(vec![5], messages);
+    assert!(replies.is_empty());
+}
+
+#[test]
+fn test_request_crossed_confirmation() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut sender = manual_connector::<ImmediateRequests>();
+    let id = sender.send_confirmed(&mut socket, 1u32).unwrap();
+    // The peer requested the message before it arrived, and confirmed it when it did
+    inject_packet(&mut sender, Packet::ConfirmPacket { id });
+    let (_, replies) = inject_packet(&mut sender, Packet::RequestPacket { id });
+    assert_eq!(vec![Packet::AlreadyDelivered { id }], replies);
+
+    // A message that was never confirmed is still not found
+    let unknown = NonZeroU64::new(9).unwrap();
+    let (_, replies) = inject_packet(&mut sender, Packet::RequestPacket { id: unknown });
+    assert_eq!(vec![Packet::PacketNotFound { id: unknown }], replies);
+
+    let mut receiver = manual_connector::<ImmediateRequests>();
+    inject_packet(
+        &mut receiver,
+        Packet::Ping {
+            last_send_message_id: NonZeroU64::new(2),
+            nonce: 0,
+        },
+    );
+    inject_packet(
+        &mut receiver,
+        Packet::AlreadyDelivered {
+            id: NonZeroU64::new(2).unwrap(),
+        },
+    );
+    socket.sent.clear();
+    receiver.clock.advance(Duration::from_millis(1));
+    receiver.update(&mut socket).unwrap();
+    assert_eq!(vec![1], requested_ids(&mut socket));
+    assert!(!receiver
+        .drain_events()
+        .any(|event| matches!(event, Event::MessageLost(_))));
+}
+
+/// Feed a single packet to the connector as if it was received from the peer, and return the messages it contained and the packets the connector send in response
+fn inject_packet<TParam>(
+    connector: &mut Connector<TParam>,
+    packet: Packet<TParam::TReceive>,
+) -> (Vec<TParam::TReceive>, Vec<Packet<TParam::TSend>>)
+where
+    TParam: ConnectorParam,
+    TParam::TSend: for<'a> serde::Deserialize<'a>,
+    TParam::TReceive: serde::Serialize,
+{
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let data = bincode::serialize(&packet).unwrap();
+    let messages = connector
+        .handle_incoming_data(&mut socket, &data)
+        .expect("Could not handle packet");
+    (messages, socket.take_sent())
+}
+
+/// Create a connector for `MemorySocket` tests, that uses a manual clock
+fn manual_connector<TParam: ConnectorParam>() -> Connector<TParam> {
+    let mut connector = Connector::bound_to("127.0.0.1:2".parse().unwrap());
+    connector.clock = Clock::manual();
+    connector
+}
+
+#[test]
+fn test_pause_and_resume() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<proxy::Client>();
+    connector
+        .send_confirmed(
+            &mut socket,
+            ClientToServer::SendMessage {
+                name: String::from("test"),
+            },
+        )
+        .expect("Could not send message");
+    socket.sent.clear();
+
+    connector.pause();
+    assert!(connector.is_paused());
+    connector.clock.advance(Duration::from_secs(3600));
+    assert_eq!(NetworkState::Connected, connector.state());
+    connector.update(&mut socket).expect("Could not update");
+    assert!(socket.sent.is_empty());
+
+    connector.resume();
+    assert!(!connector.is_paused());
+    assert_eq!(NetworkState::Connected, connector.state());
+    connector.update(&mut socket).expect("Could not update");
+    assert!(socket.sent.is_empty());
+
+    // After resuming, time moves again
+    connector.clock.advance(Duration::from_millis(600));
+    connector.update(&mut socket).expect("Could not update");
+    let sent = socket.take_sent::<ClientToServer>();
+    assert_eq!(1, sent.len());
+    assert!(matches!(sent[0], Packet::Ping { .. }));
+
+    connector.clock.advance(Duration::from_millis(500));
+    connector.update(&mut socket).expect("Could not update");
+    let sent = socket.take_sent::<ClientToServer>();
+    assert!(sent
+        .iter()
+        .any(|packet| matches!(packet, Packet::Data { .. })));
+}
+
+#[test]
+fn test_close_with_reason() {
+    let mut client_socket = MemorySocket::new("127.0.0.1:2");
+    let mut server_socket = MemorySocket::new("127.0.0.1:1");
+    let mut client = manual_connector::<proxy::Client>();
+    let mut server = Connector::<proxy::Server>::bound_to(client_socket.local_addr);
+
+    let reason = DisconnectReason::with_message(3, "Server is shutting down");
+    server
+        .close(&mut server_socket, Some(reason.clone()))
+        .expect("Could not close");
+    assert_eq!(NetworkState::Disconnected, server.state());
+
+    let (data, _) = server_socket.sent.pop().expect("Disconnect was not send");
+    client
+        .handle_incoming_data(&mut client_socket, &data)
+        .expect("Could not handle disconnect");
+    assert_eq!(NetworkState::Disconnected, client.state());
+    assert_eq!(
+        vec![
+            Event::Disconnected {
+                reason: Some(reason)
+            },
+            Event::StateChanged(NetworkState::Disconnected),
+        ],
+        client.drain_events().collect::<Vec<_>>()
+    );
+
+    // Closed connectors do not send anything
+    client.clock.advance(Duration::from_secs(1));
+    client.update(&mut client_socket).expect("Could not update");
+    assert!(client_socket.sent.is_empty());
+
+    // The handshake of the client does not reopen the server, until the server connects again
+    client
+        .connect(&mut client_socket)
+        .expect("Could not connect");
+    let (data, _) = client_socket.sent.pop().expect("Ping was not send");
+    server
+        .handle_incoming_data(&mut server_socket, &data)
+        .expect("Could not handle ping");
+    assert_eq!(NetworkState::Disconnected, server.state());
+
+    server
+        .connect(&mut server_socket)
+        .expect("Could not connect");
+    server
+        .handle_incoming_data(&mut server_socket, &data)
+        .expect("Could not handle ping");
+    assert_eq!(NetworkState::Connected, server.state());
+}
+
+#[test]
+fn test_force_disconnect() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    connector.connect(&mut socket).unwrap();
+    connector.send_confirmed(&mut socket, 1u32).unwrap();
+    socket.sent.clear();
+
+    let reason = DisconnectReason::new(7);
+    connector
+        .force_disconnect(&mut socket, Some(reason.clone()))
+        .expect("Could not disconnect");
+    assert_eq!(NetworkState::Disconnected, connector.state());
+    let sent = socket.take_sent::<u32>();
+    assert_eq!(1, sent.len());
+    assert!(matches!(&sent[0], Packet::Disconnect { reason: Some(r) } if *r == reason));
+
+    // The unconfirmed message is forgotten, and nothing is send anymore
+    assert_eq!(0, connector.unconfirmed_bytes());
+    connector.clock.advance(Duration::from_secs(5));
+    connector.update(&mut socket).expect("Could not update");
+    assert!(socket.sent.is_empty());
+
+    // The kicked peer does not get back in by connecting again
+    inject_packet(&mut connector, Packet::Hello { session: 9 });
+    assert!(inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: None,
+            nonce: 1,
+        }
+    )
+    .1
+    .is_empty());
+    connector.clock.advance(Duration::from_secs(5));
+    connector.update(&mut socket).expect("Could not update");
+    assert!(socket.sent.is_empty());
+    assert_eq!(NetworkState::Disconnected, connector.state());
+
+    // The pool keeps the connector when the disconnect could not be send, and removes it once it is
+    let mut pool = ConnectorPool::<ImmediateRequests>::new();
+    let peer = "127.0.0.1:2".parse().unwrap();
+    pool.get_or_insert(peer);
+    socket.send_capacity = Some(0);
+    assert!(pool.force_disconnect(&mut socket, peer, None).is_err());
+    assert_eq!(1, pool.peer_count());
+    socket.send_capacity = None;
+    assert!(pool
+        .force_disconnect(&mut socket, peer, None)
+        .unwrap()
+        .is_some());
+    assert_eq!(0, pool.peer_count());
+    assert!(matches!(
+        socket.take_sent::<u32>()[..],
+        [Packet::Disconnect { reason: None }]
+    ));
+    assert!(pool
+        .force_disconnect(&mut socket, peer, None)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_closed_connector_drops_packets() {
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    connector.connect(&mut socket).unwrap();
+    connector.close(&mut socket, None).unwrap();
+    socket.sent.clear();
+    let last_ping = connector.receive.last_ping;
+    connector.clock.advance(Duration::from_secs(1));
+
+    let id = |id| NonZeroU64::new(id).unwrap();
+    for packet in [
+        Packet::Data {
+            message_id: Some(id(1)),
+            compressed: false,
+            data: 1,
+        },
+        Packet::Data {
+            message_id: None,
+            compressed: false,
+            data: 2,
+        },
+        Packet::Ping {
+            last_send_message_id: Some(id(5)),
+            nonce: 0,
+        },
+        Packet::Pong {
+            last_send_message_id: None,
+            nonce: 0,
+        },
+    ] {
+        let (messages, replies) = inject_packet(&mut connector, packet);
+        assert!(messages.is_empty());
+        assert!(replies.is_empty());
+    }
+    assert_eq!(NetworkState::Disconnected, connector.state());
+    assert_eq!(last_ping, connector.receive.last_ping);
+    assert_eq!(None, connector.receive.last_message_id);
+    assert_eq!(4, connector.stats().closed_drops);
+
+    // Connecting again re-initializes the connector
+    connector.connect(&mut socket).unwrap();
+    let (messages, _) = inject_packet(
+        &mut connector,
+        Packet::Data {
+            message_id: Some(id(1)),
+            compressed: false,
+            data: 3,
+        },
+    );
+    assert_eq!(vec![3], messages);
+    assert_eq!(4, connector.stats().closed_drops);
+}
+
+#[test]
+fn test_closed_connector_stays_closed() {
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let id = |id| NonZeroU64::new(id).unwrap();
+    let data = |message_id, data| Packet::Data {
+        message_id: Some(id(message_id)),
+        compressed: false,
+        data,
+    };
+    inject_packet(&mut connector, Packet::Hello { session: 1 });
+    inject_packet(&mut connector, data(1, 1));
+    inject_packet(&mut connector, data(2, 2));
+    connector.close(&mut socket, None).unwrap();
+    connector.drain_events().for_each(drop);
+    let stats = connector.stats().clone();
+
+    // Datagrams are dropped before they are read, so they are not counted or reported as malformed
+    let messages = connector
+        .handle_incoming_data(&mut socket, &[200, 0, 0, 0])
+        .expect("Could not handle datagram");
+    assert!(messages.is_empty());
+    assert!(connector.take_events().is_empty());
+    assert_eq!(
+        stats.received_datagrams,
+        connector.stats().received_datagrams
+    );
+    assert_eq!(
+        stats.received_packet_types.total(),
+        connector.stats().received_packet_types.total()
+    );
+
+    // Neither the handshake of the session that was closed, nor that of a new session reopens the connection
+    for session in 1..=2 {
+        inject_packet(&mut connector, Packet::Hello { session });
+        assert!(inject_packet(&mut connector, data(1, 3)).0.is_empty());
+        assert!(connector.closed);
+        assert_eq!(NetworkState::Disconnected, connector.state());
+    }
+    assert_eq!(stats.closed_drops + 5, connector.stats().closed_drops);
+    assert_eq!(Some(1), connector.receive.peer_session);
+    assert_eq!(Some(id(2)), connector.receive.last_message_id);
+
+    // Only connecting again reopens it
+    connector.connect(&mut socket).expect("Could not connect");
+    assert!(!connector.closed);
+}
+
+#[test]
+fn test_update_is_idempotent() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<proxy::Client>();
+
+    // Nothing is due within the first ping interval
+    for _ in 0..1000 {
+        connector.clock.advance(Duration::from_micros(100));
+        connector.update(&mut socket).expect("Could not update");
+    }
+    assert!(socket.sent.is_empty());
+
+    // Crossing the ping interval sends exactly one ping
+    connector.clock.advance(Duration::from_millis(401));
+    for _ in 0..1000 {
+        connector.update(&mut socket).expect("Could not update");
+        connector.clock.advance(Duration::from_micros(100));
+    }
+    let sent = socket.take_sent::<ClientToServer>();
+    assert_eq!(1, sent.len());
+    assert!(matches!(sent[0], Packet::Ping { .. }));
+}
+
+struct SmallPackets;
+impl ConnectorParam for SmallPackets {
+    type TSend = ServerToClient;
+    type TReceive = ClientToServer;
+    const MAX_DESERIALIZE_BYTES: u64 = 16;
+}
+
+#[test]
+fn test_oversized_packets_are_rejected() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = Connector::<proxy::Server>::bound_to("127.0.0.1:2".parse().unwrap());
+
+    let mut data = bincode::serialize(&Packet::<_>::Data {
+        message_id: None,
+        compressed: false,
+        data: ClientToServer::SendMessage {
+            name: String::from("test"),
+        },
+    })
+    .unwrap();
+
+    // A packet that is larger than the limit is rejected before it is deserialized
+    let mut small_connector = Connector::<SmallPackets>::bound_to("127.0.0.1:2".parse().unwrap());
+    let error = small_connector
+        .handle_incoming_data(&mut socket, &data)
+        .expect_err("Oversized packet should be rejected");
+    match error.downcast_ref::<bincode::Error>().map(|e| &**e) {
+        Some(bincode::ErrorKind::SizeLimit) => {}
+        e => panic!("Unexpected error: {:?}", e),
+    }
+
+    // The data ends with the length of the name as a u64, followed by the name itself
+    let length_start = data.len() - 4 - 8;
+    data[length_start..length_start + 8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+
+    // A name that claims to be larger than the packet fails without allocating the claimed size
+    let error = connector
+        .handle_incoming_data(&mut socket, &data)
+        .expect_err("Packet with an oversized length should be rejected");
+    match error.downcast_ref::<bincode::Error>().map(|e| &**e) {
+        Some(bincode::ErrorKind::Io(e)) if e.kind() == ErrorKind::UnexpectedEof => {}
+        e => panic!("Unexpected error: {:?}", e),
+    }
+}
+
+#[test]
+fn test_deserialize_limit() {
+    let data = bincode::serialize(&String::from("text")).unwrap();
+    match packet::deserialize::<String>(&data, 8).map_err(|e| *e) {
+        Err(bincode::ErrorKind::SizeLimit) => {}
+        e => panic!("Unexpected result: {:?}", e),
+    }
+
+    // The encoding is the same as `bincode::serialize`, and trailing bytes are allowed
+    let mut data = bincode::serialize(&(1u32, vec![2u8, 3])).unwrap();
+    data.push(4);
+    assert_eq!(
+        (1u32, vec![2u8, 3]),
+        packet::deserialize(&data, 1024).unwrap()
+    );
+}
+
+#[test]
+fn test_transcript() {
+    let mut client_socket = MemorySocket::new("127.0.0.1:2");
+    let mut server_socket = MemorySocket::new("127.0.0.1:1");
+    let mut client = Connector::<proxy::Client>::bound_to(server_socket.local_addr);
+    let mut server = Connector::<proxy::Server>::bound_to(client_socket.local_addr);
+    client.start_capture();
+
+    client
+        .connect(&mut client_socket)
+        .expect("Could not connect");
+    let id = client
+        .send_confirmed(
+            &mut client_socket,
+            ClientToServer::SendMessage {
+                name: String::from("test"),
+            },
+        )
+        .expect("Could not send message");
+    for (data, _) in client_socket.sent.drain(..) {
+        server
+            .handle_incoming_data(&mut server_socket, &data)
+            .expect("Could not handle data");
+    }
+    for (data, _) in server_socket.sent.drain(..) {
+        client
+            .handle_incoming_data(&mut client_socket, &data)
+            .expect("Could not handle data");
+    }
+
+    let hello = Packet::<ClientToServer>::Hello {
+        session: client.send.session,
+    };
+    let ping = Packet::<ClientToServer>::Ping {
+        last_send_message_id: None,
+        nonce: 0,
+    };
+    assert_eq!(
+        vec![
+            Recorded::Sent(Packet::Batch {
+                packets: vec![
+                    bincode::serialize(&hello).unwrap(),
+                    bincode::serialize(&ping).unwrap(),
+                ],
+            }),
+            Recorded::Sent(Packet::Data {
+                message_id: Some(id),
+                compressed: false,
+                data: ClientToServer::SendMessage {
+                    name: String::from("test"),
+                },
+            }),
+            Recorded::Received(Packet::Pong {
+                last_send_message_id: None,
+                nonce: 0,
+            }),
+            Recorded::Received(Packet::ConfirmPacket { id }),
+        ],
+        client.transcript()
+    );
+}
+
+struct IpOnlyFilter;
+impl ConnectorParam for IpOnlyFilter {
+    type TSend = u32;
+    type TReceive = u32;
+    const ADDRESS_FILTER: AddressFilter = AddressFilter::IpOnly;
+}
+
+#[test]
+fn test_address_filter() {
+    fn receive_from_other_port<TParam: ConnectorParam<TReceive = u32>>() -> Vec<u32> {
+        let mut socket = MemorySocket::new("127.0.0.1:1");
+        let mut connector = Connector::<TParam>::bound_to("127.0.0.1:2".parse().unwrap());
+        let data = bincode::serialize(&Packet::<_>::Data {
+            message_id: None,
+            compressed: false,
+            data: 5u32,
+        })
+        .unwrap();
+        socket
+            .incoming
+            .push_back((data.clone(), "127.0.0.2:2".parse().unwrap()));
+        socket
+            .incoming
+            .push_back((data, "127.0.0.1:3".parse().unwrap()));
+        connector
+            .receive_from(&mut socket)
+            .expect("Could not receive")
+    }
+
+    assert!(receive_from_other_port::<ImmediateRequests>().is_empty());
+    assert_eq!(vec![5], receive_from_other_port::<IpOnlyFilter>());
+}
+
+#[test]
+fn test_own_datagrams_are_ignored() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = Connector::<IpOnlyFilter>::bound_to("127.0.0.1:2".parse().unwrap());
+    let data = bincode::serialize(&Packet::<_>::Data {
+        message_id: None,
+        compressed: false,
+        data: 5u32,
+    })
+    .unwrap();
+    // The filter accepts the address of our own socket, but a connector that is not a loopback ignores it
+    socket
+        .incoming
+        .push_back((data.clone(), "127.0.0.1:1".parse().unwrap()));
+    socket
+        .incoming
+        .push_back((data, "127.0.0.1:3".parse().unwrap()));
+    assert!(!connector.is_loopback());
+    assert_eq!(
+        vec![5],
+        connector
+            .receive_from(&mut socket)
+            .expect("Could not receive")
+    );
+}
+
+#[test]
+fn test_malformed_packet_source() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = Connector::<IpOnlyFilter>::bound_to("127.0.0.1:2".parse().unwrap());
+    connector.take_events();
+    let source: SocketAddr = "127.0.0.1:3".parse().unwrap();
+    socket.incoming.push_back((vec![0xff; 3], source));
+    assert!(connector.receive_once(&mut socket).is_err());
+    // The event has the address the datagram came from, not the address of the peer
+    match connector.take_events().as_slice() {
+        [Event::MalformedPacket { from, .. }] => assert_eq!(source, *from),
+        events => panic!("Unexpected events: {:?}", events),
+    }
+}
+
+#[test]
+fn test_update_local_addr() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = Connector::<IpOnlyFilter>::bound_to("127.0.0.1:2".parse().unwrap());
+    let data = bincode::serialize(&Packet::<_>::Data {
+        message_id: None,
+        compressed: false,
+        data: 5u32,
+    })
+    .unwrap();
+    let mut receive_from = |connector: &mut Connector<IpOnlyFilter>, addr: &str| {
+        socket
+            .incoming
+            .push_back((data.clone(), addr.parse().unwrap()));
+        connector
+            .receive_from(&mut socket)
+            .expect("Could not receive")
+    };
+    assert!(receive_from(&mut connector, "127.0.0.1:1").is_empty());
+
+    // The socket is bound to a new port, the old port may now belong to someone else
+    connector.update_local_addr("127.0.0.1:3".parse().unwrap());
+    assert!(receive_from(&mut connector, "127.0.0.1:3").is_empty());
+    assert_eq!(vec![5], receive_from(&mut connector, "127.0.0.1:1"));
+    assert_eq!(
+        "127.0.0.1:2".parse::<SocketAddr>().unwrap(),
+        connector.bound_addr()
+    );
+    assert_eq!(NetworkState::Connected, connector.state());
+
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = Connector::<IpOnlyFilter>::loopback(&mut socket).unwrap();
+    connector.update_local_addr("127.0.0.1:3".parse().unwrap());
+    assert!(connector.is_loopback());
+    assert_eq!(
+        "127.0.0.1:3".parse::<SocketAddr>().unwrap(),
+        connector.bound_addr()
+    );
+}
+
+struct BufferedSends;
+impl ConnectorParam for BufferedSends {
+    type TSend = u32;
+    type TReceive = u32;
+    const BUFFER_SENDS: bool = true;
+    // Room for a batch of two unconfirmed `u32` messages of 10 bytes each
+    const MAX_DATAGRAM_SIZE: usize = 4 + 8 + 2 * (8 + 10);
+}
+
+#[test]
+fn test_flush() {
+    let mut client_socket = MemorySocket::new("127.0.0.1:2");
+    let mut server_socket = MemorySocket::new("127.0.0.1:1");
+    let mut client = Connector::<BufferedSends>::bound_to(server_socket.local_addr);
+    let mut server = Connector::<BufferedSends>::bound_to(client_socket.local_addr);
+
+    for i in 0..4u32 {
+        client
+            .send_unconfirmed(&mut client_socket, i)
+            .expect("Could not send message");
+    }
+    let id = client
+        .send_confirmed(&mut client_socket, 4u32)
+        .expect("Could not send message");
+    assert!(client_socket.sent.is_empty());
+
+    client.flush(&mut client_socket).expect("Could not flush");
+    let datagrams = client_socket.sent.clone();
+    // Two batches of two messages, and the confirmed message on its own because it is larger
+    assert!(matches!(
+        &client_socket.take_sent::<u32>()[..],
+        [
+            Packet::Batch { .. },
+            Packet::Batch { .. },
+            Packet::Data {
+                message_id: Some(_),
+                data: 4,
+                ..
+            }
+        ]
+    ));
+
+    client.flush(&mut client_socket).expect("Could not flush");
+    assert!(client_socket.sent.is_empty());
+
+    let mut received = Vec::new();
+    for (data, _) in datagrams {
+        received.extend(
+            server
+                .handle_incoming_data(&mut server_socket, &data)
+                .expect("Could not handle data"),
+        );
+    }
+    assert_eq!(vec![0, 1, 2, 3, 4], received);
+
+    // The confirmation is not buffered
+    assert_eq!(1, server_socket.sent.len());
+    for (data, _) in server_socket.sent.drain(..) {
+        client
+            .handle_incoming_data(&mut client_socket, &data)
+            .expect("Could not handle data");
+    }
+    assert!(client
+        .drain_events()
+        .any(|event| event == Event::Acknowledged(id)));
+}
+
+#[test]
+fn test_session_uptime_and_reconnect_count() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<proxy::Client>();
+    connector.connect(&mut socket).expect("Could not connect");
+    assert_eq!(Some(connector.clock.now()), connector.connected_since());
+    assert_eq!(0, connector.reconnect_count());
+
+    connector.clock.advance(Duration::from_secs(1));
+    assert_eq!(Some(Duration::from_secs(1)), connector.session_uptime());
+
+    // The connection times out
+    connector.clock.advance(Duration::from_secs(2));
+    connector.update(&mut socket).expect("Could not update");
+    assert_eq!(NetworkState::Disconnected, connector.state());
+    assert_eq!(None, connector.connected_since());
+    assert_eq!(None, connector.session_uptime());
+
+    connector.connect(&mut socket).expect("Could not reconnect");
+    assert_eq!(Some(Duration::from_secs(0)), connector.session_uptime());
+    assert_eq!(1, connector.reconnect_count());
+}
+
+#[test]
+fn test_send_unconfirmed_keyed() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<BufferedSends>();
+    for (key, value) in [(1, 10u32), (2, 20), (1, 11), (1, 12)] {
+        connector
+            .send_unconfirmed_keyed(&mut socket, key, value)
+            .expect("Could not send message");
+    }
+    connector
+        .send_unconfirmed(&mut socket, 30u32)
+        .expect("Could not send message");
+    connector
+        .send_unconfirmed(&mut socket, 31u32)
+        .expect("Could not send message");
+    connector.flush(&mut socket).expect("Could not flush");
+
+    let mut receiver = manual_connector::<BufferedSends>();
+    let mut received = Vec::new();
+    for (data, _) in socket.sent.drain(..) {
+        received.extend(
+            receiver
+                .handle_incoming_data(&mut MemorySocket::new("127.0.0.1:2"), &data)
+                .expect("Could not handle data"),
+        );
+    }
+    // Only the newest value per key is send, unkeyed messages are never replaced
+    assert_eq!(vec![12, 20, 30, 31], received);
+}
+
+#[test]
+fn test_unsolicited_pong() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<proxy::Client>();
+    connector.connect(&mut socket).expect("Could not connect");
+    let stale_nonce = match socket.take_packets::<ClientToServer>()[..] {
+        [Packet::Hello { .. }, Packet::Ping { nonce, .. }] => nonce,
+        ref sent => panic!("Expected a single ping, got {:?}", sent),
+    };
+
+    // The connection times out
+    connector.clock.advance(Duration::from_secs(3));
+    connector.update(&mut socket).expect("Could not update");
+    assert_eq!(NetworkState::Disconnected, connector.state());
+    let nonce = match socket.take_packets::<ClientToServer>()[..] {
+        [Packet::Hello { .. }, Packet::Ping { nonce, .. }] => nonce,
+        ref sent => panic!("Expected a single ping, got {:?}", sent),
+    };
+
+    // A pong for a ping that was never send, or that was send too long ago, does not resurrect the connection
+    for nonce in [nonce.wrapping_add(1), stale_nonce] {
+        let pong = bincode::serialize(&Packet::<ServerToClient>::Pong {
+            last_send_message_id: None,
+            nonce,
+        })
+        .unwrap();
+        connector
+            .handle_incoming_data(&mut socket, &pong)
+            .expect("Could not handle pong");
+        assert_eq!(NetworkState::Disconnected, connector.state());
+    }
+
+    // A pong for the latest ping does, but only once
+    let pong = bincode::serialize(&Packet::<ServerToClient>::Pong {
+        last_send_message_id: None,
+        nonce,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &pong)
+        .expect("Could not handle pong");
+    assert_eq!(NetworkState::Connected, connector.state());
+    assert_eq!(None, connector.take_outstanding_ping(nonce));
+}
+
+#[test]
+fn test_unconfirmed() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<WeightedChannels>();
+    let first = connector.send_confirmed(&mut socket, 1u32).unwrap();
+    connector.clock.advance(Duration::from_secs(1));
+    let second = connector.send_confirmed(&mut socket, 2u32).unwrap();
+    connector.clock.advance(Duration::from_secs(1));
+
+    let mut unconfirmed = connector.unconfirmed().collect::<Vec<_>>();
+    unconfirmed.sort();
+    assert_eq!(
+        vec![
+            (first, Duration::from_secs(2)),
+            (second, Duration::from_secs(1))
+        ],
+        unconfirmed
+    );
+
+    let confirm = bincode::serialize(&Packet::<u32>::ConfirmPacket { id: first }).unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &confirm)
+        .expect("Could not handle confirmation");
+    assert_eq!(
+        vec![(second, Duration::from_secs(1))],
+        connector.unconfirmed().collect::<Vec<_>>()
+    );
+}
+
+struct PaddedDatagrams;
+impl ConnectorParam for PaddedDatagrams {
+    type TSend = ClientToServer;
+    type TReceive = ClientToServer;
+    const PADDING: Padding = Padding::Fixed(64);
+}
+
+#[test]
+fn test_padding() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut sender = manual_connector::<PaddedDatagrams>();
+    let mut receiver = manual_connector::<PaddedDatagrams>();
+
+    for name in ["a", "a much longer name"] {
+        sender
+            .send_unconfirmed(
+                &mut socket,
+                ClientToServer::SendMessage {
+                    name: String::from(name),
+                },
+            )
+            .expect("Could not send message");
+    }
+    let datagrams = socket
+        .sent
+        .drain(..)
+        .map(|(data, _)| data)
+        .collect::<Vec<_>>();
+    // Both datagrams have the same size, regardless of the name
+    assert_eq!(
+        vec![64, 64],
+        datagrams.iter().map(Vec::len).collect::<Vec<_>>()
+    );
+
+    let mut received = Vec::new();
+    for data in &datagrams {
+        received.extend(
+            receiver
+                .handle_incoming_data(&mut socket, data)
+                .expect("Could not handle data"),
+        );
+    }
+    assert_eq!(2, received.len());
+
+    assert_eq!(64, Padding::Fixed(64).padded_size(60));
+    assert_eq!(128, Padding::Fixed(64).padded_size(61));
+    assert_eq!(16, Padding::PowerOfTwo.padded_size(9));
+    assert_eq!(9, Padding::None.padded_size(9));
+}
+
+#[test]
+fn test_full_send_buffer() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    for i in 0..3u32 {
+        connector.send_confirmed(&mut socket, i).unwrap();
+    }
+    socket.sent.clear();
+
+    // The ping and one retransmit fit in the send buffer, the other retransmits are deferred
+    connector.clock.advance(Duration::from_millis(1100));
+    socket.send_capacity = Some(2);
+    connector
+        .update(&mut socket)
+        .expect("Update should not fail");
+    assert_eq!(2, socket.sent.len());
+    assert_eq!(2, connector.stats().deferred_sends);
+
+    // The deferred retransmits are send in the next update, without waiting for the retransmit interval
+    socket.sent.clear();
+    socket.send_capacity = None;
+    connector.update(&mut socket).expect("Could not update");
+    let sent = socket.take_sent::<u32>();
+    assert_eq!(2, sent.len());
+    assert!(sent
+        .iter()
+        .all(|packet| matches!(packet, Packet::Data { .. })));
+    assert_eq!(2, connector.stats().deferred_sends);
+}
+
+#[test]
+fn test_estimated_loss_rate() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    assert_eq!(0., connector.estimated_loss_rate());
+
+    for i in 0..3u32 {
+        connector.send_confirmed(&mut socket, i).unwrap();
+    }
+    let confirm = bincode::serialize(&Packet::<u32>::ConfirmPacket {
+        id: NonZeroU64::new(1).unwrap(),
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &confirm)
+        .expect("Could not handle confirmation");
+
+    // The two unconfirmed messages are resend
+    connector.clock.advance(Duration::from_millis(1100));
+    connector.update(&mut socket).expect("Could not update");
+    assert_eq!(3, connector.stats().confirmed_sends);
+    assert_eq!(2, connector.stats().retransmits);
+    assert_eq!(2. / 5., connector.estimated_loss_rate());
+
+    // Once the sends are out of the window, they no longer count
+    connector.clock.advance(Duration::from_secs(9));
+    connector.send_confirmed(&mut socket, 3u32).unwrap();
+    assert_eq!(2. / 3., connector.estimated_loss_rate());
+    connector.clock.advance(Duration::from_secs(2));
+    assert_eq!(0., connector.estimated_loss_rate());
+}
+
+#[test]
+fn test_send_confirmed_with_deadline() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let deadline = connector.clock.now() + Duration::from_secs(2);
+    let id = connector
+        .send_confirmed_with_deadline(&mut socket, 1u32, deadline)
+        .expect("Could not send message");
+    socket.sent.clear();
+
+    // Before the deadline the message is resend as usual
+    connector.clock.advance(Duration::from_millis(1100));
+    connector.update(&mut socket).expect("Could not update");
+    assert!(socket
+        .take_sent::<u32>()
+        .iter()
+        .any(|packet| matches!(packet, Packet::Data { .. })));
+
+    // After the deadline it is abandoned
+    connector.clock.advance(Duration::from_millis(1100));
+    connector.update(&mut socket).expect("Could not update");
+    assert!(!socket
+        .take_sent::<u32>()
+        .iter()
+        .any(|packet| matches!(packet, Packet::Data { .. })));
+    assert_eq!(0, connector.unconfirmed().count());
+    assert!(connector
+        .drain_events()
+        .any(|event| event == Event::MessageExpired(id)));
+}
+
+struct NoMissingRequests;
+impl ConnectorParam for NoMissingRequests {
+    type TSend = u32;
+    type TReceive = u32;
+    const REQUEST_MISSING_PACKET_INTERVAL_S: f64 = 0.;
+    const ENABLE_MISSING_REQUESTS: bool = false;
+}
+
+#[test]
+fn test_enable_missing_requests() {
+    fn requested_after_gap<TParam: ConnectorParam<TSend = u32, TReceive = u32>>() -> Vec<u64> {
+        let mut socket = MemorySocket::new("127.0.0.1:1");
+        let mut connector = manual_connector::<TParam>();
+        // Messages 1 and 2 never arrive
+        let data = bincode::serialize(&Packet::Data {
+            message_id: NonZeroU64::new(3),
+            compressed: false,
+            data: 3u32,
+        })
+        .unwrap();
+        let received = connector
+            .handle_incoming_data(&mut socket, &data)
+            .expect("Could not handle data");
+        assert_eq!(vec![3], received);
+        connector.clock.advance(Duration::from_millis(100));
+        connector.update(&mut socket).expect("Could not update");
+        requested_ids(&mut socket)
+    }
+
+    assert_eq!(vec![1, 2], requested_after_gap::<ImmediateRequests>());
+    assert!(requested_after_gap::<NoMissingRequests>().is_empty());
+}
+
+#[test]
+fn test_connector_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Connector<proxy::Client>>();
+    assert_send_sync::<Connector<proxy::Server>>();
+}
+
+#[test]
+fn test_replay_transcript() {
+    let mut client_socket = MemorySocket::new("127.0.0.1:2");
+    let mut server_socket = MemorySocket::new("127.0.0.1:1");
+    let mut client = Connector::<proxy::Client>::bound_to(server_socket.local_addr);
+    let mut server = manual_connector::<proxy::Server>();
+    server.start_capture();
+
+    client
+        .connect(&mut client_socket)
+        .expect("Could not connect");
+    for name in ["first", "second"] {
+        client
+            .send_confirmed(
+                &mut client_socket,
+                ClientToServer::SendMessage {
+                    name: String::from(name),
+                },
+            )
+            .expect("Could not send message");
+    }
+    for (data, _) in client_socket.sent.drain(..) {
+        server.clock.advance(Duration::from_millis(100));
+        server
+            .handle_incoming_data(&mut server_socket, &data)
+            .expect("Could not handle data");
+    }
+
+    let mut file = Vec::new();
+    let transcript = server.take_capture().expect("Capture was not started");
+    assert_eq!(6, transcript.entries.len());
+    assert_eq!(Duration::from_millis(300), transcript.entries[5].elapsed);
+    transcript.write_to(&mut file).expect("Could not write");
+
+    // Replaying the transcript into a fresh connector receives the same messages, and sends the same replies
+    let transcript = Transcript::read_from(&file[..]).expect("Could not read");
+    let mut replay_socket = MemorySocket::new("127.0.0.1:1");
+    let mut replayed = Connector::<proxy::Server>::bound_to(client_socket.local_addr);
+    let received = transcript
+        .replay(&mut replayed, &mut replay_socket)
+        .expect("Could not replay");
+    assert_eq!(2, received.len());
+    let sent = transcript
+        .entries
+        .iter()
+        .filter(|entry| entry.direction == Direction::Sent)
+        .map(|entry| entry.data.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sent,
+        replay_socket
+            .sent
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect::<Vec<_>>()
+    );
+}
+
+struct NewestFirst;
+impl ConnectorParam for NewestFirst {
+    type TSend = u32;
+    type TReceive = u32;
+    const REQUEST_MISSING_PACKET_INTERVAL_S: f64 = 0.;
+    const MAX_REQUESTS_PER_UPDATE: usize = 2;
+    const MISSING_REQUEST_ORDER: MissingRequestOrder = MissingRequestOrder::Newest;
+}
+
+struct OldestFirst;
+impl ConnectorParam for OldestFirst {
+    type TSend = u32;
+    type TReceive = u32;
+    const REQUEST_MISSING_PACKET_INTERVAL_S: f64 = 0.;
+    const MAX_REQUESTS_PER_UPDATE: usize = 2;
+}
+
+#[test]
+fn test_missing_request_order() {
+    fn requests<TParam: ConnectorParam<TSend = u32, TReceive = u32>>() -> Vec<Vec<u64>> {
+        let mut socket = MemorySocket::new("127.0.0.1:1");
+        let mut connector = manual_connector::<TParam>();
+        // Messages 1 to 4 never arrive
+        let data = bincode::serialize(&Packet::Data {
+            message_id: NonZeroU64::new(5),
+            compressed: false,
+            data: 5u32,
+        })
+        .unwrap();
+        connector
+            .handle_incoming_data(&mut socket, &data)
+            .expect("Could not handle data");
+        (0..3)
+            .map(|_| {
+                connector.clock.advance(Duration::from_millis(100));
+                connector.update(&mut socket).expect("Could not update");
+                requested_ids(&mut socket)
+            })
+            .collect()
+    }
+
+    assert_eq!(
+        vec![vec![1, 2], vec![3, 4], vec![1, 2]],
+        requests::<OldestFirst>()
+    );
+    // Packets 4 and 3 were requested last, but still go first
+    assert_eq!(
+        vec![vec![4, 3], vec![4, 3], vec![4, 3]],
+        requests::<NewestFirst>()
+    );
+}
+
+#[test]
+fn test_loopback() {
+    let mut socket = std::net::UdpSocket::bind("127.0.0.1:0").expect("Could not bind socket");
+    socket
+        .set_nonblocking(true)
+        .expect("Could not make socket non-blocking");
+    let mut connector =
+        Connector::<ImmediateRequests>::loopback(&mut socket).expect("Could not connect");
+    assert!(connector.is_loopback());
+    let id = connector
+        .send_confirmed(&mut socket, 5u32)
+        .expect("Could not send message");
+
+    let mut received = Vec::new();
+    let mut events = Vec::new();
+    for _ in 0..100 {
+        received.extend(
+            connector
+                .receive_from(&mut socket)
+                .expect("Could not receive"),
+        );
+        events.extend(connector.drain_events());
+        if events.contains(&Event::Acknowledged(id)) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(vec![5], received);
+    assert!(events.contains(&Event::Acknowledged(id)));
+    assert_eq!(NetworkState::Connected, connector.state());
+    assert_eq!(0, connector.unconfirmed().count());
+}
+
+#[test]
+fn test_with_capacity() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector =
+        Connector::<ImmediateRequests>::with_capacity("127.0.0.1:2".parse().unwrap(), 1000);
+    assert!(connector.send.unconfirmed_message_cache.capacity() >= 1000);
+    assert!(connector.receive.missing_message_id_list.capacity() >= 1000);
+
+    connector.send_confirmed(&mut socket, 1u32).unwrap();
+    connector.connect(&mut socket).expect("Could not connect");
+    assert_eq!(0, connector.unconfirmed().count());
+    assert!(connector.send.unconfirmed_message_cache.capacity() >= 1000);
+    assert!(connector.receive.missing_message_id_list.capacity() >= 1000);
+}
+
+#[test]
+fn test_id_map_out_of_order_removal() {
+    let id = |id| NonZeroU64::new(id).unwrap();
+    let mut map = id_map::IdMap::new();
+    for i in 1..=10 {
+        map.insert(id(i), i);
+    }
+    // Removing from the middle leaves the other entries in order
+    for i in (2..=10).step_by(2) {
+        assert_eq!(Some(i), map.remove(&id(i)));
+        assert_eq!(None, map.remove(&id(i)));
+    }
+    assert_eq!(
+        vec![1, 3, 5, 7, 9],
+        map.iter().map(|(_, value)| *value).collect::<Vec<_>>()
+    );
+    assert_eq!(None, map.get(&id(4)));
+
+    // An id that was removed can be inserted again
+    assert_eq!(None, map.insert(id(4), 4));
+    assert_eq!(
+        vec![(id(1), 1), (id(3), 3), (id(4), 4)],
+        map.remove_up_to(id(4))
+    );
+    assert_eq!(Some(9), map.remove(&id(9)));
+    assert_eq!(Some(5), map.remove(&id(5)));
+    assert_eq!(Some(7), map.remove(&id(7)));
+    assert!(map.is_empty());
+    assert_eq!(0, map.iter().count());
+}
+
+#[test]
+fn test_congestion_backoff() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    connector.send_confirmed(&mut socket, 1u32).unwrap();
+    socket.sent.clear();
+
+    // A datagram that was marked by a congested router
+    let packet = bincode::serialize(&Packet::<u32>::Ping {
+        last_send_message_id: None,
+        nonce: 0,
+    })
+    .unwrap();
+    connector.clock.advance(Duration::from_millis(600));
+    socket.ecn = Ecn::Ce;
+    socket.incoming.push_back((packet, connector.bound_addr()));
+    connector
+        .receive_from(&mut socket)
+        .expect("Could not receive");
+    assert_eq!(1, connector.stats().congestion_signals);
+
+    // While congested, the message is not resend after the usual interval
+    let resent = |socket: &mut MemorySocket| {
+        socket
+            .take_sent::<u32>()
+            .iter()
+            .any(|packet| matches!(packet, Packet::Data { .. }))
+    };
+    connector.clock.advance(Duration::from_millis(500));
+    connector.update(&mut socket).expect("Could not update");
+    assert!(!resent(&mut socket));
+
+    // Once the congestion is over, it is
+    connector.clock.advance(Duration::from_millis(600));
+    connector.update(&mut socket).expect("Could not update");
+    assert!(resent(&mut socket));
+}
+
+struct SlowPings;
+impl ConnectorParam for SlowPings {
+    type TSend = u32;
+    type TReceive = u32;
+    const PING_INTERVAL_S: f64 = 2.;
+    const SEND_PING_TIMEOUT_S: f64 = 10.;
+}
+
+#[test]
+fn test_effective_timeouts() {
+    let mut connector = manual_connector::<ImmediateRequests>();
+    assert_eq!(
+        Duration::from_millis(1500),
+        connector.effective_receive_timeout()
+    );
+    assert_eq!(
+        Duration::from_millis(1500),
+        connector.effective_send_timeout()
+    );
+    // The receive timeout follows the overridden ping interval
+    let slow = manual_connector::<SlowPings>();
+    assert_eq!(Duration::from_secs(6), slow.effective_receive_timeout());
+    assert_eq!(Duration::from_secs(10), slow.effective_send_timeout());
+
+    // Changes at runtime are included
+    connector.set_receive_timeout(Duration::from_secs(4));
+    assert_eq!(
+        Duration::from_secs(4),
+        connector.effective_receive_timeout()
+    );
+    connector.set_timing(Timing {
+        send_ping_timeout_s: 2.,
+        ..*connector.timing()
+    });
+    assert_eq!(Duration::from_secs(2), connector.effective_send_timeout());
+    assert_eq!(
+        Duration::from_secs(4),
+        connector.effective_receive_timeout()
+    );
+}
+
+struct InvalidTimeouts;
+impl ConnectorParam for InvalidTimeouts {
+    type TSend = u32;
+    type TReceive = u32;
+    const RECEIVE_PING_TIMEOUT_S: f64 = f64::NAN;
+    const SEND_PING_TIMEOUT_S: f64 = -1.;
+}
+
+#[test]
+fn test_effective_timeouts_do_not_panic() {
+    let connector = manual_connector::<InvalidTimeouts>();
+    assert_eq!(Duration::MAX, connector.effective_receive_timeout());
+    assert_eq!(Duration::ZERO, connector.effective_send_timeout());
+}
+
+#[test]
+fn test_update_and_receive_only() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let peer = connector.bound_addr();
+    for (id, data) in [(None, 1u32), (NonZeroU64::new(1), 2), (None, 3)] {
+        let packet = bincode::serialize(&Packet::Data {
+            message_id: id,
+            compressed: false,
+            data,
+        })
+        .unwrap();
+        socket.incoming.push_back((packet, peer));
+    }
+
+    let unconfirmed = connector
+        .update_and_receive_only(&mut socket, Reliability::Unconfirmed)
+        .expect("Could not receive");
+    assert_eq!(vec![1, 3], unconfirmed);
+
+    // The confirmed message was kept for later
+    let packet = bincode::serialize(&Packet::<_>::Data {
+        message_id: None,
+        compressed: false,
+        data: 4u32,
+    })
+    .unwrap();
+    socket.incoming.push_back((packet, peer));
+    let confirmed = connector
+        .update_and_receive_only(&mut socket, Reliability::Confirmed)
+        .expect("Could not receive");
+    assert_eq!(vec![2], confirmed);
+    let unconfirmed = connector
+        .update_and_receive_only(&mut socket, Reliability::Unconfirmed)
+        .expect("Could not receive");
+    assert_eq!(vec![4], unconfirmed);
+}
+
+#[test]
+fn test_receive_once() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let peer = connector.bound_addr();
+    let data = |data| {
+        bincode::serialize(&Packet::<_>::Data {
+            message_id: None,
+            compressed: false,
+            data,
+        })
+        .unwrap()
+    };
+    let ping = bincode::serialize(&Packet::<u32>::Ping {
+        last_send_message_id: None,
+        nonce: 0,
+    })
+    .unwrap();
+    socket.incoming.push_back((data(1u32), peer));
+    socket.incoming.push_back((ping, peer));
+    socket
+        .incoming
+        .push_back((data(2), "127.0.0.1:5".parse().unwrap()));
+    socket.incoming.push_back((data(3), peer));
+
+    // Every call handles a single datagram, datagrams from other addresses are skipped
+    assert_eq!(Some(vec![1]), connector.receive_once(&mut socket).unwrap());
+    assert_eq!(Some(vec![]), connector.receive_once(&mut socket).unwrap());
+    assert_eq!(Some(vec![3]), connector.receive_once(&mut socket).unwrap());
+    assert_eq!(None, connector.receive_once(&mut socket).unwrap());
+
+    // `receive_from` handles every datagram that is waiting
+    for message in 4..7 {
+        socket.incoming.push_back((data(message), peer));
+    }
+    assert_eq!(vec![4, 5, 6], connector.receive_from(&mut socket).unwrap());
+    assert!(socket.incoming.is_empty());
+    assert!(connector.receive_from(&mut socket).unwrap().is_empty());
+}
+
+struct QueueMessages;
+impl ConnectorParam for QueueMessages {
+    type TSend = u32;
+    type TReceive = u32;
+    const QUEUE_MESSAGES: bool = true;
+}
+
+#[test]
+fn test_queue_messages() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<QueueMessages>();
+    connector.take_events();
+    let id = NonZeroU64::new(1);
+    let data = bincode::serialize(&Packet::Data {
+        message_id: id,
+        compressed: false,
+        data: 5u32,
+    })
+    .unwrap();
+    let received = connector
+        .handle_incoming_data(&mut socket, &data)
+        .expect("Could not handle data");
+    assert!(received.is_empty());
+    assert_eq!(vec![Event::Message(5)], connector.take_events());
+    // The message is still confirmed as usual
+    assert_eq!(
+        vec![Packet::ConfirmPacket { id: id.unwrap() }],
+        socket.take_sent::<u32>()
+    );
+}
+
+#[test]
+fn test_receive_buffer_is_kept() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    assert!(connector.receive_buffer.is_empty());
+    assert_eq!(None, connector.receive_once(&mut socket).unwrap());
+    let buffer = connector.receive_buffer.as_ptr();
+    assert!(!connector.receive_buffer.is_empty());
+
+    // Later receives read into the same buffer
+    assert_eq!(None, connector.receive_once(&mut socket).unwrap());
+    assert!(connector.receive_from(&mut socket).unwrap().is_empty());
+    assert_eq!(buffer, connector.receive_buffer.as_ptr());
+}
+
+#[test]
+fn test_peek_message() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let peer = connector.bound_addr();
+    for data in [1u32, 2] {
+        let packet = bincode::serialize(&Packet::<_>::Data {
+            message_id: None,
+            compressed: false,
+            data,
+        })
+        .unwrap();
+        socket.incoming.push_back((packet, peer));
+    }
+    assert_eq!(None, connector.peek_message());
+
+    connector
+        .update_and_queue(&mut socket)
+        .expect("Could not receive");
+    assert_eq!(Some(&1), connector.peek_message());
+    assert_eq!(Some(&1), connector.peek_message());
+    assert_eq!(Some(1), connector.pop_message());
+    assert_eq!(Some(&2), connector.peek_message());
+    assert_eq!(Some(2), connector.pop_message());
+    assert_eq!(None, connector.peek_message());
+    assert_eq!(None, connector.pop_message());
+}
+
+#[test]
+fn test_highest_message_id() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    connector.receive.last_message_id = NonZeroU64::new(u64::MAX - 1);
+    connector.receive.highest_received_id = connector.receive.last_message_id;
+
+    // Requesting the last possible id must not overflow
+    let ping = bincode::serialize(&Packet::<u32>::Ping {
+        last_send_message_id: NonZeroU64::new(u64::MAX),
+        nonce: 0,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &ping)
+        .expect("Could not handle ping");
+    socket.sent.clear();
+    connector.clock.advance(Duration::from_millis(100));
+    connector.update(&mut socket).expect("Could not update");
+    assert_eq!(vec![u64::MAX], requested_ids(&mut socket));
+}
+
+#[test]
+fn test_peer_last_sent_id() {
+    let mut connector = manual_connector::<ImmediateRequests>();
+    assert_eq!(None, connector.peer_last_sent_id());
+    assert_eq!(None, connector.last_received_id());
+
+    let ping = |id| Packet::Ping {
+        last_send_message_id: NonZeroU64::new(id),
+        nonce: 0,
+    };
+    inject_packet(&mut connector, ping(5));
+    inject_packet(
+        &mut connector,
+        Packet::Data {
+            message_id: NonZeroU64::new(2),
+            compressed: false,
+            data: 1u32,
+        },
+    );
+    assert_eq!(NonZeroU64::new(5), connector.peer_last_sent_id());
+    // The pings of the peer do not count as received messages
+    assert_eq!(NonZeroU64::new(2), connector.last_received_id());
+    inject_packet(
+        &mut connector,
+        Packet::Data {
+            message_id: NonZeroU64::new(1),
+            compressed: false,
+            data: 1u32,
+        },
+    );
+    assert_eq!(NonZeroU64::new(2), connector.last_received_id());
+
+    // An older ping that arrives late does not lower the id
+    inject_packet(&mut connector, ping(8));
+    inject_packet(&mut connector, ping(3));
+    assert_eq!(NonZeroU64::new(8), connector.peer_last_sent_id());
+
+    connector
+        .connect(&mut MemorySocket::new("127.0.0.1:1"))
+        .unwrap();
+    assert_eq!(None, connector.peer_last_sent_id());
+}
+
+struct LimitedAmplification;
+impl ConnectorParam for LimitedAmplification {
+    type TSend = u32;
+    type TReceive = u32;
+    const REQUEST_MISSING_PACKET_INTERVAL_S: f64 = 0.;
+    const MAX_AMPLIFICATION: Option<u64> = Some(2);
+}
+
+#[test]
+fn test_max_amplification() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<LimitedAmplification>();
+
+    // A single ping that claims 100 messages were send
+    let ping = bincode::serialize(&Packet::<u32>::Ping {
+        last_send_message_id: NonZeroU64::new(100),
+        nonce: 0,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &ping)
+        .expect("Could not handle ping");
+    connector.clock.advance(Duration::from_millis(100));
+    connector.update(&mut socket).expect("Could not update");
+
+    // One datagram was received, so 2 * (1 + 1) control packets are allowed: the pong and 3 requests
+    assert_eq!(vec![1, 2, 3], requested_ids(&mut socket));
+    let stats = connector.stats();
+    assert_eq!(1, stats.received_datagrams);
+    assert_eq!(4, stats.control_packets_sent);
+    assert_eq!(4., stats.amplification_factor());
+    // Only the ids that could be requested are tracked as missing, the rest is tracked after the next datagrams
+    assert_eq!(1, stats.suppressed_control_packets);
+    assert_eq!(4, connector.receive.missing_message_id_list.len());
+    assert_eq!(NonZeroU64::new(4), connector.receive.last_message_id);
+
+    // A message that would make us track more missing ids than that is rejected
+    let data = bincode::serialize(&Packet::Data {
+        message_id: NonZeroU64::new(100),
+        compressed: false,
+        data: 0u32,
+    })
+    .unwrap();
+    let received = connector
+        .handle_incoming_data(&mut socket, &data)
+        .expect("Could not handle data");
+    assert!(received.is_empty());
+    assert_eq!(1, connector.stats().ids_too_far_ahead);
+    assert_eq!(4, connector.receive.missing_message_id_list.len());
+}
+
+#[test]
+fn test_receive_uninhabited_message() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    // The client receives `ServerToClient`, which is an empty enum, so it disables `RECEIVES_MESSAGES`
+    let mut connector = manual_connector::<proxy::Client>();
+    let data = bincode::serialize(&Packet::<_>::Data {
+        message_id: None,
+        compressed: false,
+        data: 0u32,
+    })
+    .unwrap();
+    let error = connector
+        .handle_incoming_data(&mut socket, &data)
+        .expect_err("Can not receive a message");
+    assert!(matches!(
+        error.downcast_ref::<Error>(),
+        Some(Error::ReceiveNotSupported)
+    ));
+
+    // Other packets are still handled
+    let ping = bincode::serialize(&Packet::<ServerToClient>::Ping {
+        last_send_message_id: None,
+        nonce: 0,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &ping)
+        .expect("Could not handle ping");
+}
+
+struct ResendState;
+impl ConnectorParam for ResendState {
+    type TSend = u32;
+    type TReceive = u32;
+    const RESEND_LATEST_UNCONFIRMED_ON_RECONNECT: bool = true;
+}
+
+#[test]
+fn test_resend_latest_unconfirmed_on_reconnect() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ResendState>();
+    for (key, value) in [(1, 10u32), (2, 20), (1, 11)] {
+        connector
+            .send_unconfirmed_keyed(&mut socket, key, value)
+            .unwrap();
+    }
+    connector.send_unconfirmed(&mut socket, 30u32).unwrap();
+    connector.send_unconfirmed(&mut socket, 31u32).unwrap();
+
+    connector.connect(&mut socket).expect("Could not connect");
+    let nonce = match socket.take_packets::<u32>().pop() {
+        Some(Packet::Ping { nonce, .. }) => nonce,
+        packet => panic!("Expected a ping, got {:?}", packet),
+    };
+    let pong = bincode::serialize(&Packet::<u32>::Pong {
+        last_send_message_id: None,
+        nonce,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &pong)
+        .expect("Could not handle pong");
+    let resent = socket
+        .take_sent::<u32>()
+        .into_iter()
+        .map(|packet| match packet {
+            Packet::Data {
+                message_id: None,
+                data,
+                ..
+            } => data,
+            packet => panic!("Expected unconfirmed data, got {:?}", packet),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(vec![31, 11, 20], resent);
+
+    // Only the first pong after connecting completes the handshake
+    connector.clock.advance(Duration::from_millis(600));
+    connector.update(&mut socket).expect("Could not update");
+    let nonce = match socket.take_sent::<u32>().pop() {
+        Some(Packet::Ping { nonce, .. }) => nonce,
+        packet => panic!("Expected a ping, got {:?}", packet),
+    };
+    let pong = bincode::serialize(&Packet::<u32>::Pong {
+        last_send_message_id: None,
+        nonce,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &pong)
+        .expect("Could not handle pong");
+    assert!(socket.sent.is_empty());
+}
+
+#[test]
+fn test_take_events() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let id = connector
+        .send_confirmed(&mut socket, 1u32)
+        .expect("Could not send message");
+    let confirm = bincode::serialize(&Packet::<u32>::ConfirmPacket { id }).unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &confirm)
+        .expect("Could not handle confirmation");
+
+    assert_eq!(vec![Event::Acknowledged(id)], connector.take_events());
+    assert!(connector.take_events().is_empty());
+    assert_eq!(0, connector.drain_events().count());
+}
+
+#[test]
+fn test_drain_acknowledgements() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let ids = (0..3u32)
+        .map(|i| connector.send_confirmed(&mut socket, i).unwrap())
+        .collect::<Vec<_>>();
+    inject_packet(&mut connector, Packet::ConfirmPacket { id: ids[1] });
+    inject_packet(&mut connector, Packet::ConfirmUpTo { id: ids[2] });
+
+    assert_eq!(
+        vec![ids[1], ids[0], ids[2]],
+        connector.drain_acknowledgements()
+    );
+    assert!(connector.drain_acknowledgements().is_empty());
+    // The events are not affected
+    assert_eq!(3, connector.drain_events().count());
+}
+
+struct Stream;
+impl ConnectorParam for Stream {
+    type TSend = StreamChunk;
+    type TReceive = StreamChunk;
+    // Room for 16 bytes per chunk
+    const MAX_DATAGRAM_SIZE: usize = 46;
+}
+
+#[test]
+fn test_reliable_stream() {
+    use std::io::{Read, Write};
+
+    let mut writer = ReliableStream::new(
+        manual_connector::<Stream>(),
+        MemorySocket::new("127.0.0.1:1"),
+    );
+    let mut reader = ReliableStream::new(
+        manual_connector::<Stream>(),
+        MemorySocket::new("127.0.0.1:1"),
+    );
+    let written = (0..100u8).collect::<Vec<_>>();
+    writer.write_all(&written).unwrap();
+    writer.flush().unwrap();
+
+    let mut datagrams = std::mem::take(&mut writer.socket_mut().sent)
+        .into_iter()
+        .map(|(data, _)| data)
+        .collect::<Vec<_>>();
+    assert_eq!(7, datagrams.len());
+    assert!(datagrams.iter().all(|data| data.len() <= 46));
+
+    // Deliver the chunks in reverse order, with the first chunk missing
+    let first = datagrams.remove(0);
+    let peer = "127.0.0.1:2".parse().unwrap();
+    for data in datagrams.iter().rev() {
+        reader.socket_mut().incoming.push_back((data.clone(), peer));
+    }
+    let mut buffer = [0u8; 256];
+    let e = reader.read(&mut buffer).unwrap_err();
+    assert_eq!(ErrorKind::WouldBlock, e.kind());
+
+    // The first chunk arrives, and one chunk arrives twice
+    reader.socket_mut().incoming.push_back((first, peer));
+    reader
+        .socket_mut()
+        .incoming
+        .push_back((datagrams[2].clone(), peer));
+    let mut read = Vec::new();
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(count) => read.extend_from_slice(&buffer[..count]),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => panic!("Could not read: {:?}", e),
+        }
+    }
+    assert_eq!(written, read);
+}
+
+struct ConnectTimeout;
+impl ConnectorParam for ConnectTimeout {
+    type TSend = u32;
+    type TReceive = u32;
+    const CONNECT_TIMEOUT_S: Option<f64> = Some(2.);
+}
+
+#[test]
+fn test_connect_timeout() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ConnectTimeout>();
+    connector.connect(&mut socket).expect("Could not connect");
+    socket.sent.clear();
+
+    connector.clock.advance(Duration::from_secs(1));
+    assert_ne!(NetworkState::Failed, connector.state());
+    connector.clock.advance(Duration::from_millis(1100));
+    assert_eq!(NetworkState::Failed, connector.state());
+
+    // A failed connector stops pinging
+    connector.drain_events().for_each(drop);
+    connector.update(&mut socket).expect("Could not update");
+    assert!(socket.sent.is_empty());
+    assert_eq!(
+        vec![Event::StateChanged(NetworkState::Failed)],
+        connector.take_events()
+    );
+
+    // When the peer answers in time, the connector does not fail
+    connector.connect(&mut socket).expect("Could not connect");
+    assert_ne!(NetworkState::Failed, connector.state());
+    let nonce = match socket.take_packets::<u32>().pop() {
+        Some(Packet::Ping { nonce, .. }) => nonce,
+        packet => panic!("Expected a ping, got {:?}", packet),
+    };
+    let pong = bincode::serialize(&Packet::<u32>::Pong {
+        last_send_message_id: None,
+        nonce,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &pong)
+        .expect("Could not handle pong");
+    connector.clock.advance(Duration::from_secs(3));
+    assert_ne!(NetworkState::Failed, connector.state());
+}
+
+struct SlowConnectTimeout;
+impl ConnectorParam for SlowConnectTimeout {
+    type TSend = u32;
+    type TReceive = u32;
+    const CONNECT_TIMEOUT_S: Option<f64> = Some(2.);
+    const RECEIVE_PING_TIMEOUT_S: f64 = 10.;
+}
+
+#[test]
+fn test_time_until_disconnect_after_failed_connect() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<SlowConnectTimeout>();
+    connector.connect(&mut socket).expect("Could not connect");
+    assert!(connector.time_until_disconnect().is_some());
+
+    // The receive timeout did not pass yet, but the connector already failed
+    connector.clock.advance(Duration::from_secs(3));
+    assert_eq!(NetworkState::Failed, connector.state());
+    assert_eq!(None, connector.time_until_disconnect());
+}
+
+struct CumulativeAcks;
+impl ConnectorParam for CumulativeAcks {
+    type TSend = u32;
+    type TReceive = u32;
+    const CUMULATIVE_ACKS: bool = true;
+}
+
+#[test]
+fn test_cumulative_acks() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut receiver = manual_connector::<CumulativeAcks>();
+    let mut confirmations = |ids: &[u64]| {
+        for id in ids {
+            let data = bincode::serialize(&Packet::Data {
+                message_id: NonZeroU64::new(*id),
+                compressed: false,
+                data: 0u32,
+            })
+            .unwrap();
+            receiver
+                .handle_incoming_data(&mut socket, &data)
+                .expect("Could not handle data");
+        }
+        socket.take_sent::<u32>()
+    };
+    let id = |id| NonZeroU64::new(id).unwrap();
+
+    assert_eq!(
+        vec![
+            Packet::ConfirmUpTo { id: id(1) },
+            Packet::ConfirmUpTo { id: id(2) }
+        ],
+        confirmations(&[1, 2])
+    );
+    // 3 and 4 are missing, so 5 is confirmed on its own
+    assert_eq!(
+        vec![Packet::ConfirmPacket { id: id(5) }],
+        confirmations(&[5])
+    );
+    // 4 arrives, 3 is still missing
+    assert_eq!(
+        vec![Packet::ConfirmPacket { id: id(4) }],
+        confirmations(&[4])
+    );
+    // Once 3 arrives, everything up to 5 is confirmed
+    assert_eq!(vec![Packet::ConfirmUpTo { id: id(5) }], confirmations(&[3]));
+
+    let mut sender = manual_connector::<CumulativeAcks>();
+    for i in 0..5u32 {
+        sender.send_confirmed(&mut socket, i).unwrap();
+    }
+    let confirm = bincode::serialize(&Packet::<u32>::ConfirmUpTo { id: id(3) }).unwrap();
+    sender
+        .handle_incoming_data(&mut socket, &confirm)
+        .expect("Could not handle confirmation");
+    assert_eq!(
+        vec![
+            Event::Acknowledged(id(1)),
+            Event::Acknowledged(id(2)),
+            Event::Acknowledged(id(3))
+        ],
+        sender.take_events()
+    );
+    assert_eq!(
+        vec![id(4), id(5)],
+        sender.unconfirmed().map(|(id, _)| id).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_ping_now() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    assert_eq!(None, connector.last_rtt());
+
+    connector.clock.advance(Duration::from_millis(300));
+    connector.ping_now(&mut socket).expect("Could not ping");
+    let nonce = match socket.take_sent::<u32>().pop() {
+        Some(Packet::Ping { nonce, .. }) => nonce,
+        packet => panic!("Expected a ping, got {:?}", packet),
+    };
+
+    connector.clock.advance(Duration::from_millis(40));
+    let pong = bincode::serialize(&Packet::<u32>::Pong {
+        last_send_message_id: None,
+        nonce,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &pong)
+        .expect("Could not handle pong");
+    assert_eq!(Some(Duration::from_millis(40)), connector.last_rtt());
+
+    // The regular ping is still send on schedule
+    connector.clock.advance(Duration::from_millis(200));
+    connector.update(&mut socket).expect("Could not update");
+    assert!(matches!(
+        socket.take_sent::<u32>().as_slice(),
+        [Packet::Ping { .. }]
+    ));
+}
+
+struct ShortSessions;
+impl ConnectorParam for ShortSessions {
+    type TSend = u32;
+    type TReceive = u32;
+    const MAX_SESSION_DURATION_S: Option<f64> = Some(10.);
+    const SESSION_EXPIRED_REASON: Option<DisconnectReason> = Some(DisconnectReason {
+        code: 3,
+        message: None,
+    });
+}
+
+#[test]
+fn test_max_session_duration() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ShortSessions>();
+    connector.connect(&mut socket).expect("Could not connect");
+    socket.sent.clear();
+    connector.drain_events().for_each(drop);
+
+    let ping = |nonce| {
+        bincode::serialize(&Packet::<u32>::Ping {
+            last_send_message_id: None,
+            nonce,
+        })
+        .unwrap()
+    };
+    // Keep the connection alive for the whole session
+    for nonce in 0..20 {
+        connector.clock.advance(Duration::from_millis(500));
+        connector
+            .handle_incoming_data(&mut socket, &ping(nonce))
+            .expect("Could not handle ping");
+        connector.update(&mut socket).expect("Could not update");
+        assert_eq!(NetworkState::Connected, connector.state());
+    }
+    socket.sent.clear();
+
+    connector.clock.advance(Duration::from_millis(100));
+    assert_eq!(NetworkState::Disconnected, connector.state());
+    connector.update(&mut socket).expect("Could not update");
+    assert_eq!(
+        vec![Packet::Disconnect {
+            reason: Some(DisconnectReason::new(3))
+        }],
+        socket.take_sent::<u32>()
+    );
+    assert_eq!(
+        vec![Event::StateChanged(NetworkState::Disconnected)],
+        connector.take_events()
+    );
+
+    // The peer connecting again does not reopen the connection, connecting again starts a new session
+    inject_packet(&mut connector, Packet::Hello { session: 1 });
+    connector
+        .handle_incoming_data(&mut socket, &ping(20))
+        .expect("Could not handle ping");
+    assert_eq!(NetworkState::Disconnected, connector.state());
+
+    connector.connect(&mut socket).expect("Could not connect");
+    connector
+        .handle_incoming_data(&mut socket, &ping(21))
+        .expect("Could not handle ping");
+    assert_eq!(NetworkState::Connected, connector.state());
+}
+
+#[test]
+fn test_send_while_disconnected() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    connector.clock.advance(Duration::from_millis(1600));
+    connector.update(&mut socket).expect("Could not update");
+    assert_eq!(NetworkState::Disconnected, connector.state());
+    socket.sent.clear();
+
+    let not_connected = |e: failure::Error| match e.downcast_ref::<Error>() {
+        Some(Error::NotConnected {
+            state: NetworkState::Disconnected,
+        }) => {}
+        e => panic!("Expected NotConnected, got {:?}", e),
+    };
+    not_connected(connector.send_confirmed(&mut socket, 1u32).unwrap_err());
+    not_connected(connector.send_unconfirmed(&mut socket, 1u32).unwrap_err());
+    assert!(socket.sent.is_empty());
+    assert_eq!(0, connector.unconfirmed().count());
+
+    // A closed connector can not send either, until it connects again
+    connector.connect(&mut socket).expect("Could not connect");
+    connector.close(&mut socket, None).expect("Could not close");
+    not_connected(connector.send_confirmed(&mut socket, 1u32).unwrap_err());
+    connector.connect(&mut socket).expect("Could not connect");
+    connector
+        .send_confirmed(&mut socket, 1u32)
+        .expect("Could not send");
+}
+
+#[test]
+fn test_on_state_change() {
+    use std::sync::{Arc, Mutex};
+
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let handler_changes = Arc::clone(&changes);
+    connector.on_state_change(move |old, new| handler_changes.lock().unwrap().push((old, new)));
+
+    // Nothing changes while the connection is alive
+    connector.update(&mut socket).expect("Could not update");
+    assert!(changes.lock().unwrap().is_empty());
+
+    connector.clock.advance(Duration::from_millis(1600));
+    connector.update(&mut socket).expect("Could not update");
+    connector.update(&mut socket).expect("Could not update");
+    let ping = bincode::serialize(&Packet::<u32>::Ping {
+        last_send_message_id: None,
+        nonce: 0,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &ping)
+        .expect("Could not handle ping");
+    connector
+        .handle_incoming_data(&mut socket, &ping)
+        .expect("Could not handle ping");
+    assert_eq!(
+        vec![
+            (NetworkState::Connected, NetworkState::Connecting),
+            (NetworkState::Connecting, NetworkState::Disconnected),
+            (NetworkState::Disconnected, NetworkState::Connected),
+        ],
+        *changes.lock().unwrap()
+    );
+}
+
+#[test]
+fn test_malformed_batch() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let data = |id, data| {
+        bincode::serialize(&Packet::Data {
+            message_id: NonZeroU64::new(id),
+            compressed: false,
+            data: data as u32,
+        })
+        .unwrap()
+    };
+    let mut batch = bincode::serialize(&Packet::<u32>::Batch {
+        packets: vec![data(1, 10), data(2, 20), data(3, 30)],
+    })
+    .unwrap();
+    // The length of the last packet claims more bytes than there are
+    let last_len = batch.len() - data(3, 30).len() - 8;
+    batch[last_len..last_len + 8].copy_from_slice(&1000u64.to_le_bytes());
+
+    let received = connector
+        .handle_incoming_data(&mut socket, &batch)
+        .expect("Could not handle batch");
+    assert_eq!(vec![10, 20], received);
+    assert_eq!(1, connector.stats().malformed_batches);
+    assert_eq!(
+        vec![
+            Packet::ConfirmPacket {
+                id: NonZeroU64::new(1).unwrap()
+            },
+            Packet::ConfirmPacket {
+                id: NonZeroU64::new(2).unwrap()
+            }
+        ],
+        socket.take_sent::<u32>()
+    );
+
+    // When the first packet is malformed, nothing was handled and the datagram is rejected
+    let mut batch = bincode::serialize(&Packet::<u32>::Batch {
+        packets: vec![data(4, 40)],
+    })
+    .unwrap();
+    batch.truncate(batch.len() - 1);
+    assert!(connector.handle_incoming_data(&mut socket, &batch).is_err());
+    assert!(socket.sent.is_empty());
+}
+
+struct CompactIds;
+impl ConnectorParam for CompactIds {
+    type TSend = u32;
+    type TReceive = u32;
+    const COMPACT_MESSAGE_IDS: bool = true;
+}
+
+#[test]
+fn test_compact_message_ids() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut sender = manual_connector::<CompactIds>();
+    let mut receiver = manual_connector::<CompactIds>();
+    // Send ids around the point where the lowest 32 bits wrap around
+    let first = (1u64 << 32) - 1;
+    sender.send.next_message_id = NonZeroU64::new(first);
+    receiver.receive.last_message_id = NonZeroU64::new(first - 1);
+    receiver.receive.highest_received_id = receiver.receive.last_message_id;
+
+    let ids = (0..3u32)
+        .map(|i| sender.send_confirmed(&mut socket, i).unwrap())
+        .collect::<Vec<_>>();
+    let datagrams = std::mem::take(&mut socket.sent);
+    // 4 bytes less than a packet with a full id
+    let full = bincode::serialize(&Packet::Data {
+        message_id: NonZeroU64::new(first),
+        compressed: false,
+        data: 0u32,
+    })
+    .unwrap();
+    assert!(datagrams
+        .iter()
+        .all(|(data, _)| data.len() == full.len() - 4));
+
+    let mut received = Vec::new();
+    for (data, _) in &datagrams {
+        received.extend(receiver.handle_incoming_data(&mut socket, data).unwrap());
+    }
+    assert_eq!(vec![0, 1, 2], received);
+    assert_eq!(NonZeroU64::new(first + 2), receiver.receive.last_message_id);
+    assert!(receiver.receive.missing_message_id_list.is_empty());
+
+    for (data, _) in std::mem::take(&mut socket.sent) {
+        sender.handle_incoming_data(&mut socket, &data).unwrap();
+    }
+    assert_eq!(
+        ids.into_iter().map(Event::Acknowledged).collect::<Vec<_>>(),
+        sender.take_events()
+    );
+}
+
+struct DelayedAcks;
+impl ConnectorParam for DelayedAcks {
+    type TSend = u32;
+    type TReceive = u32;
+    const ACK_DELAY_S: f64 = 0.1;
+    const CUMULATIVE_ACKS: bool = true;
+}
+
+#[test]
+fn test_ack_delay() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<DelayedAcks>();
+    let id = |id| NonZeroU64::new(id).unwrap();
+    for message_id in [1, 2, 5] {
+        let data = bincode::serialize(&Packet::Data {
+            message_id: Some(id(message_id)),
+            compressed: false,
+            data: 0u32,
+        })
+        .unwrap();
+        connector
+            .handle_incoming_data(&mut socket, &data)
+            .expect("Could not handle data");
+    }
+    assert!(socket.sent.is_empty());
+
+    connector.clock.advance(Duration::from_millis(50));
+    connector.update(&mut socket).expect("Could not update");
+    assert!(socket.sent.is_empty());
+
+    // All confirmations are send in a single datagram
+    connector.clock.advance(Duration::from_millis(60));
+    connector.update(&mut socket).expect("Could not update");
+    assert_eq!(1, socket.sent.len());
+    let (datagram, _) = socket.sent.remove(0);
+    let confirmations = packet::split_datagram(&datagram)
+        .map(|packet| bincode::deserialize::<Packet<u32>>(packet.unwrap()).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            Packet::ConfirmUpTo { id: id(2) },
+            Packet::ConfirmPacket { id: id(5) }
+        ],
+        confirmations
+    );
+
+    connector.clock.advance(Duration::from_millis(110));
+    connector.update(&mut socket).expect("Could not update");
+    assert!(socket.sent.is_empty());
+}
+
+#[test]
+fn test_confirm_unsent_message() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let id = connector.send_confirmed(&mut socket, 1u32).unwrap();
+
+    let unsent = NonZeroU64::new(id.get() + 1).unwrap();
+    for confirm in [
+        Packet::<u32>::ConfirmPacket { id: unsent },
+        Packet::<u32>::ConfirmUpTo { id: unsent },
+    ] {
+        let confirm = bincode::serialize(&confirm).unwrap();
+        connector
+            .handle_incoming_data(&mut socket, &confirm)
+            .expect("Could not handle confirmation");
+    }
+    assert_eq!(2, connector.stats().invalid_confirmations);
+    assert_eq!(0, connector.drain_events().count());
+    assert_eq!(
+        vec![id],
+        connector
+            .unconfirmed()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_missing_request_backlog_bytes() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    assert_eq!(0, connector.missing_request_backlog_bytes());
+
+    let data = bincode::serialize(&Packet::Data {
+        message_id: NonZeroU64::new(6),
+        compressed: false,
+        data: 0u32,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &data)
+        .expect("Could not handle data");
+    socket.sent.clear();
+
+    connector.clock.advance(Duration::from_millis(1));
+    connector.update(&mut socket).expect("Could not update");
+    let sent: usize = socket.sent.iter().map(|(data, _)| data.len()).sum();
+    assert_eq!(5, socket.sent.len());
+    assert_eq!(sent, connector.missing_request_backlog_bytes());
+}
+
+#[test]
+fn test_send_unconfirmed_ordered() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut sender = manual_connector::<ImmediateRequests>();
+    let mut receiver = manual_connector::<ImmediateRequests>();
+    for i in 0..3u32 {
+        sender
+            .send_unconfirmed_ordered(&mut socket, 1, i)
+            .expect("Could not send");
+    }
+    sender
+        .send_unconfirmed_ordered(&mut socket, 2, 10u32)
+        .expect("Could not send");
+    let datagrams = std::mem::take(&mut socket.sent);
+
+    // The newest message on stream 1 arrives first, the older messages are dropped
+    let mut received = Vec::new();
+    for index in [2, 0, 3, 1] {
+        received.extend(
+            receiver
+                .handle_incoming_data(&mut socket, &datagrams[index].0)
+                .expect("Could not handle data"),
+        );
+    }
+    assert_eq!(vec![2, 10], received);
+    assert_eq!(2, receiver.stats().out_of_order_drops);
+    assert!(socket.sent.is_empty());
+}
+
+struct FewStreams;
+impl ConnectorParam for FewStreams {
+    type TSend = u32;
+    type TReceive = u32;
+    const MAX_STREAMS: usize = 2;
+}
+
+#[test]
+fn test_ordered_streams_are_bounded() {
+    let mut receiver = manual_connector::<FewStreams>();
+    let ordered = |stream, sequence| Packet::OrderedData {
+        stream,
+        sequence,
+        data: stream,
+    };
+    inject_packet(&mut receiver, Packet::Hello { session: 1 });
+    assert_eq!(vec![1], inject_packet(&mut receiver, ordered(1, 5)).0);
+    assert_eq!(vec![2], inject_packet(&mut receiver, ordered(2, 5)).0);
+    assert!(inject_packet(&mut receiver, ordered(3, 5)).0.is_empty());
+    assert_eq!(1, receiver.stats().stream_limit_drops);
+    // The streams that are tracked still work
+    assert_eq!(vec![1], inject_packet(&mut receiver, ordered(1, 6)).0);
+    assert!(inject_packet(&mut receiver, ordered(2, 4)).0.is_empty());
+
+    // The sequences start at 0 again when the peer connects again
+    inject_packet(&mut receiver, Packet::Hello { session: 2 });
+    assert_eq!(vec![1], inject_packet(&mut receiver, ordered(1, 0)).0);
+    assert_eq!(vec![3], inject_packet(&mut receiver, ordered(3, 0)).0);
+    assert_eq!(1, receiver.stats().stream_limit_drops);
+}
+
+/// Let the connector measure the given round trip time with a ping
+fn measure_rtt<TParam: ConnectorParam>(connector: &mut Connector<TParam>, rtt: Duration) {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    connector.ping_now(&mut socket).expect("Could not ping");
+    let nonce = match socket.take_sent::<u32>().pop() {
+        Some(Packet::Ping { nonce, .. }) => nonce,
+        packet => panic!("Expected a ping, got {:?}", packet),
+    };
+    connector.clock.advance(rtt);
+    let pong = bincode::serialize(&Packet::<u32>::Pong {
+        last_send_message_id: None,
+        nonce,
+    })
+    .unwrap();
+    connector
+        .handle_incoming_data(&mut socket, &pong)
+        .expect("Could not handle pong");
+    assert_eq!(Some(rtt), connector.last_rtt());
+}
+
+#[test]
+fn test_validate() {
+    let mut connector = manual_connector::<CompactIds>();
+    connector.validate().expect("Nothing to validate yet");
+    measure_rtt(&mut connector, Duration::from_millis(40));
+    connector.validate().expect("The default settings are fine");
+
+    let mut connector = manual_connector::<ImmediateRequests>();
+    connector.validate().expect("Nothing to validate yet");
+    measure_rtt(&mut connector, Duration::from_millis(40));
+    match connector.validate().unwrap_err().downcast_ref::<Error>() {
+        Some(Error::IntervalShorterThanRtt { setting, rtt, .. }) => {
+            assert_eq!("REQUEST_MISSING_PACKET_INTERVAL_S", *setting);
+            assert_eq!(Duration::from_millis(40), *rtt);
+        }
+        e => panic!("Expected IntervalShorterThanRtt, got {:?}", e),
+    }
+}
+
+#[test]
+fn test_config_report() {
+    let connector = manual_connector::<PaddedDatagrams>();
+    let report = connector.config_report();
+    assert!(report.send_type.ends_with("ClientToServer"));
+    assert_eq!(0.5, report.ping_interval_s);
+    assert_eq!(1.5, report.receive_ping_timeout_s);
+    assert_eq!(Padding::Fixed(64), report.padding);
+    assert_eq!("Exact", report.address_filter);
+
+    let bytes = bincode::serialize(&report).unwrap();
+    assert_eq!(
+        report,
+        bincode::deserialize::<ConfigReport>(&bytes).unwrap()
+    );
+}
+
+#[test]
+fn test_reliable_stream_retransmitted_chunk() {
+    use std::io::{Read, Write};
+
+    let mut writer = ReliableStream::new(
+        manual_connector::<Stream>(),
+        MemorySocket::new("127.0.0.1:1"),
+    );
+    let mut reader = ReliableStream::new(
+        manual_connector::<Stream>(),
+        MemorySocket::new("127.0.0.1:1"),
+    );
+    writer
+        .connector_mut()
+        .connect(&mut MemorySocket::new("127.0.0.1:1"))
+        .unwrap();
+    let written = (0..48u8).collect::<Vec<_>>();
+    writer.write_all(&written).unwrap();
+    let datagrams = std::mem::take(&mut writer.socket_mut().sent)
+        .into_iter()
+        .map(|(data, _)| data)
+        .collect::<Vec<_>>();
+    assert_eq!(3, datagrams.len());
+
+    // The last chunk arrives twice, and the middle chunk is lost
+    let peer = "127.0.0.1:2".parse().unwrap();
+    for data in [&datagrams[0], &datagrams[2], &datagrams[2]] {
+        reader.socket_mut().incoming.push_back((data.clone(), peer));
+    }
+    let mut buffer = [0u8; 256];
+    assert_eq!(16, reader.read(&mut buffer).unwrap());
+    assert_eq!(&written[..16], &buffer[..16]);
+    let e = reader.read(&mut buffer).unwrap_err();
+    assert_eq!(ErrorKind::WouldBlock, e.kind());
+    assert_eq!(1, reader.connector().stats().duplicate_messages);
+
+    // Only the middle chunk is resend, with the same message id and offset as before
+    for (data, _) in std::mem::take(&mut reader.socket_mut().sent) {
+        writer
+            .connector_mut()
+            .handle_incoming_data(&mut MemorySocket::new("127.0.0.1:1"), &data)
+            .unwrap();
+    }
+    writer
+        .connector_mut()
+        .clock
+        .advance(Duration::from_millis(1100));
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    writer.connector_mut().update(&mut socket).unwrap();
+    let resend = socket
+        .sent
+        .into_iter()
+        .map(|(data, _)| data)
+        .filter(|data| {
+            matches!(
+                bincode::deserialize(data),
+                Ok(Packet::<StreamChunk>::Data { .. })
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(vec![datagrams[1].clone()], resend);
+
+    reader
+        .socket_mut()
+        .incoming
+        .push_back((resend[0].clone(), peer));
+    reader
+        .socket_mut()
+        .incoming
+        .push_back((datagrams[1].clone(), peer));
+    let mut read = Vec::new();
+    while let Ok(count) = reader.read(&mut buffer) {
+        read.extend_from_slice(&buffer[..count]);
+    }
+    assert_eq!(&written[16..], &read[..]);
+    assert_eq!(2, reader.connector().stats().duplicate_messages);
+}
+
+struct SmallReorderBuffer;
+impl ConnectorParam for SmallReorderBuffer {
+    type TSend = StreamChunk;
+    type TReceive = StreamChunk;
+    const MAX_DATAGRAM_SIZE: usize = 45;
+    const MAX_REORDER_BUFFER: usize = 2;
+}
+
+#[test]
+fn test_reorder_buffer_overflow() {
+    use std::io::{Read, Write};
+
+    let mut writer = ReliableStream::new(
+        manual_connector::<SmallReorderBuffer>(),
+        MemorySocket::new("127.0.0.1:1"),
+    );
+    let mut reader = ReliableStream::new(
+        manual_connector::<SmallReorderBuffer>(),
+        MemorySocket::new("127.0.0.1:1"),
+    );
+    writer.write_all(&[0u8; 100]).unwrap();
+    let datagrams = std::mem::take(&mut writer.socket_mut().sent);
+
+    // The first chunk never arrives
+    let peer = "127.0.0.1:2".parse().unwrap();
+    for (data, _) in &datagrams[1..3] {
+        reader.socket_mut().incoming.push_back((data.clone(), peer));
+    }
+    let mut buffer = [0u8; 256];
+    let e = reader.read(&mut buffer).unwrap_err();
+    assert_eq!(ErrorKind::WouldBlock, e.kind());
+
+    reader
+        .socket_mut()
+        .incoming
+        .push_back((datagrams[3].0.clone(), peer));
+    let e = reader.read(&mut buffer).unwrap_err();
+    assert!(e.to_string().contains("out of order"), "{}", e);
+    assert_eq!(NetworkState::Disconnected, reader.connector().state());
+    assert!(matches!(
+        reader.socket_mut().take_sent::<StreamChunk>().last(),
+        Some(Packet::Disconnect { .. })
+    ));
+}
+
+/// A datagram with the handshake of a peer that connects, followed by an unconfirmed message
+fn handshake_with(message: u32) -> Vec<u8> {
+    let hello = Packet::<u32>::Hello { session: 1 };
+    let data = Packet::<u32>::Data {
+        message_id: None,
+        compressed: false,
+        data: message,
+    };
+    let packets = vec![
+        bincode::serialize(&hello).unwrap(),
+        bincode::serialize(&data).unwrap(),
+    ];
+    bincode::serialize(&Packet::<u32>::Batch { packets }).unwrap()
+}
+
+#[test]
+fn test_connector_pool_receive_from() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut pool = ConnectorPool::<ImmediateRequests>::new();
+    let first = "127.0.0.1:2".parse().unwrap();
+    let second = "127.0.0.1:3".parse().unwrap();
+    socket.incoming.push_back((handshake_with(5), first));
+    socket.incoming.push_back((vec![0xff; 3], first));
+    socket
+        .incoming
+        .push_back((handshake_with(6), socket.local_addr));
+    socket.incoming.push_back((handshake_with(7), second));
+
+    assert_eq!(
+        vec![(first, 5), (second, 7)],
+        pool.receive_from(&mut socket).unwrap()
+    );
+    assert_eq!(2, pool.peer_count());
+    assert!(pool
+        .get_mut(first)
+        .unwrap()
+        .drain_events()
+        .any(|event| matches!(event, Event::MalformedPacket { .. })));
+    assert!(pool.receive_from(&mut socket).unwrap().is_empty());
+}
+
+#[test]
+fn test_connector_pool() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut pool = ConnectorPool::<ImmediateRequests>::new();
+    let first = "127.0.0.1:2".parse().unwrap();
+    let second = "127.0.0.1:3".parse().unwrap();
+    let data = handshake_with(5);
+    assert_eq!(
+        vec![5],
+        pool.handle_incoming_data(&mut socket, first, &data)
+            .unwrap()
+    );
+    pool.handle_incoming_data(&mut socket, second, &data)
+        .unwrap();
+    assert_eq!(2, pool.peer_count());
+
+    for (_, connector) in pool.iter_mut() {
+        connector.clock = Clock::manual();
+    }
+    pool.get_mut(second)
+        .unwrap()
+        .close(&mut socket, None)
+        .unwrap();
+    assert_eq!(vec![first], pool.connected_peers().collect::<Vec<_>>());
+    assert_eq!(2, pool.peer_count());
+
+    assert!(pool.remove(second).is_some());
+    assert_eq!(1, pool.peer_count());
+
+    // A full pool ignores new peers
+    pool.set_max_peers(Some(1));
+    assert!(pool
+        .handle_incoming_data(&mut socket, second, &data)
+        .unwrap()
+        .is_empty());
+    assert!(pool.get(second).is_none());
+    assert_eq!(
+        vec![5],
+        pool.handle_incoming_data(&mut socket, first, &data)
+            .unwrap()
+    );
+    pool.remove(first);
+    pool.handle_incoming_data(&mut socket, second, &data)
+        .unwrap();
+    assert_eq!(1, pool.peer_count());
+    assert!(pool.get(second).is_some());
+}
+
+#[test]
+fn test_connector_pool_new_peer() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut pool = ConnectorPool::<ImmediateRequests>::new();
+    let peer = "127.0.0.1:2".parse().unwrap();
+    let data = bincode::serialize(&Packet::<_>::Data {
+        message_id: None,
+        compressed: false,
+        data: 5u32,
+    })
+    .unwrap();
+    let mut unknown = 100u32.to_le_bytes().to_vec();
+    unknown.extend_from_slice(&[1, 2, 3]);
+    let new_peer_events = |pool: &mut ConnectorPool<ImmediateRequests>| {
+        pool.get_mut(peer)
+            .unwrap()
+            .take_events()
+            .into_iter()
+            .filter(|event| matches!(event, Event::NewPeer(_)))
+            .collect::<Vec<_>>()
+    };
+
+    // Datagrams without a handshake do not create a connector, even if they could be handled
+    for datagram in [vec![0xff; 3], unknown, data.clone()] {
+        assert!(pool
+            .handle_incoming_data(&mut socket, peer, &datagram)
+            .unwrap()
+            .is_empty());
+        assert!(pool.get(peer).is_none());
+    }
+    // Neither does a handshake that is corrupt
+    let hello = bincode::serialize(&Packet::<u32>::Hello { session: 1 }).unwrap();
+    let corrupt = bincode::serialize(&Packet::<u32>::Batch {
+        packets: vec![hello[..5].to_vec(), data.clone()],
+    })
+    .unwrap();
+    assert!(pool
+        .handle_incoming_data(&mut socket, peer, &corrupt)
+        .is_err());
+    assert!(pool.get(peer).is_none());
+
+    // The new peer is reported before the other events of its handshake
+    let handshake = bincode::serialize(&Packet::<u32>::Batch {
+        packets: vec![hello, vec![0xff; 3]],
+    })
+    .unwrap();
+    pool.handle_incoming_data(&mut socket, peer, &handshake)
+        .unwrap();
+    assert!(matches!(
+        pool.get_mut(peer).unwrap().take_events()[..],
+        [Event::NewPeer(_), Event::MalformedPacket { .. }]
+    ));
+    pool.handle_incoming_data(&mut socket, peer, &data).unwrap();
+    assert!(new_peer_events(&mut pool).is_empty());
+
+    // A new connector for the same address is a new peer
+    pool.remove(peer);
+    pool.handle_incoming_data(&mut socket, peer, &handshake_with(5))
+        .unwrap();
+    assert_eq!(vec![Event::NewPeer(peer)], new_peer_events(&mut pool));
+}
+
+#[test]
+fn test_connector_pool_keys() {
+    let mut pool = ConnectorPool::<ImmediateRequests, u32>::new();
+    let first = "127.0.0.1:2".parse().unwrap();
+    let second = "127.0.0.1:3".parse().unwrap();
+    assert!(!pool.associate_key(first, 42));
+    pool.get_or_insert(first);
+    pool.get_or_insert(second);
+
+    assert!(pool.associate_key(first, 42));
+    assert_eq!(Some(first), pool.peer_addr_of(&42));
+    assert_eq!(Some(first), pool.get_by_key(&42).map(Connector::bound_addr));
+    assert!(pool.get_by_key_mut(&7).is_none());
+
+    // The player logged in again from another address
+    assert!(pool.associate_key(second, 42));
+    assert_eq!(Some(second), pool.peer_addr_of(&42));
+    assert!(pool.associate_key(second, 43));
+    assert_eq!(None, pool.peer_addr_of(&42));
+
+    pool.remove(second);
+    assert!(pool.get_by_key(&43).is_none());
+    assert_eq!(first, pool.get_or_insert(first).bound_addr());
+}
+
+struct StrictPackets;
+impl ConnectorParam for StrictPackets {
+    type TSend = u32;
+    type TReceive = u32;
+    const IGNORE_UNKNOWN_PACKETS: bool = false;
+}
+
+#[test]
+fn test_unknown_packets() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    // A packet kind from a newer version, with a variant index this version does not know
+    let mut unknown = 100u32.to_le_bytes().to_vec();
+    unknown.extend_from_slice(&[1, 2, 3]);
+    let data = bincode::serialize(&Packet::<_>::Data {
+        message_id: None,
+        compressed: false,
+        data: 5u32,
+    })
+    .unwrap();
+
+    assert!(connector
+        .handle_incoming_data(&mut socket, &unknown)
+        .expect("Could not handle unknown packet")
+        .is_empty());
+    let batch = bincode::serialize(&Packet::<u32>::Batch {
+        packets: vec![unknown.clone(), data.clone()],
+    })
+    .unwrap();
+    assert_eq!(
+        vec![5],
+        connector
+            .handle_incoming_data(&mut socket, &batch)
+            .expect("Could not handle batch")
+    );
+    assert_eq!(2, connector.stats().unknown_packets);
+    assert_eq!(0, connector.stats().malformed_batches);
+    assert_eq!(NetworkState::Connected, connector.state());
+
+    let mut connector = manual_connector::<StrictPackets>();
+    assert!(connector
+        .handle_incoming_data(&mut socket, &unknown)
+        .is_err());
+    assert_eq!(0, connector.stats().unknown_packets);
+}
+
+#[test]
+fn test_time_until_disconnect() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    connector.connect(&mut socket).unwrap();
+    assert_eq!(
+        Some(Duration::from_millis(1500)),
+        connector.time_until_disconnect()
+    );
+
+    connector.clock.advance(Duration::from_secs(1));
+    assert_eq!(
+        Some(Duration::from_millis(500)),
+        connector.time_until_disconnect()
+    );
+
+    // A ping of the peer resets the countdown
+    inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: None,
+            nonce: 0,
+        },
+    );
+    assert_eq!(
+        Some(Duration::from_millis(1500)),
+        connector.time_until_disconnect()
+    );
+
+    connector.clock.advance(Duration::from_secs(2));
+    assert_eq!(None, connector.time_until_disconnect());
+
+    connector.connect(&mut socket).unwrap();
+    assert!(connector.time_until_disconnect().is_some());
+    connector.close(&mut socket, None).unwrap();
+    assert_eq!(None, connector.time_until_disconnect());
+}
+
+struct AnyPacketIsPing;
+impl ConnectorParam for AnyPacketIsPing {
+    type TSend = u32;
+    type TReceive = u32;
+    const ANY_PACKET_IS_PING: bool = true;
+}
+
+#[test]
+fn test_any_packet_is_ping() {
+    let data = || Packet::Data {
+        message_id: None,
+        compressed: false,
+        data: 1u32,
+    };
+
+    // By default, data does not count as a ping
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    connector.connect(&mut socket).unwrap();
+    connector.clock.advance(Duration::from_secs(1));
+    inject_packet(&mut connector, data());
+    assert_eq!(
+        Some(Duration::from_millis(500)),
+        connector.time_until_disconnect()
+    );
+
+    let mut connector = manual_connector::<AnyPacketIsPing>();
+    connector.connect(&mut socket).unwrap();
+    for _ in 0..4 {
+        connector.clock.advance(Duration::from_secs(1));
+        let (received, _) = inject_packet(&mut connector, data());
+        assert_eq!(vec![1], received);
+        assert_eq!(NetworkState::Connected, connector.state());
+    }
+    assert_eq!(
+        Some(Duration::from_millis(1500)),
+        connector.time_until_disconnect()
+    );
+
+    // Garbage does not keep the connection alive
+    connector.clock.advance(Duration::from_secs(1));
+    assert!(connector
+        .handle_incoming_data(&mut socket, &[0xff; 3])
+        .is_err());
+    assert_eq!(
+        Some(Duration::from_millis(500)),
+        connector.time_until_disconnect()
+    );
+}
+
+#[test]
+fn test_send_confirmed_await() {
+    use std::future::Future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct CountingWaker(AtomicUsize);
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = Waker::from(counter.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let mut delivery = connector.send_confirmed_await(&mut socket, 5u32).unwrap();
+    let mut delivery = std::pin::Pin::new(&mut delivery);
+    assert!(delivery.as_mut().poll(&mut cx).is_pending());
+
+    inject_packet(&mut connector, Packet::ConfirmPacket { id: delivery.id() });
+    assert_eq!(1, counter.0.load(Ordering::SeqCst));
+    assert!(matches!(
+        delivery.as_mut().poll(&mut cx),
+        Poll::Ready(Ok(()))
+    ));
+
+    // A message that expires is never delivered
+    let deadline = connector.now() + Duration::from_millis(500);
+    let mut expired = connector
+        .send_confirmed_await_with_deadline(&mut socket, 6u32, deadline)
+        .unwrap();
+    assert!(std::pin::Pin::new(&mut expired).poll(&mut cx).is_pending());
+    connector.clock.advance(Duration::from_secs(1));
+    connector.update(&mut socket).unwrap();
+    assert_eq!(2, counter.0.load(Ordering::SeqCst));
+    match std::pin::Pin::new(&mut expired).poll(&mut cx) {
+        Poll::Ready(Err(e)) => assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::NotDelivered { .. })
+        )),
+        _ => panic!("Expected the delivery to fail"),
+    }
+
+    // Reconnecting forgets the unconfirmed messages
+    let mut forgotten = connector.send_confirmed_await(&mut socket, 7u32).unwrap();
+    connector.connect(&mut socket).unwrap();
+    assert!(matches!(
+        std::pin::Pin::new(&mut forgotten).poll(&mut cx),
+        Poll::Ready(Err(_))
+    ));
+}
+
+struct LimitedCache;
+impl ConnectorParam for LimitedCache {
+    type TSend = Vec<u8>;
+    type TReceive = Vec<u8>;
+    const MAX_UNCONFIRMED_BYTES: Option<usize> = Some(250);
+}
+
+#[test]
+fn test_max_unconfirmed_bytes() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<LimitedCache>();
+    let first = connector
+        .send_confirmed(&mut socket, vec![0u8; 100])
+        .unwrap();
+    connector
+        .send_confirmed(&mut socket, vec![0u8; 100])
+        .unwrap();
+    // Every message takes 4 + 1 + 8 + 1 + 8 bytes on top of its data
+    assert_eq!(244, connector.unconfirmed_bytes());
+
+    let error = connector
+        .send_confirmed(&mut socket, vec![0u8; 1])
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<Error>(),
+        Some(Error::CacheFull { bytes: 244 })
+    ));
+    assert_eq!(2, socket.sent.len());
+
+    // A confirmation makes room again
+    inject_packet(&mut connector, Packet::ConfirmPacket { id: first });
+    assert_eq!(122, connector.unconfirmed_bytes());
+    connector
+        .send_confirmed(&mut socket, vec![0u8; 100])
+        .unwrap();
+    assert_eq!(244, connector.unconfirmed_bytes());
+
+    connector.connect(&mut socket).unwrap();
+    assert_eq!(0, connector.unconfirmed_bytes());
+}
+
+struct CompactLimitedCache;
+impl ConnectorParam for CompactLimitedCache {
+    type TSend = u32;
+    type TReceive = u32;
+    const COMPACT_MESSAGE_IDS: bool = true;
+    const MAX_UNCONFIRMED_BYTES: Option<usize> = Some(28);
+}
+
+#[test]
+fn test_max_unconfirmed_bytes_compact() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<CompactLimitedCache>();
+    connector.send_confirmed(&mut socket, 1u32).unwrap();
+    // A compact id takes 4 bytes instead of 8, and the limit counts the bytes that are send
+    assert_eq!(14, connector.unconfirmed_bytes());
+    connector.send_confirmed(&mut socket, 2u32).unwrap();
+    assert_eq!(28, connector.unconfirmed_bytes());
+    assert!(connector.send_confirmed(&mut socket, 3u32).is_err());
+}
+
+#[test]
+fn test_request_selector() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: NonZeroU64::new(5),
+            nonce: 0,
+        },
+    );
+
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let selector_seen = seen.clone();
+    connector.set_request_selector(Some(Box::new(move |missing| {
+        *selector_seen.lock().unwrap() = missing.to_vec();
+        // Only the even messages are still relevant, and 8 is not missing at all
+        vec![
+            NonZeroU64::new(2).unwrap(),
+            NonZeroU64::new(4).unwrap(),
+            NonZeroU64::new(8).unwrap(),
+        ]
+    })));
+    connector.clock.advance(Duration::from_millis(100));
+    connector.update(&mut socket).unwrap();
+    assert_eq!(vec![2, 4], requested_ids(&mut socket));
+    assert_eq!(
+        (1..=5)
+            .map(|id| (NonZeroU64::new(id).unwrap(), Duration::from_millis(100)))
+            .collect::<Vec<_>>(),
+        *seen.lock().unwrap()
+    );
+
+    connector.set_request_selector(None);
+    connector.clock.advance(Duration::from_millis(1));
+    connector.update(&mut socket).unwrap();
+    // The messages that were not requested by the selector were waiting the longest
+    assert_eq!(vec![1, 3, 5, 2, 4], requested_ids(&mut socket));
+}
+
+#[test]
+fn test_request_selector_duplicates() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: NonZeroU64::new(3),
+            nonce: 0,
+        },
+    );
+    let id = |id| NonZeroU64::new(id).unwrap();
+    connector.set_request_selector(Some(Box::new(move |_| {
+        vec![id(3), id(1), id(3), id(7), id(1)]
+    })));
+    connector.clock.advance(Duration::from_millis(100));
+    connector.update(&mut socket).unwrap();
+    // Every missing id is requested once
+    assert_eq!(vec![1, 3], requested_ids(&mut socket));
+}
+
+#[test]
+fn test_packet_roundtrip() {
+    let id = |id| NonZeroU64::new(id).unwrap();
+    // Every variant with the variant index it must have on the wire
+    let packets = vec![
+        (
+            0u32,
+            Packet::Ping {
+                last_send_message_id: Some(id(3)),
+                nonce: 7,
+            },
+        ),
+        (
+            0,
+            Packet::Ping {
+                last_send_message_id: None,
+                nonce: u32::MAX,
+            },
+        ),
+        (
+            1,
+            Packet::Pong {
+                last_send_message_id: Some(id(u64::MAX)),
+                nonce: 7,
+            },
+        ),
+        (2, Packet::PacketNotFound { id: id(1) }),
+        (3, Packet::RequestPacket { id: id(2) }),
+        (4, Packet::ConfirmPacket { id: id(3) }),
+        (
+            5,
+            Packet::Data {
+                message_id: Some(id(4)),
+                compressed: false,
+                data: ClientToServer::SendMessage {
+                    name: String::from("confirmed"),
+                },
+            },
+        ),
+        (
+            5,
+            Packet::Data {
+                message_id: None,
+                compressed: false,
+                data: ClientToServer::SendMessage {
+                    name: String::new(),
+                },
+            },
+        ),
+        (6, Packet::Disconnect { reason: None }),
+        (
+            6,
+            Packet::Disconnect {
+                reason: Some(DisconnectReason::with_message(3, "kicked")),
+            },
+        ),
+        (
+            7,
+            Packet::Batch {
+                packets: vec![vec![1, 2, 3], Vec::new()],
+            },
+        ),
+        (8, Packet::ConfirmUpTo { id: id(5) }),
+        (
+            9,
+            Packet::OrderedData {
+                stream: 2,
+                sequence: 6,
+                data: ClientToServer::SendMessage {
+                    name: String::from("ordered"),
+                },
+            },
+        ),
+        (10, Packet::Idle { acknowledged: true }),
+        (
+            11,
+            Packet::Wrap {
+                epoch: 1,
+                acknowledged: false,
+            },
+        ),
+        (
+            12,
+            Packet::CheckedBatch {
+                packets: vec![(3, vec![1, 2])],
+            },
+        ),
+        (13, Packet::Processed { id: id(6) }),
+        (14, Packet::AlreadyDelivered { id: id(7) }),
+        (
+            15,
+            Packet::Time {
+                nonce: 3,
+                time_us: 1_000_000,
+            },
+        ),
+        (
+            16,
+            Packet::Capabilities {
+                flags: 1,
+                acknowledged: true,
+            },
+        ),
+        (
+            17,
+            Packet::SequenceAck {
+                stream: 2,
+                sequence: 9,
+                received: 8,
+            },
+        ),
+        (18, Packet::Hello { session: 0xdead }),
+    ];
+
+    for (tag, packet) in packets {
+        let bytes = bincode::serialize(&packet).unwrap();
+        assert_eq!(tag.to_le_bytes(), bytes[..4], "{:?}", packet);
+        assert_eq!(
+            packet,
+            bincode::deserialize::<Packet<ClientToServer>>(&bytes).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_last_tag() {
+    // `LAST_TAG` is the variant index of the last variant, so the index after it is unknown
+    let last = bincode::serialize(&Packet::<u32>::Hello { session: 1 }).unwrap();
+    assert_eq!(packet::LAST_TAG.to_le_bytes(), last[..4]);
+    let mut unknown = last;
+    unknown[..4].copy_from_slice(&(packet::LAST_TAG + 1).to_le_bytes());
+    assert!(bincode::deserialize::<Packet<u32>>(&unknown).is_err());
+    assert!(packet::is_unknown(&unknown));
+}
+
+struct IdleAfterOneSecond;
+impl ConnectorParam for IdleAfterOneSecond {
+    type TSend = u32;
+    type TReceive = u32;
+    const IDLE_AFTER_S: Option<f64> = Some(1.);
+}
+
+#[test]
+fn test_idle() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<IdleAfterOneSecond>();
+    connector.connect(&mut socket).unwrap();
+    let ping = || Packet::Ping {
+        last_send_message_id: None,
+        nonce: 0,
+    };
+    let idle = |acknowledged| Packet::<u32>::Idle { acknowledged };
+
+    // No data for a second, so the connector offers to go idle with its next ping
+    connector.clock.advance(Duration::from_millis(1100));
+    inject_packet(&mut connector, ping());
+    socket.sent.clear();
+    connector.update(&mut socket).unwrap();
+    let sent = socket.take_sent::<u32>();
+    assert_eq!(Some(&idle(false)), sent.last());
+    assert!(!connector.is_idle());
+
+    // The peer has no data either, and expects our pings less often
+    let (_, sent) = inject_packet(&mut connector, idle(true));
+    assert_eq!(vec![idle(true)], sent);
+    assert!(connector.is_idle());
+    assert_eq!(vec![Event::IdleStarted], connector.take_events());
+
+    // Pings are slow, and the peer is expected to ping slowly as well
+    connector.clock.advance(Duration::from_secs(9));
+    connector.update(&mut socket).unwrap();
+    assert!(socket.sent.is_empty());
+    assert_eq!(NetworkState::Connected, connector.state());
+    connector.clock.advance(Duration::from_millis(1500));
+    connector.update(&mut socket).unwrap();
+    // The handshake never completed, so the ping is still preceded by our session
+    assert!(matches!(
+        socket.take_packets::<u32>()[..],
+        [
+            Packet::Hello { .. },
+            Packet::Ping { .. },
+            Packet::Idle { acknowledged: true }
+        ]
+    ));
+    assert_eq!(NetworkState::Connected, connector.state());
+
+    // Data from the peer ends idle mode on both sides
+    inject_packet(
+        &mut connector,
+        Packet::Data {
+            message_id: None,
+            compressed: false,
+            data: 5,
+        },
+    );
+    assert!(!connector.is_idle());
+    assert_eq!(vec![Event::IdleEnded], connector.take_events());
+    assert_eq!(NetworkState::Connected, connector.state());
+    connector.clock.advance(Duration::from_millis(600));
+    connector.update(&mut socket).unwrap();
+    assert!(matches!(
+        socket.take_packets::<u32>()[..],
+        [Packet::Hello { .. }, Packet::Ping { .. }]
+    ));
+
+    // Sending data ends idle mode as well
+    connector.clock.advance(Duration::from_millis(1100));
+    inject_packet(&mut connector, ping());
+    connector.update(&mut socket).unwrap();
+    inject_packet(&mut connector, idle(true));
+    assert!(connector.is_idle());
+    connector.send_unconfirmed(&mut socket, 6u32).unwrap();
+    assert!(!connector.is_idle());
+    assert_eq!(
+        vec![Event::IdleStarted, Event::IdleEnded],
+        connector.take_events()
+    );
+}
+
+#[test]
+fn test_malformed_packet_event() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    // A `Packet::Data` with a `u32` that is cut short
+    let malformed = vec![5, 0, 0, 0, 0, 7, 0];
+    assert!(connector
+        .handle_incoming_data(&mut socket, &malformed)
+        .is_err());
+    let events = connector.take_events();
+    match &events[..] {
+        [Event::MalformedPacket { from, data, error }] => {
+            assert_eq!(connector.bound_addr(), *from);
+            assert_eq!(&malformed, data);
+            assert!(!error.is_empty());
+        }
+        events => panic!("Unexpected events {:?}", events),
+    }
+
+    // A malformed packet in a batch is reported by itself
+    let data = bincode::serialize(&Packet::<_>::Data {
+        message_id: None,
+        compressed: false,
+        data: 5u32,
+    })
+    .unwrap();
+    let batch = bincode::serialize(&Packet::<u32>::Batch {
+        packets: vec![data, malformed.clone()],
+    })
+    .unwrap();
+    assert_eq!(
+        vec![5],
+        connector.handle_incoming_data(&mut socket, &batch).unwrap()
+    );
+    assert!(matches!(
+        &connector.take_events()[..],
+        [Event::MalformedPacket { data, .. }] if *data == malformed
+    ));
+
+    assert_eq!(
+        "0000  05 00 00 00 00 07 00                             |.......|\n",
+        hex_dump(&malformed)
+    );
+    assert_eq!(
+        "0000  68 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0a 00 ff  |hello, world!...|\n\
+         0010  41                                               |A|\n",
+        hex_dump(b"hello, world!\n\0\xffA")
+    );
+}
+
+/// Two connectors that are connected through memory sockets, with a link that can go down
+struct Link<P: ConnectorParam> {
+    client: Connector<P>,
+    server: Connector<P>,
+    client_socket: MemorySocket,
+    server_socket: MemorySocket,
+    /// The messages the server received, in order
+    received: Vec<u32>,
+}
+
+impl<P: ConnectorParam<TSend = u32, TReceive = u32>> Link<P> {
+    fn new() -> Link<P> {
+        let mut client_socket = MemorySocket::new("127.0.0.1:2");
+        let mut server_socket = MemorySocket::new("127.0.0.1:1");
+        let mut client = manual_connector::<P>();
+        let mut server = Connector::bound_to(client_socket.local_addr);
+        server.clock = client.clock;
+        client.connect(&mut client_socket).unwrap();
+        server.connect(&mut server_socket).unwrap();
+        Link {
+            client,
+            server,
+            client_socket,
+            server_socket,
+            received: Vec::new(),
+        }
+    }
+
+    /// Deliver what the client sent to the server
+    fn deliver_to_server(&mut self) {
+        for (data, _) in std::mem::take(&mut self.client_socket.sent) {
+            let messages = self
+                .server
+                .handle_incoming_data(&mut self.server_socket, &data)
+                .unwrap();
+            self.received.extend(messages);
+        }
+    }
+
+    /// Deliver everything that was sent if the link is up, then advance the time and update both sides
+    fn step(&mut self, link_up: bool) {
+        if link_up {
+            self.deliver_to_server();
+            for (data, _) in std::mem::take(&mut self.server_socket.sent) {
+                self.client
+                    .handle_incoming_data(&mut self.client_socket, &data)
+                    .unwrap();
+            }
+        } else {
+            self.client_socket.sent.clear();
+            self.server_socket.sent.clear();
+        }
+        self.client.clock.advance(Duration::from_millis(100));
+        self.server.clock.advance(Duration::from_millis(100));
+        self.client.update(&mut self.client_socket).unwrap();
+        self.server.update(&mut self.server_socket).unwrap();
+    }
+}
+
+#[test]
+fn test_confirmed_delivery_survives_outage() {
+    let mut link = Link::<ImmediateRequests>::new();
+    for _ in 0..5 {
+        link.step(true);
+    }
+    assert_eq!(NetworkState::Connected, link.client.state());
+    assert_eq!(NetworkState::Connected, link.server.state());
+
+    // The first messages arrive, but the link goes down before they are confirmed
+    for i in 0..3u32 {
+        link.client
+            .send_confirmed(&mut link.client_socket, i)
+            .unwrap();
+    }
+    link.deliver_to_server();
+    // These are send after the link went down, so they do not arrive until it is restored
+    for i in 3..6u32 {
+        link.client
+            .send_confirmed(&mut link.client_socket, i)
+            .unwrap();
+    }
+    for _ in 0..50 {
+        link.step(false);
+    }
+    assert_eq!(NetworkState::Disconnected, link.client.state());
+    assert_eq!(NetworkState::Disconnected, link.server.state());
+
+    // Both sides keep trying to reach each other, and resend the messages once they do
+    for _ in 0..100 {
+        link.step(true);
+    }
+    assert_eq!(NetworkState::Connected, link.client.state());
+    assert_eq!(NetworkState::Connected, link.server.state());
+    assert_eq!(0, link.client.unconfirmed().count());
+    // The first messages were resend because their confirmations were lost, but are only received once
+    assert_eq!(vec![0, 1, 2, 3, 4, 5], link.received);
+    assert!(link.server.stats().duplicate_messages >= 3);
+}
+
+#[test]
+fn test_peer_reconnect_resets_received_ids() {
+    let mut link = Link::<ImmediateRequests>::new();
+    link.step(true);
+    for i in 0..3u32 {
+        link.client
+            .send_confirmed(&mut link.client_socket, i)
+            .unwrap();
+    }
+    link.step(true);
+    assert_eq!(vec![0, 1, 2], link.received);
+
+    // The client starts over, so its ids start at 1 again
+    link.client.connect(&mut link.client_socket).unwrap();
+    link.step(true);
+    let id = link
+        .client
+        .send_confirmed(&mut link.client_socket, 3u32)
+        .unwrap();
+    assert_eq!(1, id.get());
+    for _ in 0..3 {
+        link.step(true);
+    }
+    assert_eq!(vec![0, 1, 2, 3], link.received);
+    assert_eq!(0, link.client.unconfirmed().count());
+    assert_eq!(0, link.server.stats().duplicate_messages);
+    assert_eq!(Some(id), link.server.last_received_id());
+}
+
+struct AsymmetricMtu;
+impl ConnectorParam for AsymmetricMtu {
+    type TSend = u32;
+    type TReceive = u32;
+    const BUFFER_SENDS: bool = true;
+    // Room for a batch of two unconfirmed `u32` messages of 10 bytes each
+    const MAX_SEND_DATAGRAM_SIZE: usize = 4 + 8 + 2 * (8 + 10);
+    const MAX_RECEIVE_DATAGRAM_SIZE: usize = 1024;
+}
+
+#[test]
+fn test_asymmetric_datagram_sizes() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<AsymmetricMtu>();
+    for i in 0..4u32 {
+        connector.send_unconfirmed(&mut socket, i).unwrap();
+    }
+    connector.flush(&mut socket).unwrap();
+    assert_eq!(2, socket.sent.len());
+    assert!(socket
+        .sent
+        .iter()
+        .all(|(data, _)| data.len() <= AsymmetricMtu::MAX_SEND_DATAGRAM_SIZE));
+
+    // The peer may send larger datagrams than we do
+    let packets = (0..4u32)
+        .map(|i| {
+            bincode::serialize(&Packet::<_>::Data {
+                message_id: None,
+                compressed: false,
+                data: i,
+            })
+            .unwrap()
+        })
+        .collect();
+    let batch = bincode::serialize(&Packet::<u32>::Batch { packets }).unwrap();
+    assert!(batch.len() > AsymmetricMtu::MAX_SEND_DATAGRAM_SIZE);
+    socket.incoming.push_back((batch, connector.bound_addr()));
+    assert_eq!(
+        vec![0, 1, 2, 3],
+        connector.receive_from(&mut socket).unwrap()
+    );
+
+    let report = connector.config_report();
+    assert_eq!(48, report.max_send_datagram_size);
+    assert_eq!(1024, report.max_receive_datagram_size);
+}
+
+struct WrapMessageIds;
+impl ConnectorParam for WrapMessageIds {
+    type TSend = u32;
+    type TReceive = u32;
+    const REQUEST_MISSING_PACKET_INTERVAL_S: f64 = 0.;
+    const WRAP_MESSAGE_IDS: bool = true;
+}
+
+fn is_ids_exhausted(result: Result<NonZeroU64>) -> bool {
+    matches!(
+        result.unwrap_err().downcast_ref::<Error>(),
+        Some(Error::MessageIdsExhausted)
+    )
+}
+
+#[test]
+fn test_message_ids_exhausted() {
+    let mut link = Link::<ImmediateRequests>::new();
+    link.step(true);
+    link.client.send.next_message_id = NonZeroU64::new(u64::MAX);
+    link.server.receive.last_message_id = NonZeroU64::new(u64::MAX - 1);
+    link.server.receive.highest_received_id = link.server.receive.last_message_id;
+
+    assert_eq!(
+        u64::MAX,
+        link.client
+            .send_confirmed(&mut link.client_socket, 1u32)
+            .unwrap()
+            .get()
+    );
+    for _ in 0..3 {
+        link.step(true);
+    }
+    assert_eq!(vec![1], link.received);
+    assert_eq!(0, link.client.unconfirmed_bytes());
+
+    // Without wrapping, the ids never start over
+    assert!(is_ids_exhausted(
+        link.client.send_confirmed(&mut link.client_socket, 2u32)
+    ));
+    link.step(true);
+    assert!(is_ids_exhausted(
+        link.client.send_confirmed(&mut link.client_socket, 2u32)
+    ));
+}
+
+#[test]
+fn test_wrap_message_ids() {
+    let mut link = Link::<WrapMessageIds>::new();
+    link.step(true);
+    link.client.send.next_message_id = NonZeroU64::new(u64::MAX - 1);
+    link.server.receive.last_message_id = NonZeroU64::new(u64::MAX - 2);
+    link.server.receive.highest_received_id = link.server.receive.last_message_id;
+
+    link.client
+        .send_confirmed(&mut link.client_socket, 1u32)
+        .unwrap();
+    link.client
+        .send_confirmed(&mut link.client_socket, 2u32)
+        .unwrap();
+    let late = link.client_socket.sent.clone();
+
+    // The ids do not wrap while a message is not confirmed yet
+    assert!(is_ids_exhausted(
+        link.client.send_confirmed(&mut link.client_socket, 3u32)
+    ));
+    for _ in 0..3 {
+        link.step(true);
+    }
+    assert_eq!(vec![1, 2], link.received);
+
+    // The first send after all messages are confirmed starts a new epoch, which the peer has to acknowledge
+    assert!(is_ids_exhausted(
+        link.client.send_confirmed(&mut link.client_socket, 3u32)
+    ));
+    assert!(matches!(
+        bincode::deserialize(&link.client_socket.sent[0].0).unwrap(),
+        Packet::<u32>::Wrap {
+            epoch: 1,
+            acknowledged: false
+        }
+    ));
+    link.step(true);
+    link.step(true);
+    assert_eq!(
+        1,
+        link.client
+            .send_confirmed(&mut link.client_socket, 3u32)
+            .unwrap()
+            .get()
+    );
+    for _ in 0..3 {
+        link.step(true);
+    }
+    assert_eq!(vec![1, 2, 3], link.received);
+    assert_eq!(0, link.client.unconfirmed_bytes());
+
+    // Messages of the previous epoch that arrive late are ignored, and do not request the ids in between
+    for (data, _) in late {
+        link.client_socket
+            .sent
+            .push((data, link.server.bound_addr()));
+    }
+    link.step(true);
+    assert_eq!(vec![1, 2, 3], link.received);
+    assert!(link.server.receive.missing_message_id_list.is_empty());
+    assert_eq!(NonZeroU64::new(1), link.server.receive.last_message_id);
+}
+
+struct AcksInPings;
+impl ConnectorParam for AcksInPings {
+    type TSend = u32;
+    type TReceive = u32;
+    const REQUEST_MISSING_PACKET_INTERVAL_S: f64 = 0.;
+    const EMIT_UNCONFIRMED_PACKET_INTERVAL_S: f64 = 5.;
+    const ACKS_IN_PINGS: bool = true;
+}
+
+#[test]
+fn test_acks_in_pings() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let mut link = Link::<AcksInPings>::new();
+    link.step(true);
+    let datagrams = Arc::new(AtomicUsize::new(0));
+    let counter = datagrams.clone();
+    link.server.set_send_interceptor(Some(Box::new(move |data| {
+        // Every datagram of the receiving side is a ping or pong, with or without a confirmation
+        let packet = bincode::deserialize::<Packet<u32>>(data).unwrap();
+        let packets = match packet {
+            Packet::Batch { packets } => packets
+                .iter()
+                .map(|packet| bincode::deserialize::<Packet<u32>>(packet).unwrap())
+                .collect(),
+            packet => vec![packet],
+        };
+        assert!(matches!(
+            packets[0],
+            Packet::Ping { .. } | Packet::Pong { .. }
+        ));
+        assert!(packets[1..]
+            .iter()
+            .all(|packet| matches!(packet, Packet::ConfirmUpTo { .. })));
+        counter.fetch_add(1, Ordering::SeqCst);
+        SendAction::Send
+    })));
+
+    for i in 0..3u32 {
+        link.client
+            .send_confirmed(&mut link.client_socket, i)
+            .unwrap();
+    }
+    link.deliver_to_server();
+    assert_eq!(vec![0, 1, 2], link.received);
+    assert!(link.server_socket.sent.is_empty());
+
+    // The next ping of the server confirms all messages at once
+    link.client.drain_events().for_each(drop);
+    for _ in 0..10 {
+        link.step(true);
+    }
+    assert_eq!(0, link.client.unconfirmed_bytes());
+    assert_eq!(
+        3,
+        link.client
+            .drain_events()
+            .filter(|event| matches!(event, Event::Acknowledged(_)))
+            .count()
+    );
+    assert_eq!(vec![0, 1, 2], link.received);
+    assert!(datagrams.load(Ordering::SeqCst) > 0);
+}
+
+#[test]
+fn test_set_timing() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<DefaultParam<u32, u32>>();
+    assert_eq!(Timing::default(), *connector.timing());
+    connector.set_timing(Timing {
+        ping_interval_s: 2.,
+        receive_ping_timeout_s: 5.,
+        ..Timing::default()
+    });
+    assert_eq!(2., connector.config_report().ping_interval_s);
+    connector.connect(&mut socket).unwrap();
+    socket.sent.clear();
+
+    connector.clock.advance(Duration::from_secs(1));
+    connector.update(&mut socket).unwrap();
+    assert!(socket.sent.is_empty());
+    assert_eq!(NetworkState::Connected, connector.state());
+
+    connector.clock.advance(Duration::from_millis(1100));
+    connector.update(&mut socket).unwrap();
+    assert!(matches!(
+        socket.take_packets::<u32>()[..],
+        [Packet::Hello { .. }, Packet::Ping { .. }]
+    ));
+    assert_eq!(NetworkState::Connected, connector.state());
+}
+
+#[test]
+fn test_unchecked_timing() {
+    let mut connector = manual_connector::<DefaultParam<u32, u32>>();
+    connector.send.last_rtt = Some(Duration::from_millis(10));
+    connector.set_timing(Timing {
+        receive_ping_timeout_s: f64::NAN,
+        emit_unconfirmed_packet_interval_s: f64::INFINITY,
+        request_missing_packet_interval_s: 1e300,
+        ack_delay_s: -1.,
+        ..Timing::default()
+    });
+    assert!(connector.time_until_disconnect() > Some(Duration::from_secs(u64::MAX / 2)));
+    connector.validate().expect("The intervals never pass");
+
+    connector.set_timing(Timing {
+        receive_ping_timeout_s: -1.,
+        request_missing_packet_interval_s: f64::NAN,
+        emit_unconfirmed_packet_interval_s: -1.,
+        ..Timing::default()
+    });
+    assert_eq!(None, connector.time_until_disconnect());
+    match connector.validate().map_err(|e| e.downcast::<Error>()) {
+        Err(Ok(Error::IntervalShorterThanRtt {
+            setting: "EMIT_UNCONFIRMED_PACKET_INTERVAL_S",
+            interval: Duration::ZERO,
+            ..
+        })) => {}
+        e => panic!("Unexpected result: {:?}", e),
+    }
+}
+
+#[test]
+fn test_per_connector_timing_overrides() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut desktop = manual_connector::<ImmediateRequests>();
+    let mut mobile = manual_connector::<ImmediateRequests>();
+    mobile.clock = desktop.clock;
+    mobile.set_ping_interval(Duration::from_secs(2));
+    mobile.set_receive_timeout(Duration::from_secs(10));
+    assert_eq!(10., mobile.timing().receive_ping_timeout_s);
+
+    for connector in [&mut desktop, &mut mobile] {
+        connector.connect(&mut socket).unwrap();
+    }
+    socket.sent.clear();
+    for connector in [&mut desktop, &mut mobile] {
+        connector.clock.advance(Duration::from_secs(1));
+        connector.update(&mut socket).unwrap();
+    }
+    // Only the desktop sends a ping after the default interval
+    assert_eq!(1, socket.sent.len());
+
+    for connector in [&mut desktop, &mut mobile] {
+        connector.clock.advance(Duration::from_secs(1));
+    }
+    assert_ne!(NetworkState::Connected, desktop.state());
+    assert_eq!(NetworkState::Connected, mobile.state());
+}
+
+struct ChecksummedBatches;
+impl ConnectorParam for ChecksummedBatches {
+    type TSend = u32;
+    type TReceive = u32;
+    const BUFFER_SENDS: bool = true;
+    const CHECKSUMMED_BATCHES: bool = true;
+}
+
+#[test]
+fn test_checksummed_batches() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut sender = manual_connector::<ChecksummedBatches>();
+    for message in 1..=3u32 {
+        sender.send_unconfirmed(&mut socket, message).unwrap();
+    }
+    sender.flush(&mut socket).unwrap();
+    let (datagram, _) = socket.sent.pop().unwrap();
+    assert!(socket.sent.is_empty());
+    // The batch header, and three packets of 10 bytes with a checksum and length each
+    assert_eq!(4 + 8 + 3 * (4 + 8 + 10), datagram.len());
+
+    // Corrupt the amount of packets, the length of the middle packet, or a byte of the middle packet
+    for (index, expected) in [(4, vec![1, 2, 3]), (38, vec![1, 3]), (52, vec![1, 3])] {
+        let mut corrupt = datagram.clone();
+        corrupt[index] ^= 0xff;
+        let mut receiver = manual_connector::<ChecksummedBatches>();
+        assert_eq!(
+            expected,
+            receiver
+                .handle_incoming_data(&mut socket, &corrupt)
+                .expect("Could not handle batch")
+        );
+        assert_eq!(
+            3 - expected.len() as u64,
+            receiver.stats().corrupt_batch_entries
+        );
+        assert_eq!(0, receiver.stats().malformed_batches);
+    }
+}
+
+#[test]
+fn test_checksummed_batch_limits() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let data = |data| {
+        let packet = Packet::<u32>::Data {
+            message_id: None,
+            compressed: false,
+            data,
+        };
+        packet::checked_entry(bincode::serialize(&packet).unwrap())
+    };
+    let batch = |garbage| {
+        let packets = vec![data(1), (0, vec![0xaa; garbage]), data(2)];
+        bincode::serialize(&Packet::<u32>::CheckedBatch { packets }).unwrap()
+    };
+
+    // The packet after the corrupt packet is only searched for a limited amount of positions
+    for (garbage, expected) in [(100, vec![1, 2]), (10_000, vec![1])] {
+        let mut receiver = manual_connector::<ChecksummedBatches>();
+        assert_eq!(
+            expected,
+            receiver
+                .handle_incoming_data(&mut socket, &batch(garbage))
+                .expect("Could not handle batch")
+        );
+        assert_eq!(1, receiver.stats().corrupt_batch_entries);
+    }
+
+    // Without checksummed batches enabled, the datagram is rejected
+    let mut receiver = manual_connector::<ImmediateRequests>();
+    assert!(receiver
+        .handle_incoming_data(&mut socket, &batch(100))
+        .is_err());
+    assert!(matches!(
+        receiver.take_events()[..],
+        [Event::MalformedPacket { .. }]
+    ));
+    assert_eq!(0, receiver.stats().received_packet_types.total());
+}
+
+#[test]
+fn test_display() {
+    assert_eq!("connected", NetworkState::Connected.to_string());
+    assert_eq!("connecting", NetworkState::Connecting.to_string());
+    assert_eq!("disconnected", NetworkState::Disconnected.to_string());
+    assert_eq!("failed", NetworkState::Failed.to_string());
+
+    let packets = vec![
+        (
+            "Ping(last=3)",
+            Packet::Ping {
+                last_send_message_id: NonZeroU64::new(3),
+                nonce: 7,
+            },
+        ),
+        (
+            "Pong(last=none)",
+            Packet::Pong {
+                last_send_message_id: None,
+                nonce: 7,
+            },
+        ),
+        (
+            "Data(id=5, 120 bytes)",
+            Packet::Data {
+                message_id: NonZeroU64::new(5),
+                compressed: false,
+                data: vec![0u8; 112],
+            },
+        ),
+        (
+            "Data(unconfirmed, 8 bytes)",
+            Packet::Data {
+                message_id: None,
+                compressed: false,
+                data: Vec::new(),
+            },
+        ),
+        (
+            "Disconnect(code=3)",
+            Packet::Disconnect {
+                reason: Some(DisconnectReason::with_message(3, "Kicked")),
+            },
+        ),
+        (
+            "Batch(2 packets)",
+            Packet::Batch {
+                packets: vec![vec![1], vec![2]],
+            },
+        ),
+    ];
+    for (expected, packet) in packets {
+        assert_eq!(expected, packet.to_string());
+    }
+}
+
+struct BufferBeforeConnected;
+impl ConnectorParam for BufferBeforeConnected {
+    type TSend = u32;
+    type TReceive = u32;
+    const SEND_BEFORE_CONNECTED: SendBeforeConnected = SendBeforeConnected::Buffer;
+}
+
+#[test]
+fn test_buffer_before_connected() {
+    let mut link = Link::<BufferBeforeConnected>::new();
+    let id = link
+        .client
+        .send_confirmed(&mut link.client_socket, 7u32)
+        .unwrap();
+    assert_eq!(Some(id), link.client.send.last_message_id());
+    // Only the ping of the handshake was sent
+    assert_eq!(1, link.client_socket.sent.len());
+
+    link.step(true);
+    assert!(link.received.is_empty());
+    link.step(true);
+    assert_eq!(vec![7], link.received);
+    link.step(true);
+    assert!(link.client.send.unconfirmed_message_cache.is_empty());
+}
+
+#[test]
+fn test_request_for_held_packet() {
+    let mut link = Link::<BufferBeforeConnected>::new();
+    let id = link
+        .client
+        .send_confirmed(&mut link.client_socket, 7u32)
+        .unwrap();
+    // A request does not send the message before the connection is established
+    let (_, replies) = inject_packet(&mut link.client, Packet::RequestPacket { id });
+    assert!(replies.is_empty());
+    assert!(link.client.send.unconfirmed_message_cache[&id].held);
+    assert_eq!(0, link.client.stats().retransmits);
+}
+
+struct RejectBeforeConnected;
+impl ConnectorParam for RejectBeforeConnected {
+    type TSend = u32;
+    type TReceive = u32;
+    const SEND_BEFORE_CONNECTED: SendBeforeConnected = SendBeforeConnected::Reject;
+}
+
+#[test]
+fn test_reject_before_connected() {
+    let mut link = Link::<RejectBeforeConnected>::new();
+    let error = link
+        .client
+        .send_confirmed(&mut link.client_socket, 7u32)
+        .expect_err("Sending before the handshake completed should fail");
+    match error.downcast_ref::<Error>() {
+        Some(Error::NotConnected {
+            state: NetworkState::Connecting,
+        }) => {}
+        e => panic!("Unexpected error: {:?}", e),
+    }
+    assert!(link.client.send.unconfirmed_message_cache.is_empty());
+
+    link.step(true);
+    link.client
+        .send_confirmed(&mut link.client_socket, 8u32)
+        .unwrap();
+    link.step(true);
+    assert_eq!(vec![8], link.received);
+}
+
+#[test]
+fn test_packet_type_counts() {
+    let mut link = Link::<ImmediateRequests>::new();
+    link.client
+        .send_confirmed(&mut link.client_socket, 7u32)
+        .unwrap();
+    // The session that precedes the handshake ping counts as another packet
+    assert_eq!(
+        PacketTypeCounts {
+            pings: 1,
+            data: 1,
+            other: 1,
+            ..PacketTypeCounts::default()
+        },
+        link.client.stats().sent_packet_types
+    );
+
+    link.deliver_to_server();
+    let received = link.server.stats().received_packet_types;
+    assert_eq!((1, 1, 3), (received.pings, received.data, received.total()));
+    let sent = link.server.stats().sent_packet_types;
+    assert_eq!((1, 1), (sent.pongs, sent.confirms));
+
+    // A request for a message that was never send is answered with a not found
+    inject_packet(
+        &mut link.server,
+        Packet::<u32>::RequestPacket {
+            id: NonZeroU64::new(9).unwrap(),
+        },
+    );
+    assert_eq!(1, link.server.stats().received_packet_types.requests);
+    assert_eq!(1, link.server.stats().sent_packet_types.not_founds);
+}
+
+/// Packets that do not make sense, with the anomaly each of them causes
+fn anomalous_packets() -> Vec<(Packet<u32>, Anomaly)> {
+    let id = |id| NonZeroU64::new(id).unwrap();
+    vec![
+        (
+            Packet::ConfirmPacket { id: id(4) },
+            Anomaly::UnknownConfirmation { id: id(4) },
+        ),
+        (
+            Packet::PacketNotFound { id: id(9) },
+            Anomaly::UnexpectedPacketNotFound { id: id(9) },
+        ),
+        (
+            Packet::Pong {
+                last_send_message_id: None,
+                nonce: 9,
+            },
+            Anomaly::UnmatchedPong { nonce: 9 },
+        ),
+        (
+            Packet::Ping {
+                last_send_message_id: Some(id(5)),
+                nonce: 0,
+            },
+            Anomaly::IdRegression {
+                id: id(5),
+                previous: id(6),
+            },
+        ),
+    ]
+}
+
+#[test]
+fn test_anomalies_are_counted() {
+    let mut connector = manual_connector::<ImmediateRequests>();
+    inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: NonZeroU64::new(6),
+            nonce: 0,
+        },
+    );
+    for (packet, _) in anomalous_packets() {
+        inject_packet(&mut connector, packet);
+    }
+    let stats = connector.stats();
+    assert_eq!(
+        (1, 1, 1, 1),
+        (
+            stats.invalid_confirmations,
+            stats.unexpected_not_founds,
+            stats.unmatched_pongs,
+            stats.id_regressions
+        )
+    );
+}
+
+struct Strict;
+impl ConnectorParam for Strict {
+    type TSend = u32;
+    type TReceive = u32;
+    const STRICT: bool = true;
+}
+
+#[test]
+fn test_strict() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<Strict>();
+    inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: NonZeroU64::new(6),
+            nonce: 0,
+        },
+    );
+    for (packet, anomaly) in anomalous_packets() {
+        let data = bincode::serialize(&packet).unwrap();
+        let error = connector
+            .handle_incoming_data(&mut socket, &data)
+            .expect_err("An anomaly should be an error");
+        match error.downcast_ref::<Error>() {
+            Some(Error::Anomaly { anomaly: a }) if *a == anomaly => {}
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+    assert_eq!(1, connector.stats().id_regressions);
+}
+
+#[test]
+fn test_strict_keeps_earlier_messages() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<Strict>();
+    let unknown = NonZeroU64::new(4).unwrap();
+    let batch = bincode::serialize(&Packet::<u32>::Batch {
+        packets: vec![
+            bincode::serialize(&Packet::Data {
+                message_id: NonZeroU64::new(1),
+                compressed: false,
+                data: 10u32,
+            })
+            .unwrap(),
+            bincode::serialize(&Packet::<u32>::ConfirmPacket { id: unknown }).unwrap(),
+            bincode::serialize(&Packet::Data {
+                message_id: NonZeroU64::new(2),
+                compressed: false,
+                data: 20u32,
+            })
+            .unwrap(),
+        ],
+    })
+    .unwrap();
+    connector.take_events();
+
+    // The message before the anomaly is returned, the packets after it are dropped
+    let received = connector
+        .handle_incoming_data(&mut socket, &batch)
+        .expect("The earlier message should be kept");
+    assert_eq!(vec![10], received);
+    assert_eq!(1, connector.stats().malformed_batches);
+    match connector.take_events().as_slice() {
+        [Event::MalformedPacket { error, .. }] => assert_eq!(
+            Error::Anomaly {
+                anomaly: Anomaly::UnknownConfirmation { id: unknown }
+            }
+            .to_string(),
+            *error
+        ),
+        events => panic!("Unexpected events: {:?}", events),
+    }
+}
+
+#[test]
+fn test_backoff_policy_delay() {
+    let policy = BackoffPolicy {
+        initial_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(500),
+        ..BackoffPolicy::default()
+    };
+    let delays = (0..5).map(|i| policy.delay(i)).collect::<Vec<_>>();
+    assert_eq!(
+        [100, 200, 400, 500, 500]
+            .map(Duration::from_millis)
+            .to_vec(),
+        delays
+    );
+    assert_eq!(Duration::from_millis(500), policy.delay(u32::MAX));
+    for attempt in 0..5 {
+        let delay = policy.jittered_delay(attempt);
+        assert!(delay >= policy.delay(attempt).mul_f64(0.8));
+        assert!(delay <= policy.delay(attempt).mul_f64(1.2));
+    }
+
+    // Extreme values do not panic
+    let policy = BackoffPolicy {
+        initial_delay: Duration::MAX,
+        max_delay: Duration::MAX,
+        jitter: f64::NAN,
+        ..BackoffPolicy::default()
+    };
+    assert_eq!(Duration::MAX, policy.delay(3));
+    assert_eq!(Duration::MAX, policy.jittered_delay(3));
+    let policy = BackoffPolicy {
+        jitter: 1.,
+        ..policy
+    };
+    policy.jittered_delay(3);
+}
+
+#[test]
+fn test_reconnect_loop_gives_up() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let start = connector.now();
+    let policy = BackoffPolicy {
+        initial_delay: Duration::from_millis(100),
+        jitter: 0.,
+        max_attempts: Some(3),
+        ..BackoffPolicy::default()
+    };
+    let error = connector
+        .reconnect_loop(&mut socket, policy)
+        .expect_err("The peer never answers");
+    match error.downcast_ref::<Error>() {
+        Some(Error::ReconnectFailed { attempts: 3 }) => {}
+        e => panic!("Unexpected error: {:?}", e),
+    }
+    assert_eq!(2, connector.reconnect_count());
+    assert_eq!(
+        Duration::from_millis(700),
+        connector.now().duration_since(start)
+    );
+}
+
+#[test]
+fn test_reconnect_loop() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let pong = Packet::<u32>::Pong {
+        last_send_message_id: None,
+        nonce: 0,
+    };
+    let message = Packet::Data {
+        message_id: None,
+        compressed: false,
+        data: 5u32,
+    };
+    for packet in [pong, message] {
+        let data = bincode::serialize(&packet).unwrap();
+        socket.incoming.push_back((data, connector.bound_addr()));
+    }
+    connector
+        .reconnect_loop(&mut socket, BackoffPolicy::default())
+        .unwrap();
+    assert_eq!(0, connector.reconnect_count());
+    assert_eq!(Some(5), connector.pop_message());
+}
+
+#[test]
+fn test_acknowledge_processed() {
+    let mut link = Link::<ImmediateRequests>::new();
+    link.step(true);
+    let id = link
+        .client
+        .send_confirmed(&mut link.client_socket, 7u32)
+        .unwrap();
+    link.client
+        .send_unconfirmed(&mut link.client_socket, 8u32)
+        .unwrap();
+
+    let mut received = Vec::new();
+    for (data, _) in std::mem::take(&mut link.client_socket.sent) {
+        received.extend(
+            link.server
+                .handle_incoming_data_with_ids(&mut link.server_socket, &data)
+                .unwrap(),
+        );
+    }
+    assert_eq!(vec![(Some(id), 7), (None, 8)], received);
+    link.server
+        .acknowledge_processed(&mut link.server_socket, id)
+        .unwrap();
+    link.step(true);
+
+    let events = link.client.drain_events().collect::<Vec<_>>();
+    let acknowledged = events.iter().position(|e| *e == Event::Acknowledged(id));
+    let processed = events.iter().position(|e| *e == Event::Processed(id));
+    assert!(acknowledged.is_some());
+    assert!(acknowledged < processed);
+}
+
+#[test]
+fn test_serialize_state() {
+    let mut link = Link::<ImmediateRequests>::new();
+    link.step(true);
+    let id = link
+        .client
+        .send_confirmed(&mut link.client_socket, 7u32)
+        .unwrap();
+    link.client_socket.sent.clear();
+    let until_disconnect = link.client.time_until_disconnect();
+
+    let state = link.client.serialize_state().unwrap();
+    // The restored connector runs on a clock that started at a different moment
+    let mut clock = link.client.clock;
+    clock.advance(Duration::from_secs(3600));
+    link.client = Connector::restore(&state, clock).unwrap();
+    assert_eq!(until_disconnect, link.client.time_until_disconnect());
+    assert_eq!(NetworkState::Connected, link.client.state());
+    let unconfirmed = link.client.unconfirmed().collect::<Vec<_>>();
+    assert_eq!(vec![(id, Duration::from_millis(0))], unconfirmed);
+
+    link.server.clock.advance(Duration::from_secs(3600));
+    for _ in 0..20 {
+        link.step(true);
+    }
+    assert_eq!(vec![7], link.received);
+    assert_eq!(0, link.client.reconnect_count());
+    assert_eq!(vec![id], link.client.drain_acknowledgements());
+    let next = link
+        .client
+        .send_confirmed(&mut link.client_socket, 8u32)
+        .unwrap();
+    assert_eq!(id.get() + 1, next.get());
+}
+
+#[test]
+fn test_serialize_state_history() {
+    let mut link = Link::<ImmediateRequests>::new();
+    link.step(true);
+    let id = link
+        .client
+        .send_confirmed(&mut link.client_socket, 7u32)
+        .unwrap();
+    link.step(true);
+    link.step(true);
+    assert!(link.client.send.unconfirmed_message_cache.is_empty());
+
+    let state = link.client.serialize_state().unwrap();
+    link.client = Connector::restore(&state, link.client.clock).unwrap();
+    // A request that crossed the confirmation is still answered with `AlreadyDelivered`
+    let (_, replies) = inject_packet(&mut link.client, Packet::RequestPacket { id });
+    assert_eq!(vec![Packet::AlreadyDelivered { id }], replies);
+
+    // The pong of a ping that was send before the state was serialized still counts
+    link.client.ping_now(&mut link.client_socket).unwrap();
+    let state = link.client.serialize_state().unwrap();
+    link.client = Connector::restore(&state, link.client.clock).unwrap();
+    link.step(true);
+    assert_eq!(0, link.client.stats().unmatched_pongs);
+    assert!(link.client.last_rtt().is_some());
+}
+
+struct ClockSync;
+impl ConnectorParam for ClockSync {
+    type TSend = u32;
+    type TReceive = u32;
+    const CLOCK_SYNC: bool = true;
+}
+
+#[test]
+fn test_estimated_clock_offset() {
+    let mut link = Link::<ClockSync>::new();
+    assert_eq!(None, link.client.estimated_clock_offset());
+    link.step(true);
+    // Both connectors run on the same machine
+    let offset = link.client.estimated_clock_offset().unwrap();
+    assert!(offset.abs() < 0.1, "{}", offset);
+
+    // The measurements are kept in the serialized state
+    let state = link.client.serialize_state().unwrap();
+    let restored = Connector::<ClockSync>::restore(&state, link.client.clock).unwrap();
+    assert_eq!(Some(offset), restored.estimated_clock_offset());
+
+    // The measurement with the shortest round trip time wins
+    let samples = &mut link.client.send.clock_samples;
+    samples.clear();
+    samples.push_back((Duration::from_millis(80), 2.5));
+    samples.push_back((Duration::from_millis(20), 1.));
+    samples.push_back((Duration::from_millis(50), -3.));
+    assert_eq!(Some(1.), link.client.estimated_clock_offset());
+
+    // A pong without the time of the peer is no measurement
+    let mut link = Link::<ImmediateRequests>::new();
+    link.step(true);
+    assert!(link.client.last_rtt().is_some());
+    assert_eq!(None, link.client.estimated_clock_offset());
+}
+
+struct NegotiatedAcks;
+impl ConnectorParam for NegotiatedAcks {
+    type TSend = u32;
+    type TReceive = u32;
+    const NEGOTIATE_CUMULATIVE_ACKS: bool = true;
+}
+
+#[test]
+fn test_negotiated_cumulative_acks() {
+    let id = |id| NonZeroU64::new(id).unwrap();
+    let run = || Packet::Batch {
+        packets: (1..=3)
+            .map(|message_id| {
+                bincode::serialize(&Packet::Data {
+                    message_id: Some(id(message_id)),
+                    compressed: false,
+                    data: 0u32,
+                })
+                .unwrap()
+            })
+            .collect(),
+    };
+
+    // A peer that never announced its capabilities gets a confirmation for every message
+    let mut receiver = manual_connector::<NegotiatedAcks>();
+    let (messages, replies) = inject_packet(&mut receiver, run());
+    assert_eq!(vec![0, 0, 0], messages);
+    assert_eq!(
+        vec![
+            Packet::ConfirmPacket { id: id(1) },
+            Packet::ConfirmPacket { id: id(2) },
+            Packet::ConfirmPacket { id: id(3) }
+        ],
+        replies
+    );
+
+    // The announcement is answered, and the run is confirmed at once
+    let mut receiver = manual_connector::<NegotiatedAcks>();
+    let announcement = Packet::Capabilities {
+        flags: 1,
+        acknowledged: false,
+    };
+    let (_, replies) = inject_packet(&mut receiver, announcement);
+    assert_eq!(
+        vec![Packet::Capabilities {
+            flags: 1,
+            acknowledged: true
+        }],
+        replies
+    );
+    let (messages, replies) = inject_packet(&mut receiver, run());
+    assert_eq!(vec![0, 0, 0], messages);
+    assert_eq!(vec![Packet::ConfirmUpTo { id: id(3) }], replies);
+
+    // Both sides announce their capabilities with their pings, until the peer answered
+    let mut link = Link::<NegotiatedAcks>::new();
+    link.step(true);
+    link.step(true);
+    for connector in [&link.client, &link.server] {
+        assert_eq!(Some(1), connector.receive.peer_capabilities);
+        assert!(connector.receive.capabilities_acknowledged);
+    }
+    link.client.send.last_ping = link.client.clock.now() - Duration::from_secs(60);
+    link.client.update(&mut link.client_socket).unwrap();
+    let sent = link.client_socket.take_sent::<u32>();
+    assert!(sent
+        .iter()
+        .any(|packet| matches!(packet, Packet::Ping { .. })));
+    assert!(!sent
+        .iter()
+        .any(|packet| matches!(packet, Packet::Capabilities { .. })));
+}
+
+#[test]
+fn test_recording_socket() {
+    let mut socket = RecordingSocket::<u32>::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    connector.connect(&mut socket).unwrap();
+    inject_packet(
+        &mut connector,
+        Packet::Pong {
+            last_send_message_id: None,
+            nonce: 0,
+        },
+    );
+    connector.send_confirmed(&mut socket, 7u32).unwrap();
+    connector.clock.advance(Duration::from_secs(1));
+    connector.update(&mut socket).unwrap();
+    // The handshake ping is preceded by our session, see `Packet::Hello`
+    assert!(matches!(
+        socket.sent(),
+        [
+            Packet::Batch { .. },
+            Packet::Data { data: 7, .. },
+            Packet::Ping { nonce: 1, .. },
+        ]
+    ));
+}
+
+struct MaxIdGap;
+impl ConnectorParam for MaxIdGap {
+    type TSend = u32;
+    type TReceive = u32;
+    const MAX_ID_GAP: Option<u64> = Some(1000);
+}
+
+#[test]
+fn test_max_id_gap() {
+    let mut connector = manual_connector::<MaxIdGap>();
+    let (messages, replies) = inject_packet(
+        &mut connector,
+        Packet::Data {
+            message_id: NonZeroU64::new(u64::MAX),
+            compressed: false,
+            data: 1,
+        },
+    );
+    assert!(messages.is_empty());
+    assert!(replies.is_empty());
+    let (_, replies) = inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: NonZeroU64::new(u64::MAX),
+            nonce: 0,
+        },
+    );
+    assert!(matches!(replies[..], [Packet::Pong { .. }]));
+    assert!(connector.receive.missing_message_id_list.is_empty());
+    assert_eq!(None, connector.receive.last_message_id);
+    assert_eq!(2, connector.stats().ids_too_far_ahead);
+
+    // A gap up to the limit is tracked as usual
+    let (messages, _) = inject_packet(
+        &mut connector,
+        Packet::Data {
+            message_id: NonZeroU64::new(1000),
+            compressed: false,
+            data: 2,
+        },
+    );
+    assert_eq!(vec![2], messages);
+    assert_eq!(999, connector.receive.missing_message_id_list.len());
+    assert_eq!(2, connector.stats().ids_too_far_ahead);
+}
+
+#[test]
+fn test_max_id_gap_with_stepping_pings() {
+    let mut connector = manual_connector::<MaxIdGap>();
+    let id = |id| NonZeroU64::new(id).unwrap();
+
+    // Every ping is within the limit of the previous one, but not of the messages that arrived
+    for step in 1..=10 {
+        inject_packet(
+            &mut connector,
+            Packet::Ping {
+                last_send_message_id: Some(id(step * 1000)),
+                nonce: step as u32,
+            },
+        );
+    }
+    assert_eq!(1000, connector.receive.missing_message_id_list.len());
+    assert_eq!(9, connector.stats().ids_too_far_ahead);
+
+    // Messages move the limit forward
+    let (messages, _) = inject_packet(
+        &mut connector,
+        Packet::Data {
+            message_id: Some(id(1000)),
+            compressed: false,
+            data: 1,
+        },
+    );
+    assert_eq!(vec![1], messages);
+    inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: Some(id(2000)),
+            nonce: 11,
+        },
+    );
+    assert_eq!(1999, connector.receive.missing_message_id_list.len());
+    assert_eq!(9, connector.stats().ids_too_far_ahead);
+}
+
+#[test]
+fn test_missing_ids_stay_sorted() {
+    let mut connector = manual_connector::<ImmediateRequests>();
+    let id = |id| NonZeroU64::new(id).unwrap();
+    let missing = |connector: &Connector<ImmediateRequests>| {
+        connector
+            .receive
+            .missing_message_id_list
+            .iter()
+            .map(|missing| missing.id.get())
+            .collect::<Vec<_>>()
+    };
+
+    // The gap is bounded by default
+    inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: NonZeroU64::new(u64::MAX),
+            nonce: 0,
+        },
+    );
+    assert!(missing(&connector).is_empty());
+    assert_eq!(1, connector.stats().ids_too_far_ahead);
+
+    for message_id in [5, 3] {
+        inject_packet(
+            &mut connector,
+            Packet::Data {
+                message_id: Some(id(message_id)),
+                compressed: false,
+                data: 0,
+            },
+        );
+    }
+    inject_packet(
+        &mut connector,
+        Packet::Ping {
+            last_send_message_id: Some(id(8)),
+            nonce: 1,
+        },
+    );
+    assert_eq!(vec![1, 2, 4, 6, 7, 8], missing(&connector));
+    assert!(connector.was_received(id(3)));
+    assert!(!connector.was_received(id(4)));
+    assert_eq!(None, connector.confirmed_up_to());
+
+    for message_id in [2, 1] {
+        inject_packet(
+            &mut connector,
+            Packet::Data {
+                message_id: Some(id(message_id)),
+                compressed: false,
+                data: 0,
+            },
+        );
+    }
+    assert_eq!(vec![4, 6, 7, 8], missing(&connector));
+    assert_eq!(Some(id(3)), connector.confirmed_up_to());
+}
+
+#[test]
+fn test_shutdown() {
+    let data_count = |packets: &[Packet<u32>]| {
+        packets
+            .iter()
+            .filter(|packet| matches!(packet, Packet::Data { .. }))
+            .count()
+    };
+
+    // The peer does not answer, so the messages are resend during the whole grace period
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<ImmediateRequests>();
+    connector.connect(&mut socket).unwrap();
+    connector.send_confirmed(&mut socket, 1u32).unwrap();
+    connector.send_confirmed(&mut socket, 2u32).unwrap();
+    socket.sent.clear();
+    let start = connector.clock.now();
+    connector
+        .shutdown(&mut socket, Duration::from_millis(300))
+        .unwrap();
+    assert_eq!(Duration::from_millis(300), connector.clock.now() - start);
+    let sent = socket.take_sent::<u32>();
+    assert_eq!(6, data_count(&sent));
+    assert!(matches!(sent.last(), Some(Packet::Disconnect { .. })));
+    assert_eq!(NetworkState::Disconnected, connector.state());
+
+    // Once everything is confirmed, the connector disconnects right away
+    let mut connector = manual_connector::<ImmediateRequests>();
+    connector.connect(&mut socket).unwrap();
+    connector.send_confirmed(&mut socket, 1u32).unwrap();
+    socket.sent.clear();
+    let confirm = bincode::serialize(&Packet::<u32>::ConfirmPacket {
+        id: NonZeroU64::new(1).unwrap(),
+    })
+    .unwrap();
+    socket.incoming.push_back((confirm, connector.peer_addr));
+    let start = connector.clock.now();
+    connector
+        .shutdown(&mut socket, Duration::from_millis(300))
+        .unwrap();
+    assert_eq!(start, connector.clock.now());
+    let sent = socket.take_sent::<u32>();
+    assert_eq!(0, data_count(&sent));
+    assert!(matches!(sent.last(), Some(Packet::Disconnect { .. })));
+}
+
+struct SequenceAcks;
+impl ConnectorParam for SequenceAcks {
+    type TSend = u32;
+    type TReceive = u32;
+    const SEQUENCE_ACKS: bool = true;
+}
+
+#[test]
+fn test_sequence_acks() {
+    let mut link = Link::<SequenceAcks>::new();
+    link.step(true);
+    for frame in 0..4u32 {
+        link.client
+            .send_unconfirmed_ordered(&mut link.client_socket, 3, frame)
+            .unwrap();
+    }
+    // The frames with sequence 1 and 2 are lost
+    link.client_socket.sent.retain(|(data, _)| {
+        !matches!(
+            bincode::deserialize::<Packet<u32>>(data),
+            Ok(Packet::OrderedData {
+                sequence: 1..=2,
+                ..
+            })
+        )
+    });
+    link.deliver_to_server();
+    assert_eq!(vec![0, 3], link.received);
+
+    for _ in 0..10 {
+        link.step(true);
+    }
+    let feedback = link.client.stats().stream_feedback[&3];
+    assert_eq!(
+        StreamFeedback {
+            highest_sequence: 3,
+            received: 2,
+            window_sent: 4,
+            window_received: 2,
+        },
+        feedback
+    );
+    assert_eq!(0.5, feedback.loss_rate());
+
+    // Only the messages since the last report count
+    let mut feedback = feedback;
+    feedback.report(7, 6);
+    assert_eq!(0., feedback.loss_rate());
+    // A report that arrived out of order changes nothing
+    feedback.report(5, 4);
+    assert_eq!(7, feedback.highest_sequence);
+
+    // The highest possible sequence does not overflow
+    let mut feedback = StreamFeedback::default();
+    feedback.report(u64::MAX, 1);
+    assert_eq!(u64::MAX, feedback.window_sent);
+}
+
+#[test]
+fn test_sequence_acks_are_bounded() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<SequenceAcks>();
+    for stream in 0..20 {
+        inject_packet(
+            &mut connector,
+            Packet::OrderedData {
+                stream,
+                sequence: 0,
+                data: 0,
+            },
+        );
+    }
+    let mut reported_streams = |connector: &mut Connector<SequenceAcks>, nonce| {
+        let ping = bincode::serialize(&Packet::<u32>::Ping {
+            last_send_message_id: None,
+            nonce,
+        })
+        .unwrap();
+        connector.handle_incoming_data(&mut socket, &ping).unwrap();
+        socket
+            .take_packets::<u32>()
+            .into_iter()
+            .filter_map(|packet| match packet {
+                Packet::SequenceAck { stream, .. } => Some(stream),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    // Every pong reports the streams after the ones of the previous pong
+    assert_eq!(
+        (0..16).collect::<Vec<_>>(),
+        reported_streams(&mut connector, 0)
+    );
+    assert_eq!(
+        (16..20).chain(0..12).collect::<Vec<_>>(),
+        reported_streams(&mut connector, 1)
+    );
+
+    // Reports of streams that we never send on are ignored
+    let (_, replies) = inject_packet(
+        &mut connector,
+        Packet::SequenceAck {
+            stream: 7,
+            sequence: 0,
+            received: 1,
+        },
+    );
+    assert!(replies.is_empty());
+    connector
+        .send_unconfirmed_ordered(&mut socket, 8, 0u32)
+        .unwrap();
+    for stream in [7, 8] {
+        inject_packet(
+            &mut connector,
+            Packet::SequenceAck {
+                stream,
+                sequence: 0,
+                received: 1,
+            },
+        );
+    }
+    // A sequence that was not send yet is ignored as well
+    inject_packet(
+        &mut connector,
+        Packet::SequenceAck {
+            stream: 8,
+            sequence: 5,
+            received: 2,
+        },
+    );
+    let feedback = &connector.stats().stream_feedback;
+    assert_eq!(vec![&8], feedback.keys().collect::<Vec<_>>());
+    assert_eq!(0, feedback[&8].highest_sequence);
+}
+
+struct Text;
+impl ConnectorParam for Text {
+    type TSend = String;
+    type TReceive = String;
+}
+
+struct Units;
+impl ConnectorParam for Units {
+    type TSend = ();
+    type TReceive = ();
+}
+
+#[test]
+fn test_receive_compressed_unit() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut connector = manual_connector::<Units>();
+    // `()` is zero-sized, but can be received
+    let data = bincode::serialize(&Packet::Data {
+        message_id: NonZeroU64::new(1),
+        compressed: true,
+        data: packet::compress(&[]),
+    })
+    .unwrap();
+    assert_eq!(
+        vec![()],
+        connector.handle_incoming_data(&mut socket, &data).unwrap()
+    );
+}
+
+#[test]
+fn test_send_confirmed_compressed() {
+    let mut sender_socket = MemorySocket::new("127.0.0.1:1");
+    let mut receiver_socket = MemorySocket::new("127.0.0.1:2");
+    let mut sender = manual_connector::<Text>();
+    let mut receiver = Connector::<Text>::bound_to(sender_socket.local_addr);
+    receiver.clock = sender.clock;
+    sender.connect(&mut sender_socket).unwrap();
+    sender_socket.sent.clear();
+
+    let message = "compressible ".repeat(1000);
+    let id = sender
+        .send_confirmed_compressed(&mut sender_socket, message.clone())
+        .unwrap();
+    let (data, _) = sender_socket.sent.pop().unwrap();
+    assert!(data.len() < message.len() / 10, "{} bytes", data.len());
+    // The message is send as the compressed bytes of the serialized message
+    assert!(matches!(
+        bincode::deserialize::<Packet<Vec<u8>>>(&data),
+        Ok(Packet::Data {
+            compressed: true,
+            ..
+        })
+    ));
+
+    // The message can not borrow from the datagram
+    let error = receiver
+        .handle_incoming_data_borrowed::<&str>(&mut receiver_socket, &data)
+        .expect_err("A compressed message can not be borrowed");
+    match error.downcast_ref::<Error>() {
+        Some(Error::CompressedMessageBorrowed) => {}
+        e => panic!("Unexpected error: {:?}", e),
+    }
+
+    assert_eq!(
+        vec![message],
+        receiver
+            .handle_incoming_data(&mut receiver_socket, &data)
+            .unwrap()
+    );
+    for (data, _) in std::mem::take(&mut receiver_socket.sent) {
+        sender
+            .handle_incoming_data(&mut sender_socket, &data)
+            .unwrap();
+    }
+    assert_eq!(vec![Event::Acknowledged(id)], sender.take_events());
+
+    // A small message that is not compressed arrives as usual
+    sender
+        .send_confirmed(&mut sender_socket, String::from("small"))
+        .unwrap();
+    let (data, _) = sender_socket.sent.pop().unwrap();
+    assert_eq!(
+        vec![String::from("small")],
+        receiver
+            .handle_incoming_data(&mut receiver_socket, &data)
+            .unwrap()
+    );
+}
+
+struct CompactText;
+impl ConnectorParam for CompactText {
+    type TSend = String;
+    type TReceive = String;
+    const COMPACT_MESSAGE_IDS: bool = true;
+}
+
+#[test]
+fn test_send_confirmed_compressed_compact() {
+    let mut socket = MemorySocket::new("127.0.0.1:1");
+    let mut sender = manual_connector::<CompactText>();
+    let mut receiver = manual_connector::<CompactText>();
+    let message = "compressible ".repeat(100);
+    sender
+        .send_confirmed_compressed(&mut socket, message.clone())
+        .unwrap();
+    let (data, _) = socket.sent.pop().unwrap();
+    assert_eq!(
+        vec![(NonZeroU64::new(1), message)],
+        receiver
+            .handle_incoming_data_with_ids(&mut socket, &data)
+            .unwrap()
+    );
+}
@@ -1,3 +1,4 @@
+use crate::clock::Clock;
 use crate::*;
 use std::io::ErrorKind;
 use std::io::{Read, Write};
@@ -13,10 +14,9 @@ impl Socket for TcpStream {
     fn local_addr(&self) -> SocketAddr {
         TcpStream::local_addr(self).unwrap()
     }
-    fn send_to(&mut self, buffer: &[u8], target: SocketAddr) -> Result<()> {
+    fn send_to(&mut self, buffer: &[u8], target: SocketAddr) -> std::io::Result<()> {
         assert_eq!(target, self.peer_addr().unwrap());
-        TcpStream::write(self, buffer)?;
-        Ok(())
+        self.write_all(buffer)
     }
 }
 
@@ -40,6 +40,8 @@ pub struct Client;
 impl ConnectorParam for Client {
     type TSend = ClientToServer;
     type TReceive = ServerToClient;
+
+    const RECEIVES_MESSAGES: bool = false;
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -81,14 +83,18 @@ impl Default for Proxy {
         let server_socket_pair = get_tcp_stream_pair(&listener);
         let client_socket_pair = get_tcp_stream_pair(&listener);
 
-        let server = ServerConnector {
+        // Both sides share a manual clock, so timing only changes with `Proxy::advance`
+        let clock = Clock::manual();
+        let mut server = ServerConnector {
             connector: Connector::bound_to(server_socket_pair.1.local_addr().unwrap()),
             socket: server_socket_pair.0,
         };
-        let client = ClientConnector {
+        server.connector.clock = clock;
+        let mut client = ClientConnector {
             connector: Connector::bound_to(client_socket_pair.1.local_addr().unwrap()),
             socket: client_socket_pair.0,
         };
+        client.connector.clock = clock;
 
         for (name, socket) in &[
             ("Client socket", &client.socket),
@@ -106,52 +112,84 @@ impl Default for Proxy {
             client_socket: client_socket_pair.1,
         };
 
+        proxy.complete_handshake();
         proxy
-            .client
+    }
+}
+
+impl Proxy {
+    /// Advance the clocks of the client and server, and update their state, without waiting for the time to pass
+    pub fn advance(&mut self, duration: Duration) {
+        self.client.connector.clock.advance(duration);
+        self.server.connector.clock.advance(duration);
+        self.client.connector.refresh_state();
+        self.server.connector.refresh_state();
+    }
+
+    /// Connect the client to the server, relaying the ping and pong between them, and assert that the client is connected afterwards
+    pub fn complete_handshake(&mut self) {
+        self.client
             .connector
-            .connect(&mut proxy.client.socket)
+            .connect(&mut self.client.socket)
             .expect("Could not connect to server");
+        assert_eq!(NetworkState::Connected, self.client.connector.state());
 
-        assert_eq!(NetworkState::Connected, proxy.client.connector.state());
-
-        let message = proxy.handle_one_message_from_client();
-        assert_eq!(
-            Packet::Ping {
-                last_send_message_id: None
-            },
-            message
-        );
+        // The ping is preceded by the session of the client, see `Packet::Hello`
+        let packets = match self.handle_one_message_from_client() {
+            Packet::Batch { packets } => packets
+                .iter()
+                .map(|bytes| bincode::deserialize(bytes).expect("Could not deserialize packet"))
+                .collect::<Vec<Packet<ClientToServer>>>(),
+            message => panic!("Expected a batch, got {:?}", message),
+        };
+        assert!(matches!(
+            packets[..],
+            [
+                Packet::Hello { .. },
+                Packet::Ping {
+                    last_send_message_id: None,
+                    ..
+                }
+            ]
+        ));
 
-        println!("server receiving from");
-        proxy
+        // The server needs to be polled to answer the ping
+        let result = self
             .server
             .connector
-            .receive_from(&mut proxy.server.socket)
+            .receive_from(&mut self.server.socket)
             .expect("Could not update server");
-        println!("Handling one server message");
-        let message = proxy.handle_one_message_from_server();
-        assert_eq!(
+        assert!(result.is_empty());
+        let message = self.handle_one_message_from_server();
+        assert!(matches!(
+            message,
             Packet::Pong {
-                last_send_message_id: None
-            },
-            message
-        );
+                last_send_message_id: None,
+                ..
+            }
+        ));
 
-        proxy
-            .client
+        self.client
             .connector
-            .update_and_receive(&mut proxy.client.socket)
+            .update_and_receive(&mut self.client.socket)
             .expect("Could not update client");
 
-        assert!(proxy.client_has_no_pending_messages());
-        assert!(proxy.server_has_no_pending_messages());
-        assert_eq!(NetworkState::Connected, proxy.client.connector.state());
+        assert!(self.client_has_no_pending_messages());
+        assert!(self.server_has_no_pending_messages());
+        assert_eq!(NetworkState::Connected, self.client.connector.state());
+    }
 
-        proxy
+    /// Read the next packet the client sent, without relaying it to the server, as if it was lost
+    pub fn drop_one_message_from_client(&mut self) -> Packet<ClientToServer> {
+        thread::sleep(Duration::from_millis(100));
+        let mut data = [0u8; 1024];
+        let (count, _addr) = self
+            .client_socket
+            .recv_from(&mut data)
+            .expect("Could not receive data from client");
+        bincode::deserialize(&data[..count]).expect("Could not deserialize packet")
     }
-}
 
-impl Proxy {
     pub fn handle_one_message_from_client(&mut self) -> Packet<ClientToServer> {
         thread::sleep(Duration::from_millis(100));
         println!(
@@ -0,0 +1,39 @@
+use crate::packet::Packet;
+use crate::*;
+use std::io::ErrorKind;
+
+/// A socket that decodes every datagram that is send through it, so a test can assert the exact sequence of packets. Nothing is ever received.
+///
+/// Datagrams are decoded as a single `Packet`, so this only works for connectors without `ConnectorParam::PADDING` and `ConnectorParam::COMPACT_MESSAGE_IDS`. Coalesced packets are recorded as the `Packet::Batch` they were send in.
+pub struct RecordingSocket<T> {
+    pub local_addr: SocketAddr,
+    sent: Vec<Packet<T>>,
+}
+
+impl<T: for<'a> serde::Deserialize<'a>> RecordingSocket<T> {
+    pub fn new(local_addr: &str) -> RecordingSocket<T> {
+        RecordingSocket {
+            local_addr: local_addr.parse().unwrap(),
+            sent: Vec::new(),
+        }
+    }
+
+    /// The packets that were sent by this socket, in order
+    pub fn sent(&self) -> &[Packet<T>] {
+        &self.sent
+    }
+}
+
+impl<T: for<'a> serde::Deserialize<'a>> Socket for RecordingSocket<T> {
+    fn recv_from(&mut self, _buffer: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        Err(ErrorKind::WouldBlock.into())
+    }
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+    fn send_to(&mut self, buffer: &[u8], _target: SocketAddr) -> std::io::Result<()> {
+        let packet = bincode::deserialize(buffer).expect("Could not deserialize packet");
+        self.sent.push(packet);
+        Ok(())
+    }
+}
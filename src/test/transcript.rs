@@ -0,0 +1,26 @@
+use crate::*;
+
+/// A packet in the transcript of a connector, see `Connector::transcript`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Recorded<TSend, TReceive> {
+    Sent(Packet<TSend>),
+    Received(Packet<TReceive>),
+}
+
+impl<TParam: ConnectorParam> Connector<TParam> {
+    /// Decode all packets that were send and received since `start_capture` was called, in order
+    pub fn transcript(&self) -> Vec<Recorded<TParam::TSend, TParam::TReceive>> {
+        self.capture
+            .iter()
+            .flat_map(|(_, transcript)| &transcript.entries)
+            .map(|entry| match entry.direction {
+                Direction::Sent => Recorded::Sent(deserialize(&entry.data)),
+                Direction::Received => Recorded::Received(deserialize(&entry.data)),
+            })
+            .collect()
+    }
+}
+
+fn deserialize<T: for<'a> serde::Deserialize<'a>>(bytes: &[u8]) -> Packet<T> {
+    bincode::deserialize(bytes).expect("Could not deserialize recorded packet")
+}
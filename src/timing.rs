@@ -0,0 +1,66 @@
+use crate::ConnectorParam;
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+
+/// The intervals and timeouts of a connector. A connector starts with the values of its `ConnectorParam`, and they can be changed at runtime with `Connector::set_timing`.
+///
+/// This can be deserialized, e.g. from a configuration file. Every field has the name of the matching constant in `ConnectorParam`, see there for more info. Fields that are missing get the default value of that constant. The values are not checked: a negative value passes right away, and NaN or infinity never passes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+#[allow(missing_docs)]
+pub struct Timing {
+    pub ping_interval_s: f64,
+    pub request_missing_packet_interval_s: f64,
+    pub emit_unconfirmed_packet_interval_s: f64,
+    pub receive_ping_timeout_s: f64,
+    pub send_ping_timeout_s: f64,
+    pub connect_timeout_s: Option<f64>,
+    pub ack_delay_s: f64,
+}
+
+impl Timing {
+    /// The timing as configured in the given `ConnectorParam`
+    pub fn of<TParam: ConnectorParam>() -> Timing {
+        Timing {
+            ping_interval_s: TParam::PING_INTERVAL_S,
+            request_missing_packet_interval_s: TParam::REQUEST_MISSING_PACKET_INTERVAL_S,
+            emit_unconfirmed_packet_interval_s: TParam::EMIT_UNCONFIRMED_PACKET_INTERVAL_S,
+            receive_ping_timeout_s: TParam::RECEIVE_PING_TIMEOUT_S,
+            send_ping_timeout_s: TParam::SEND_PING_TIMEOUT_S,
+            connect_timeout_s: TParam::CONNECT_TIMEOUT_S,
+            ack_delay_s: TParam::ACK_DELAY_S,
+        }
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Timing {
+        Timing::of::<DefaultParam<(), ()>>()
+    }
+}
+
+/// A `ConnectorParam` with the default settings for the given message types. It holds no values itself: applications that load their intervals and timeouts at runtime can use this with `Connector::set_timing`, which keeps the values in the connector, instead of defining their own type.
+///
+/// ```rust
+/// # use udp_connector::{Connector, DefaultParam, Timing};
+/// let timing = Timing {
+///     ping_interval_s: 1.,
+///     receive_ping_timeout_s: 5.,
+///     ..Timing::default()
+/// };
+/// let peer = "127.0.0.1:8080".parse().unwrap();
+/// let mut connector = Connector::<DefaultParam<String, String>>::bound_to(peer);
+/// connector.set_timing(timing);
+/// ```
+pub struct DefaultParam<TSend, TReceive> {
+    _marker: PhantomData<fn() -> (TSend, TReceive)>,
+}
+
+impl<TSend, TReceive> ConnectorParam for DefaultParam<TSend, TReceive>
+where
+    TSend: for<'a> Deserialize<'a> + Serialize,
+    TReceive: for<'a> Deserialize<'a> + Serialize,
+{
+    type TSend = TSend;
+    type TReceive = TReceive;
+}
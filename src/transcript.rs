@@ -0,0 +1,70 @@
+use crate::{Connector, ConnectorParam, Result, Socket};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// The direction a datagram travelled in, as seen from the connector that recorded it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The datagram was send to the peer
+    Sent,
+
+    /// The datagram was received from the peer
+    Received,
+}
+
+/// A single datagram in a `Transcript`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    /// Whether the datagram was send or received
+    pub direction: Direction,
+
+    /// The time between the start of the capture and the moment the datagram was send or received
+    pub elapsed: Duration,
+
+    /// The datagram, exactly as it was send or received
+    pub data: Vec<u8>,
+}
+
+/// A timestamped log of all datagrams that were send and received by a connector. This is recorded with `Connector::start_capture`.
+///
+/// A transcript can be written to a file, e.g. by a user that runs into a bug, and replayed into a fresh connector to reproduce the bug.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    /// The datagrams in the order they were send and received
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    /// Write this transcript to the given writer, e.g. a file
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+        bincode::serialize_into(writer, self)?;
+        Ok(())
+    }
+
+    /// Read a transcript that was written with `write_to`
+    pub fn read_from<R: Read>(reader: R) -> Result<Transcript> {
+        Ok(bincode::deserialize_from(reader)?)
+    }
+
+    /// Feed all received datagrams in this transcript into the given connector, as if they were received at the same time relative to each other as when they were recorded. Returns all messages that were received.
+    ///
+    /// The connector will send replies to the given socket. This should be a socket that does not actually send anything, so the replay does not reach a real peer.
+    pub fn replay<TParam: ConnectorParam>(
+        &self,
+        connector: &mut Connector<TParam>,
+        socket: &mut dyn Socket,
+    ) -> Result<Vec<TParam::TReceive>> {
+        let start = Instant::now();
+        let mut result = Vec::new();
+        for entry in &self.entries {
+            if entry.direction == Direction::Received {
+                result.extend(connector.handle_incoming_data_at(
+                    socket,
+                    &entry.data,
+                    start + entry.elapsed,
+                )?);
+            }
+        }
+        Ok(result)
+    }
+}